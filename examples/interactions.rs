@@ -0,0 +1,62 @@
+// compares entity interactions through the spatial hash with the old way of
+// checking every entity against a clone of all of them, run with
+// cargo run --release --no-default-features --example interactions
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::time::Instant;
+use U::config::WorldConfig;
+use U::worldgen::{Chunk, Coords, Entity, Faction, Status};
+
+const ENTITIES: usize = 10_000;
+// entities only fight those of other factions, so they are spread over a few
+const FACTIONS: [Faction; 3] = [Faction::Hiisi, Faction::Virumaa, Faction::Pohjola];
+
+fn main() {
+    let config = WorldConfig::new();
+    let side = config.chunk_size as f32;
+    let vicinity = config.vicinity_dist as f32;
+    let mut rng = StdRng::seed_from_u64(0);
+    let entities: Vec<Entity> = (0..ENTITIES)
+        .map(|i| {
+            let mut entity = Entity::new(i);
+            entity.coords = Coords::from((rng.gen_range(0.0..side), rng.gen_range(0.0..side)));
+            entity.alignment.faction = FACTIONS[rng.gen_range(0..FACTIONS.len())].clone();
+            if rng.gen_range(0..10) == 0 {
+                entity.status = Status::Fighting;
+            }
+            entity
+        })
+        .collect();
+
+    let mut chunk = Chunk::from(vec![], entities.clone(), Coords::new(), 0);
+    let start = Instant::now();
+    chunk.interact(&config);
+    let grid = start.elapsed();
+
+    let mut chunk = Chunk::from(vec![], entities, Coords::new(), 0);
+    let start = Instant::now();
+    let clone = chunk.entities.clone();
+    for entity in &mut chunk.entities {
+        for other in &clone {
+            let (dx, dy) = (
+                other.coords.x - entity.coords.x,
+                other.coords.y - entity.coords.y,
+            );
+            if other.index == entity.index || dx * dx + dy * dy > vicinity * vicinity {
+                continue;
+            }
+            if let Some(encounter) = entity.encounter(other, &mut chunk.rng) {
+                entity.suffer(&encounter);
+            }
+        }
+    }
+    let pairs = start.elapsed();
+
+    println!("{} entities in one chunk", ENTITIES);
+    println!("spatial hash  {:?}", grid);
+    println!("every pair    {:?}", pairs);
+    println!(
+        "speedup       {:.1}x",
+        pairs.as_secs_f64() / grid.as_secs_f64()
+    );
+}
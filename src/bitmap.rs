@@ -1,22 +1,56 @@
 use lazy_static::lazy_static;
 use std::collections::HashMap;
-#[derive(Hash, Eq, PartialEq)]
+#[derive(Hash, Eq, PartialEq, Clone, Copy, Debug)]
 pub enum BuildingType {
     SmallHouse,
+    LongHouse,
+    Storehouse,
+}
+impl BuildingType {
+    pub fn parse(name: &str) -> Option<BuildingType> {
+        match name {
+            "smallhouse" => Some(BuildingType::SmallHouse),
+            "longhouse" => Some(BuildingType::LongHouse),
+            "storehouse" => Some(BuildingType::Storehouse),
+            _ => None,
+        }
+    }
 }
 lazy_static! {
-    pub static ref BUILDINGS: HashMap<BuildingType, Bitmap> = HashMap::from([(
-        BuildingType::SmallHouse,
-        Bitmap::from(
-            "########\n
+    // `#` is a wall and `x` a floor, a floor on the outline is a door
+    pub static ref BUILDINGS: HashMap<BuildingType, Bitmap> = HashMap::from([
+        (
+            BuildingType::SmallHouse,
+            Bitmap::from(
+                "########\n
                 #xxxxxx#\n
                 #xxxxxx#\n
                 #xxxxxx#\n
                 #xxxxxx#\n
                 #xxxxxx#\n
                 #####x##\n"
+            )
+        ),
+        (
+            BuildingType::LongHouse,
+            Bitmap::from(
+                "##############\n
+                #xxxxxxxxxxxx#\n
+                xxxxxxxxxxxxxx\n
+                #xxxxxxxxxxxx#\n
+                ##############\n"
+            )
+        ),
+        (
+            BuildingType::Storehouse,
+            Bitmap::from(
+                "#####\n
+                #xxx#\n
+                #xxx#\n
+                ##x##\n"
+            )
         )
-    )]);
+    ]);
     pub static ref LETTERS: HashMap<char, Bitmap> = HashMap::from([
         (
             'a',
@@ -405,4 +439,40 @@ impl Bitmap {
         }
         Bitmap { map: char_map }
     }
+    // the drawn characters row by row. line ends and empty rows are dropped
+    // and the indentation the rows after the first have in common is taken
+    // off, since the first row starts right after the opening quote. every
+    // other space stays, so a gap in a side wall keeps its column
+    pub fn grid(&self) -> Vec<Vec<char>> {
+        let mut rows: Vec<(i32, Vec<(i32, char)>)> = vec![];
+        let mut cells: Vec<(&(i32, i32), &char)> = self.map.iter().collect();
+        cells.sort_by_key(|((x, y), _)| (*y, *x));
+        for ((x, y), c) in cells {
+            match rows.last_mut() {
+                Some((row, cells)) if row == y => cells.push((*x, *c)),
+                _ => rows.push((*y, vec![(*x, *c)])),
+            }
+        }
+        let rows: Vec<Vec<char>> = rows
+            .into_iter()
+            .map(|(_, cells)| {
+                cells
+                    .into_iter()
+                    .map(|(_, c)| c)
+                    .filter(|c| *c != '\n' && *c != '\r')
+                    .collect::<Vec<char>>()
+            })
+            .filter(|row| row.iter().any(|c| !c.is_whitespace()))
+            .collect();
+        let indent = rows
+            .iter()
+            .skip(1)
+            .map(|row| row.iter().take_while(|c| c.is_whitespace()).count())
+            .min()
+            .unwrap_or(0);
+        rows.into_iter()
+            .enumerate()
+            .map(|(y, row)| if y == 0 { row } else { row[indent..].to_vec() })
+            .collect()
+    }
 }
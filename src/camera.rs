@@ -0,0 +1,38 @@
+use crate::config::WorldConfig;
+use crate::worldgen::Coords;
+use lazy_static::lazy_static;
+lazy_static! {
+    pub static ref DEFAULT_ZOOM: i32 = 1;
+}
+#[derive(Clone)]
+pub struct Camera {
+    pub coords: Coords,
+    pub ccoords: Coords,
+    pub render_distance_w: i32,
+    pub render_distance_h: i32,
+    pub zoom: i32,
+}
+impl Camera {
+    pub fn new(config: &WorldConfig) -> Camera {
+        Camera {
+            coords: Coords::new(),
+            ccoords: Coords::new(),
+            render_distance_w: config.window_width as i32,
+            render_distance_h: config.window_height as i32,
+            zoom: *DEFAULT_ZOOM,
+        }
+    }
+    // sees the whole world, used when there is no window
+    pub fn overview(config: &WorldConfig) -> Camera {
+        let side = (config.world_size * config.chunk_size * config.tile_size) as i32;
+        Camera {
+            render_distance_w: side,
+            render_distance_h: side,
+            ..Camera::new(config)
+        }
+    }
+    pub fn tick(&mut self, chunk_size: u32) {
+        self.ccoords.x = self.coords.x / chunk_size as f32;
+        self.ccoords.y = self.coords.y / chunk_size as f32;
+    }
+}
@@ -0,0 +1,260 @@
+use crate::config::WorldConfig;
+use std::path::Path;
+
+pub const USAGE: &str = "usage: U [options] [--<config key> <value>...]
+
+options:
+  --seed <n>            seed of the first world, the others count up from it
+                        (random when left out)
+  --worlds <n>          number of worlds simulated side by side (1)
+  --world-size <n>      world side in chunks (16)
+  --chunk-size <n>      chunk side in tiles (128)
+  --step-increment <n>  steps taken per resolve (1)
+  --steps <n>           stop after this many steps, headless runs default to 1000
+  --headless            run without a window
+  --gui                 open a window (default when built with the gui feature)
+  --load <file>         continue from a save instead of generating, with several
+                        worlds <file>.0, <file>.1, ... are read
+  --save <file>         where saves are written (autosave.wgs)
+  --autosave <n>        save every n steps, 0 turns it off (0)
+  --output <dir>        directory for saves and the headless summary (.)
+  --config <file>       read config keys from a `key = value` file
+  -h, --help            print this help
+
+every other config key, like --plates or --sea-level, can be given as
+--<key> <value>. the seed and world options are taken from the save when
+loading and cannot be given with --load
+
+--memory-budget <mb> bounds the memory of the world (512). a quarter of it
+goes to the erosion, water and climate pass over the whole world, run on a
+coarser grid when the world is too wide for it, a quarter to the terrain,
+cut from it in blocks of 2048 tiles a side at 13 bytes a tile, and half to
+the generated chunks; both drop what was used longest ago and generate it
+again when needed. at least four terrain blocks are kept";
+
+// runtime options of the binary, everything else on the command line is
+// handed to the world config
+pub struct Options {
+    pub seed: Option<u32>,
+    pub worlds: usize,
+    pub step_increment: i32,
+    pub steps: Option<u64>,
+    pub headless: bool,
+    pub load: Option<String>,
+    pub save: String,
+    pub autosave: u64,
+    pub output_dir: String,
+    pub help: bool,
+    pub config: WorldConfig,
+}
+impl Options {
+    pub fn new() -> Options {
+        Options {
+            seed: None,
+            worlds: 1,
+            step_increment: 1,
+            steps: None,
+            headless: !cfg!(feature = "gui"),
+            load: None,
+            save: "autosave.wgs".to_string(),
+            autosave: 0,
+            output_dir: ".".to_string(),
+            help: false,
+            config: WorldConfig::new(),
+        }
+    }
+    pub fn from_args(args: &[String]) -> Result<Options, String> {
+        let mut options = Options::new();
+        let mut rest = vec![];
+        let mut i = 0;
+        while i < args.len() {
+            let flag = args[i].replace('_', "-");
+            match flag.as_str() {
+                "-h" | "--help" => options.help = true,
+                "--headless" => options.headless = true,
+                "--gui" => {
+                    if !cfg!(feature = "gui") {
+                        return Err(
+                            "built without the gui feature, --gui is not available".to_string()
+                        );
+                    }
+                    options.headless = false
+                }
+                "--seed" | "--worlds" | "--step-increment" | "--steps" | "--load" | "--save"
+                | "--autosave" | "--output" => {
+                    options.set(&flag, value(args, i)?)?;
+                    i += 1;
+                }
+                _ => {
+                    let key = match flag.strip_prefix("--") {
+                        Some(key) if key == "config" || WorldConfig::is_key(key) => key,
+                        _ => return Err(format!("unknown option {}", args[i])),
+                    };
+                    rest.push(format!("--{}", key));
+                    rest.push(value(args, i)?.to_string());
+                    i += 1;
+                }
+            }
+            i += 1;
+        }
+        if options.help {
+            return Ok(options);
+        }
+        if options.load.is_some() {
+            let given = options
+                .seed
+                .map(|_| "--seed")
+                .or(rest.first().map(|k| k.as_str()));
+            if let Some(flag) = given {
+                return Err(format!(
+                    "{} cannot be combined with --load, world options come from the save",
+                    flag
+                ));
+            }
+        }
+        options.config = WorldConfig::from_args(&rest)?;
+        Ok(options)
+    }
+    fn set(&mut self, flag: &str, value: &str) -> Result<(), String> {
+        match flag {
+            "--seed" => self.seed = Some(parse(flag, value)?),
+            "--worlds" => {
+                self.worlds = parse(flag, value)?;
+                if self.worlds == 0 {
+                    return Err("--worlds must be at least 1".to_string());
+                }
+            }
+            "--step-increment" => {
+                self.step_increment = parse(flag, value)?;
+                if self.step_increment < 1 {
+                    return Err("--step-increment must be at least 1".to_string());
+                }
+            }
+            "--steps" => self.steps = Some(parse(flag, value)?),
+            "--load" => self.load = Some(value.to_string()),
+            "--save" => self.save = value.to_string(),
+            "--autosave" => self.autosave = parse(flag, value)?,
+            "--output" => self.output_dir = value.to_string(),
+            _ => return Err(format!("unknown option {}", flag)),
+        }
+        Ok(())
+    }
+    // relative save paths go into the output directory
+    pub fn save_path(&self) -> String {
+        Path::new(&self.output_dir)
+            .join(&self.save)
+            .to_string_lossy()
+            .to_string()
+    }
+}
+impl Default for Options {
+    fn default() -> Options {
+        Options::new()
+    }
+}
+// the argument after a flag, another flag there means the value was left out
+fn value(args: &[String], i: usize) -> Result<&str, String> {
+    match args.get(i + 1) {
+        Some(value) if !value.starts_with("--") && value != "-h" => Ok(value),
+        _ => Err(format!("missing value for {}", args[i])),
+    }
+}
+fn parse<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value '{}' for {}", value, flag))
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(line: &str) -> Result<Options, String> {
+        let args: Vec<String> = line.split_whitespace().map(String::from).collect();
+        Options::from_args(&args)
+    }
+    #[test]
+    fn help_skips_the_rest() {
+        assert!(options("--help").unwrap().help);
+        assert!(options("-h").unwrap().help);
+        // the world config is not checked when only the usage is wanted
+        assert!(options("--world-size 0 --help").unwrap().help);
+    }
+    #[test]
+    fn options_and_config_keys() {
+        let options = options("--seed 5 --worlds 2 --plates 3 --sea_level -0.5").unwrap();
+        assert_eq!(options.seed, Some(5));
+        assert_eq!(options.worlds, 2);
+        assert_eq!(options.config.plates, 3);
+        assert_eq!(options.config.sea_level, -0.5);
+    }
+    #[test]
+    fn missing_values_are_refused() {
+        assert_eq!(options("--seed").err().unwrap(), "missing value for --seed");
+        assert_eq!(
+            options("--load --headless").err().unwrap(),
+            "missing value for --load"
+        );
+        assert_eq!(
+            options("--plates --help").err().unwrap(),
+            "missing value for --plates"
+        );
+        assert_eq!(
+            options("--plates").err().unwrap(),
+            "missing value for --plates"
+        );
+    }
+    #[test]
+    fn unknown_options_are_refused() {
+        // nothing after the unknown flag is taken as its value
+        assert_eq!(
+            options("--frobnicate --help").err().unwrap(),
+            "unknown option --frobnicate"
+        );
+        assert_eq!(options("seed 5").err().unwrap(), "unknown option seed");
+    }
+    #[test]
+    fn counts_must_be_positive() {
+        assert_eq!(
+            options("--worlds 0").err().unwrap(),
+            "--worlds must be at least 1"
+        );
+        assert_eq!(
+            options("--step-increment 0").err().unwrap(),
+            "--step-increment must be at least 1"
+        );
+        assert_eq!(
+            options("--worlds x").err().unwrap(),
+            "invalid value 'x' for --worlds"
+        );
+    }
+    #[test]
+    fn gui_needs_the_feature() {
+        let gui = options("--headless --gui");
+        if cfg!(feature = "gui") {
+            assert!(!gui.unwrap().headless);
+        } else {
+            assert_eq!(
+                gui.err().unwrap(),
+                "built without the gui feature, --gui is not available"
+            );
+        }
+    }
+    #[test]
+    fn load_takes_the_world_from_the_save() {
+        assert_eq!(
+            options("--load a.wgs --world-size 4").err().unwrap(),
+            "--world-size cannot be combined with --load, world options come from the save"
+        );
+        assert_eq!(
+            options("--seed 1 --load a.wgs").err().unwrap(),
+            "--seed cannot be combined with --load, world options come from the save"
+        );
+        assert_eq!(
+            options("--load a.wgs --config world.cfg").err().unwrap(),
+            "--config cannot be combined with --load, world options come from the save"
+        );
+        let options = options("--load a.wgs --worlds 2 --steps 10").unwrap();
+        assert_eq!(options.load, Some("a.wgs".to_string()));
+        assert_eq!(options.seed, None);
+    }
+}
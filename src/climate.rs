@@ -0,0 +1,217 @@
+use crate::height::HeightMap;
+use crate::worldgen::TileType;
+use rayon::prelude::*;
+use std::collections::VecDeque;
+
+// degrees at the equator and at the poles, which sit on the top and bottom edges
+const EQUATOR_TEMPERATURE: f32 = 30.0;
+const POLE_TEMPERATURE: f32 = -15.0;
+// degrees lost per unit of height above the sea
+const LAPSE_RATE: f32 = 0.5;
+// tiles over which the moisture of a coast fades out
+const COAST_REACH: f32 = 24.0;
+// fraction of the carried moisture lost per tile over land and per unit of climb
+const DRYING: f32 = 0.02;
+const OROGRAPHIC: f32 = 0.08;
+
+// `top` is the world row the map starts at and `rows` the rows of the world
+pub fn temperature(map: &HeightMap, top: usize, rows: usize, sea_level: f32) -> Vec<f32> {
+    let (w, h) = (map.width, map.height);
+    (0..w * h)
+        .into_par_iter()
+        .map(|i| {
+            let row = top + i / w;
+            let latitude = (row as f32 / (rows.max(2) - 1) as f32 - 0.5).abs() * 2.0;
+            let altitude = (map.data[i] - sea_level).max(0.0);
+            EQUATOR_TEMPERATURE
+                - (EQUATOR_TEMPERATURE - POLE_TEMPERATURE) * latitude
+                - altitude * LAPSE_RATE
+        })
+        .collect()
+}
+// half of the moisture comes from the distance to the nearest water, the other
+// half from a westerly wind that picks up water over the sea and rains it out
+// on the windward side of slopes, leaving a shadow behind mountains. `scale` is
+// the tiles a cell of the map stands for
+pub fn moisture(map: &HeightMap, water: &[Option<TileType>], scale: f32) -> Vec<f32> {
+    let (w, h) = (map.width, map.height);
+    let mut distance = vec![u32::MAX; w * h];
+    let mut open = VecDeque::new();
+    for (i, t) in water.iter().enumerate() {
+        if t.is_some() {
+            distance[i] = 0;
+            open.push_back(i);
+        }
+    }
+    while let Some(i) = open.pop_front() {
+        let (x, y) = ((i % w) as i64, (i / w) as i64);
+        for (nx, ny) in [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)] {
+            if !map.contains(nx, ny) {
+                continue;
+            }
+            let n = ny as usize * w + nx as usize;
+            if distance[n] == u32::MAX {
+                distance[n] = distance[i] + 1;
+                open.push_back(n);
+            }
+        }
+    }
+    let rain: Vec<f32> = (0..h)
+        .into_par_iter()
+        .flat_map_iter(|y| {
+            let mut air = 0.3f32;
+            let mut row = Vec::with_capacity(w);
+            for x in 0..w {
+                let i = y * w + x;
+                if water[i].is_some() {
+                    air = 1.0;
+                    row.push(1.0);
+                    continue;
+                }
+                let climb = if x > 0 {
+                    (map.data[i] - map.data[i - 1]).max(0.0)
+                } else {
+                    0.0
+                };
+                let fall = (air * (DRYING * scale + climb * OROGRAPHIC)).min(air);
+                air -= fall;
+                row.push((air * 0.6 + fall * 10.0).min(1.0));
+            }
+            row
+        })
+        .collect();
+    distance
+        .par_iter()
+        .zip(rain.par_iter())
+        .map(|(d, r)| {
+            let coast = if *d == u32::MAX {
+                0.0
+            } else {
+                (-(*d as f32) * scale / COAST_REACH).exp()
+            };
+            (coast + r) / 2.0
+        })
+        .collect()
+}
+// whittaker style lookup on temperature in degrees and moisture in 0..1
+pub fn classify(temperature: f32, moisture: f32) -> TileType {
+    if temperature < -10.0 {
+        TileType::Glacier
+    } else if temperature < -2.0 {
+        TileType::Tundra
+    } else if temperature < 6.0 {
+        if moisture > 0.4 {
+            TileType::Taiga
+        } else {
+            TileType::Tundra
+        }
+    } else if moisture > 0.85 {
+        TileType::Marsh
+    } else if moisture > 0.55 {
+        TileType::TemperateForest
+    } else if moisture > 0.4 {
+        TileType::Grass
+    } else if temperature < 18.0 || moisture > 0.25 {
+        TileType::Steppe
+    } else {
+        TileType::Desert
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a row of land with the sea on its west end and a ridge in the middle
+    fn coast(ridge: f32) -> (HeightMap, Vec<Option<TileType>>) {
+        let mut map = HeightMap::new(40, 1);
+        for x in 0..40 {
+            let height = ridge * (1.0 - (x as f32 - 20.0).abs() / 6.0).max(0.0);
+            map.set(x, 0, height);
+        }
+        let water = (0..40)
+            .map(|x| if x < 3 { Some(TileType::Ocean) } else { None })
+            .collect();
+        (map, water)
+    }
+
+    #[test]
+    fn biomes_follow_the_thresholds() {
+        for (temperature, moisture, ttype) in [
+            (-10.5, 0.9, TileType::Glacier),
+            (-10.0, 0.9, TileType::Tundra),
+            (-2.5, 0.9, TileType::Tundra),
+            (-2.0, 0.41, TileType::Taiga),
+            (5.9, 0.4, TileType::Tundra),
+            (6.0, 0.86, TileType::Marsh),
+            (6.0, 0.85, TileType::TemperateForest),
+            (20.0, 0.56, TileType::TemperateForest),
+            (20.0, 0.55, TileType::Grass),
+            (20.0, 0.41, TileType::Grass),
+            (20.0, 0.4, TileType::Steppe),
+            (17.9, 0.1, TileType::Steppe),
+            (18.0, 0.26, TileType::Steppe),
+            (18.0, 0.25, TileType::Desert),
+            (30.0, 0.0, TileType::Desert),
+        ] {
+            assert_eq!(
+                classify(temperature, moisture),
+                ttype,
+                "{} {}",
+                temperature,
+                moisture
+            );
+        }
+    }
+
+    #[test]
+    fn colder_towards_the_poles() {
+        let map = HeightMap::new(1, 21);
+        let t = temperature(&map, 0, 21, 0.0);
+        assert_eq!(t[10], EQUATOR_TEMPERATURE);
+        assert_eq!(t[0], POLE_TEMPERATURE);
+        assert_eq!(t[20], POLE_TEMPERATURE);
+        for row in 0..10 {
+            assert!(t[row] < t[row + 1]);
+            assert!(t[20 - row] < t[19 - row]);
+        }
+        // a part of the world has the latitudes of its rows in the world
+        let part = temperature(&HeightMap::new(1, 5), 12, 21, 0.0);
+        assert_eq!(part, t[12..17]);
+    }
+
+    #[test]
+    fn colder_higher_up() {
+        let mut map = HeightMap::new(3, 1);
+        map.data = vec![-5.0, 0.0, 10.0];
+        let t = temperature(&map, 0, 1, 0.0);
+        // below the sea is no warmer than at sea level
+        assert_eq!(t[0], t[1]);
+        assert_eq!(t[1] - t[2], 10.0 * LAPSE_RATE);
+        let raised = temperature(&map, 0, 1, 10.0);
+        assert_eq!(raised[2], t[1]);
+    }
+
+    #[test]
+    fn mountains_cast_a_rain_shadow() {
+        let (flat, water) = coast(0.0);
+        let (ridge, _) = coast(10.0);
+        let plain = moisture(&flat, &water, 1.0);
+        let shadowed = moisture(&ridge, &water, 1.0);
+        assert!(plain
+            .iter()
+            .chain(&shadowed)
+            .all(|m| (0.0..=1.0).contains(m)));
+        assert_eq!(plain[..3], [1.0, 1.0, 1.0]);
+        // wetter going up the windward side, drier behind the ridge
+        for x in 15..20 {
+            assert!(shadowed[x] > plain[x], "{}", x);
+        }
+        for x in 27..40 {
+            assert!(shadowed[x] < plain[x], "{}", x);
+        }
+        // and drier away from the coast
+        for x in 3..39 {
+            assert!(plain[x + 1] < plain[x]);
+        }
+    }
+}
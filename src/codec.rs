@@ -0,0 +1,1163 @@
+use crate::bitmap::BuildingType;
+use crate::config::WorldConfig;
+use crate::construction::{Blueprint, Blueprints};
+use crate::faction::{FactionState, Relation};
+use crate::height::{NoiseType, Octave, Warp};
+use crate::roads::{Road, Roads};
+use crate::settlement::{Growth, Settlement};
+use crate::stats::Stats;
+use crate::task::{Job, Target, TaskKind};
+use crate::worldgen::{
+    Alignment, Chunk, Coords, Entity, EntityType, Faction, Gender, Inventory, Item, Personality,
+    Size, Status, Tasks, Tile, TileType,
+};
+use rand::SeedableRng;
+use rand_chacha::ChaCha12Rng;
+use std::collections::{HashMap, HashSet};
+
+// bumped whenever the layout of any encoded type changes
+pub const FORMAT_VERSION: u8 = 13;
+pub const CHUNK_MAGIC: &[u8; 4] = b"WGC1";
+
+pub struct Writer {
+    pub bytes: Vec<u8>,
+}
+impl Default for Writer {
+    fn default() -> Writer {
+        Writer::new()
+    }
+}
+impl Writer {
+    pub fn new() -> Writer {
+        Writer { bytes: vec![] }
+    }
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+    pub fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    pub fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    pub fn f32(&mut self, value: f32) {
+        self.u32(value.to_bits());
+    }
+    pub fn f64(&mut self, value: f64) {
+        self.u64(value.to_bits());
+    }
+    // little endian base 128, small numbers take a single byte
+    pub fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.u8((value as u8) | 0x80);
+            value >>= 7;
+        }
+        self.u8(value as u8);
+    }
+    pub fn zigzag(&mut self, value: i64) {
+        self.varint(((value << 1) ^ (value >> 63)) as u64);
+    }
+    pub fn str(&mut self, value: &str) {
+        self.varint(value.len() as u64);
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+    // neighbouring values are close, so their sign, exponent and high mantissa
+    // bits match, the xor of their bits is a small number and the varint stays
+    // short
+    pub fn f32_deltas(&mut self, values: &[f32]) {
+        let mut previous = 0u32;
+        for value in values {
+            let bits = value.to_bits();
+            self.varint((bits ^ previous) as u64);
+            previous = bits;
+        }
+    }
+}
+pub struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+impl<'a> Reader<'a> {
+    pub fn from(bytes: &'a [u8]) -> Reader<'a> {
+        Reader { bytes, pos: 0 }
+    }
+    pub fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if n > self.remaining() {
+            return Err("unexpected end of data".to_string());
+        }
+        let slice = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(slice)
+    }
+    pub fn remaining(&self) -> usize {
+        self.bytes.len() - self.pos
+    }
+    pub fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+    pub fn bool(&mut self) -> Result<bool, String> {
+        Ok(self.u8()? != 0)
+    }
+    pub fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+    pub fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+    pub fn f32(&mut self) -> Result<f32, String> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+    pub fn f64(&mut self) -> Result<f64, String> {
+        Ok(f64::from_bits(self.u64()?))
+    }
+    pub fn varint(&mut self) -> Result<u64, String> {
+        let mut value = 0u64;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift >= 64 {
+                return Err("varint too long".to_string());
+            }
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+            shift += 7;
+        }
+    }
+    pub fn zigzag(&mut self) -> Result<i64, String> {
+        let value = self.varint()?;
+        Ok((value >> 1) as i64 ^ -((value & 1) as i64))
+    }
+    pub fn str(&mut self) -> Result<String, String> {
+        let len = self.varint()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|e| e.to_string())
+    }
+    // every value takes at least a byte, so a count larger than what is left
+    // is damaged and is refused before anything is allocated for it
+    pub fn f32_deltas(&mut self, count: usize) -> Result<Vec<f32>, String> {
+        if count > self.remaining() {
+            return Err("unexpected end of data".to_string());
+        }
+        let mut previous = 0u32;
+        let mut values = Vec::with_capacity(count);
+        for _ in 0..count {
+            let bits = (self.varint()? as u32) ^ previous;
+            values.push(f32::from_bits(bits));
+            previous = bits;
+        }
+        Ok(values)
+    }
+}
+pub trait Codec: Sized {
+    fn encode(&self, w: &mut Writer);
+    fn decode(r: &mut Reader) -> Result<Self, String>;
+}
+fn invalid<T>(what: &str, tag: u8) -> Result<T, String> {
+    Err(format!("invalid {} tag {}", what, tag))
+}
+impl Codec for TileType {
+    fn encode(&self, w: &mut Writer) {
+        w.u8(match self {
+            TileType::Grass => 0,
+            TileType::WoodenWall => 1,
+            TileType::River => 2,
+            TileType::Lake => 3,
+            TileType::Ocean => 4,
+            TileType::Tundra => 5,
+            TileType::Taiga => 6,
+            TileType::TemperateForest => 7,
+            TileType::Steppe => 8,
+            TileType::Desert => 9,
+            TileType::Marsh => 10,
+            TileType::Glacier => 11,
+            TileType::WoodenFloor => 12,
+            TileType::WoodenDoor => 13,
+            TileType::Road => 14,
+            TileType::Bridge => 15,
+        });
+    }
+    fn decode(r: &mut Reader) -> Result<TileType, String> {
+        Ok(match r.u8()? {
+            0 => TileType::Grass,
+            1 => TileType::WoodenWall,
+            2 => TileType::River,
+            3 => TileType::Lake,
+            4 => TileType::Ocean,
+            5 => TileType::Tundra,
+            6 => TileType::Taiga,
+            7 => TileType::TemperateForest,
+            8 => TileType::Steppe,
+            9 => TileType::Desert,
+            10 => TileType::Marsh,
+            11 => TileType::Glacier,
+            12 => TileType::WoodenFloor,
+            13 => TileType::WoodenDoor,
+            14 => TileType::Road,
+            15 => TileType::Bridge,
+            tag => return invalid("tile type", tag),
+        })
+    }
+}
+impl Codec for Faction {
+    fn encode(&self, w: &mut Writer) {
+        w.u8(match self {
+            Faction::Empty => 0,
+            Faction::Hiisi => 1,
+            Faction::Virumaa => 2,
+            Faction::Pohjola => 3,
+            Faction::Tapiola => 4,
+            Faction::Kalevala => 5,
+            Faction::Novgorod => 6,
+        });
+    }
+    fn decode(r: &mut Reader) -> Result<Faction, String> {
+        Ok(match r.u8()? {
+            0 => Faction::Empty,
+            1 => Faction::Hiisi,
+            2 => Faction::Virumaa,
+            3 => Faction::Pohjola,
+            4 => Faction::Tapiola,
+            5 => Faction::Kalevala,
+            6 => Faction::Novgorod,
+            tag => return invalid("faction", tag),
+        })
+    }
+}
+impl Codec for Gender {
+    fn encode(&self, w: &mut Writer) {
+        w.u8(match self {
+            Gender::Male => 0,
+            Gender::Female => 1,
+            Gender::Other => 2,
+        });
+    }
+    fn decode(r: &mut Reader) -> Result<Gender, String> {
+        Ok(match r.u8()? {
+            0 => Gender::Male,
+            1 => Gender::Female,
+            2 => Gender::Other,
+            tag => return invalid("gender", tag),
+        })
+    }
+}
+impl Codec for Status {
+    fn encode(&self, w: &mut Writer) {
+        w.u8(match self {
+            Status::Talking => 0,
+            Status::Fighting => 1,
+            Status::Idle => 2,
+        });
+    }
+    fn decode(r: &mut Reader) -> Result<Status, String> {
+        Ok(match r.u8()? {
+            0 => Status::Talking,
+            1 => Status::Fighting,
+            2 => Status::Idle,
+            tag => return invalid("status", tag),
+        })
+    }
+}
+impl Codec for EntityType {
+    fn encode(&self, w: &mut Writer) {
+        w.u8(match self {
+            EntityType::Human => 0,
+        });
+    }
+    fn decode(r: &mut Reader) -> Result<EntityType, String> {
+        Ok(match r.u8()? {
+            0 => EntityType::Human,
+            tag => return invalid("entity type", tag),
+        })
+    }
+}
+impl Codec for Item {
+    fn encode(&self, w: &mut Writer) {
+        w.u8(match self {
+            Item::Bread => 0,
+            Item::Coin => 1,
+            Item::Sword => 2,
+            Item::Armour => 3,
+            Item::Wood => 4,
+        });
+    }
+    fn decode(r: &mut Reader) -> Result<Item, String> {
+        Ok(match r.u8()? {
+            0 => Item::Bread,
+            1 => Item::Coin,
+            2 => Item::Sword,
+            3 => Item::Armour,
+            4 => Item::Wood,
+            tag => return invalid("item", tag),
+        })
+    }
+}
+impl Codec for Coords {
+    fn encode(&self, w: &mut Writer) {
+        w.f32(self.x);
+        w.f32(self.y);
+    }
+    fn decode(r: &mut Reader) -> Result<Coords, String> {
+        Ok(Coords::from((r.f32()?, r.f32()?)))
+    }
+}
+impl Codec for Size {
+    fn encode(&self, w: &mut Writer) {
+        w.zigzag(self.x as i64);
+        w.zigzag(self.y as i64);
+    }
+    fn decode(r: &mut Reader) -> Result<Size, String> {
+        Ok(Size::from((r.zigzag()? as i32, r.zigzag()? as i32)))
+    }
+}
+impl<T: Codec> Codec for Option<T> {
+    fn encode(&self, w: &mut Writer) {
+        match self {
+            Some(value) => {
+                w.bool(true);
+                value.encode(w);
+            }
+            None => w.bool(false),
+        }
+    }
+    fn decode(r: &mut Reader) -> Result<Option<T>, String> {
+        Ok(if r.bool()? { Some(T::decode(r)?) } else { None })
+    }
+}
+impl Codec for Inventory {
+    fn encode(&self, w: &mut Writer) {
+        // sorted so equal inventories encode to equal bytes
+        let mut items: Vec<(&Item, &i32)> = self.items.iter().collect();
+        items.sort_by_key(|(item, _)| encode(*item));
+        w.varint(items.len() as u64);
+        for (item, count) in items {
+            item.encode(w);
+            w.zigzag(*count as i64);
+        }
+    }
+    fn decode(r: &mut Reader) -> Result<Inventory, String> {
+        let len = r.varint()?;
+        let mut items = HashMap::new();
+        for _ in 0..len {
+            let item = Item::decode(r)?;
+            items.insert(item, r.zigzag()? as i32);
+        }
+        Ok(Inventory { items })
+    }
+}
+impl Codec for Stats {
+    fn encode(&self, w: &mut Writer) {
+        w.u8(self.health);
+        w.u8(self.max_health);
+        w.u8(self.hunger);
+        w.u8(self.starvation);
+        w.u8(self.strength);
+        w.u8(self.intelligence);
+        w.u8(self.agility);
+    }
+    fn decode(r: &mut Reader) -> Result<Stats, String> {
+        Ok(Stats {
+            health: r.u8()?,
+            max_health: r.u8()?,
+            hunger: r.u8()?,
+            starvation: r.u8()?,
+            strength: r.u8()?,
+            intelligence: r.u8()?,
+            agility: r.u8()?,
+        })
+    }
+}
+impl Codec for Tasks {
+    fn encode(&self, w: &mut Writer) {
+        for (priority, available) in [
+            self.build,
+            self.fight,
+            self.animal_husbandry,
+            self.industry,
+            self.farm,
+            self.oil_rig,
+        ] {
+            w.u8(priority);
+            w.bool(available);
+        }
+    }
+    fn decode(r: &mut Reader) -> Result<Tasks, String> {
+        let mut task = || -> Result<(u8, bool), String> { Ok((r.u8()?, r.bool()?)) };
+        Ok(Tasks {
+            build: task()?,
+            fight: task()?,
+            animal_husbandry: task()?,
+            industry: task()?,
+            farm: task()?,
+            oil_rig: task()?,
+        })
+    }
+}
+impl Codec for Alignment {
+    fn encode(&self, w: &mut Writer) {
+        self.faction.encode(w);
+        w.u8(self.personality.aggression);
+    }
+    fn decode(r: &mut Reader) -> Result<Alignment, String> {
+        Ok(Alignment {
+            faction: Faction::decode(r)?,
+            personality: Personality {
+                aggression: r.u8()?,
+            },
+        })
+    }
+}
+impl Codec for BuildingType {
+    fn encode(&self, w: &mut Writer) {
+        w.u8(match self {
+            BuildingType::SmallHouse => 0,
+            BuildingType::LongHouse => 1,
+            BuildingType::Storehouse => 2,
+        });
+    }
+    fn decode(r: &mut Reader) -> Result<BuildingType, String> {
+        Ok(match r.u8()? {
+            0 => BuildingType::SmallHouse,
+            1 => BuildingType::LongHouse,
+            2 => BuildingType::Storehouse,
+            tag => return invalid("building type", tag),
+        })
+    }
+}
+impl Codec for Blueprint {
+    fn encode(&self, w: &mut Writer) {
+        w.varint(self.width as u64);
+        w.varint(self.height as u64);
+        for tile in &self.tiles {
+            tile.encode(w);
+        }
+    }
+    fn decode(r: &mut Reader) -> Result<Blueprint, String> {
+        let width = r.varint()? as usize;
+        let height = r.varint()? as usize;
+        let cells = width
+            .checked_mul(height)
+            .ok_or(format!("blueprint of {} x {} tiles", width, height))?;
+        Ok(Blueprint {
+            width,
+            height,
+            tiles: (0..cells)
+                .map(|_| Option::<TileType>::decode(r))
+                .collect::<Result<Vec<Option<TileType>>, String>>()?,
+        })
+    }
+}
+// sorted by building type so the same blueprints always encode the same
+impl Codec for Blueprints {
+    fn encode(&self, w: &mut Writer) {
+        let mut btypes: Vec<&BuildingType> = self.map.keys().collect();
+        btypes.sort_by_key(|btype| encode(*btype));
+        w.varint(btypes.len() as u64);
+        for btype in btypes {
+            btype.encode(w);
+            self.map[btype].encode(w);
+        }
+    }
+    fn decode(r: &mut Reader) -> Result<Blueprints, String> {
+        Ok(Blueprints {
+            map: (0..r.varint()?)
+                .map(|_| Ok((BuildingType::decode(r)?, Blueprint::decode(r)?)))
+                .collect::<Result<HashMap<BuildingType, Blueprint>, String>>()?,
+        })
+    }
+}
+impl Codec for Growth {
+    fn encode(&self, w: &mut Writer) {
+        w.u8(match self {
+            Growth::Growing => 0,
+            Growth::Stable => 1,
+            Growth::Shrinking => 2,
+            Growth::Abandoned => 3,
+        });
+    }
+    fn decode(r: &mut Reader) -> Result<Growth, String> {
+        Ok(match r.u8()? {
+            0 => Growth::Growing,
+            1 => Growth::Stable,
+            2 => Growth::Shrinking,
+            3 => Growth::Abandoned,
+            tag => return invalid("growth", tag),
+        })
+    }
+}
+impl Codec for Settlement {
+    fn encode(&self, w: &mut Writer) {
+        w.varint(self.id as u64);
+        w.str(&self.name);
+        self.faction.encode(w);
+        w.varint(self.centre.0 as u64);
+        w.varint(self.centre.1 as u64);
+        w.varint(self.chunk as u64);
+        w.varint(self.population as u64);
+        w.varint(self.buildings.len() as u64);
+        for (btype, corner) in &self.buildings {
+            btype.encode(w);
+            w.varint(corner.0 as u64);
+            w.varint(corner.1 as u64);
+        }
+        self.growth.encode(w);
+    }
+    fn decode(r: &mut Reader) -> Result<Settlement, String> {
+        Ok(Settlement {
+            id: r.varint()? as usize,
+            name: r.str()?,
+            faction: Faction::decode(r)?,
+            centre: (r.varint()? as usize, r.varint()? as usize),
+            chunk: r.varint()? as usize,
+            population: r.varint()? as usize,
+            buildings: (0..r.varint()?)
+                .map(|_| {
+                    Ok((
+                        BuildingType::decode(r)?,
+                        (r.varint()? as usize, r.varint()? as usize),
+                    ))
+                })
+                .collect::<Result<Vec<_>, String>>()?,
+            growth: Growth::decode(r)?,
+        })
+    }
+}
+impl Codec for Road {
+    fn encode(&self, w: &mut Writer) {
+        w.varint(self.from as u64);
+        w.varint(self.to as u64);
+        w.varint(self.tiles.len() as u64);
+        for tile in &self.tiles {
+            w.varint(*tile as u64);
+        }
+        w.f32(self.length);
+    }
+    fn decode(r: &mut Reader) -> Result<Road, String> {
+        Ok(Road {
+            from: r.varint()? as usize,
+            to: r.varint()? as usize,
+            tiles: (0..r.varint()?)
+                .map(|_| Ok(r.varint()? as usize))
+                .collect::<Result<Vec<usize>, String>>()?,
+            length: r.f32()?,
+        })
+    }
+}
+// the links between settlements are rebuilt from the roads
+impl Codec for Roads {
+    fn encode(&self, w: &mut Writer) {
+        w.varint(self.roads.len() as u64);
+        for road in &self.roads {
+            road.encode(w);
+        }
+    }
+    fn decode(r: &mut Reader) -> Result<Roads, String> {
+        let mut roads = Roads::new();
+        for _ in 0..r.varint()? {
+            roads.add(Road::decode(r)?);
+        }
+        Ok(roads)
+    }
+}
+impl Codec for Relation {
+    fn encode(&self, w: &mut Writer) {
+        w.u8(match self {
+            Relation::War => 0,
+            Relation::Peace => 1,
+            Relation::Alliance => 2,
+            Relation::TradePact => 3,
+        });
+    }
+    fn decode(r: &mut Reader) -> Result<Relation, String> {
+        Ok(match r.u8()? {
+            0 => Relation::War,
+            1 => Relation::Peace,
+            2 => Relation::Alliance,
+            3 => Relation::TradePact,
+            tag => return invalid("relation", tag),
+        })
+    }
+}
+// territory and the maps are sorted so a faction always encodes the same
+impl Codec for FactionState {
+    fn encode(&self, w: &mut Writer) {
+        self.faction.encode(w);
+        let mut territory: Vec<&usize> = self.territory.iter().collect();
+        territory.sort();
+        w.varint(territory.len() as u64);
+        for chunk in territory {
+            w.varint(*chunk as u64);
+        }
+        match self.capital {
+            Some(id) => {
+                w.u8(1);
+                w.varint(id as u64);
+            }
+            None => w.u8(0),
+        }
+        w.zigzag(self.treasury as i64);
+        let mut others: Vec<&Faction> = self.relations.keys().collect();
+        others.sort_by_key(|faction| encode(*faction));
+        w.varint(others.len() as u64);
+        for other in others {
+            other.encode(w);
+            self.relation(other).encode(w);
+            w.zigzag(self.opinion(other) as i64);
+        }
+    }
+    fn decode(r: &mut Reader) -> Result<FactionState, String> {
+        let faction = Faction::decode(r)?;
+        let territory = (0..r.varint()?)
+            .map(|_| Ok(r.varint()? as usize))
+            .collect::<Result<HashSet<usize>, String>>()?;
+        let capital = match r.u8()? {
+            0 => None,
+            1 => Some(r.varint()? as usize),
+            tag => return invalid("capital", tag),
+        };
+        let treasury = r.zigzag()? as i32;
+        let mut relations = HashMap::new();
+        let mut opinions = HashMap::new();
+        for _ in 0..r.varint()? {
+            let other = Faction::decode(r)?;
+            relations.insert(other.clone(), Relation::decode(r)?);
+            opinions.insert(other, r.zigzag()? as i32);
+        }
+        Ok(FactionState {
+            faction,
+            territory,
+            capital,
+            treasury,
+            relations,
+            opinions,
+        })
+    }
+}
+impl Codec for TaskKind {
+    fn encode(&self, w: &mut Writer) {
+        w.u8(match self {
+            TaskKind::Build => 0,
+            TaskKind::Fight => 1,
+            TaskKind::AnimalHusbandry => 2,
+            TaskKind::Industry => 3,
+            TaskKind::Farm => 4,
+            TaskKind::OilRig => 5,
+        });
+    }
+    fn decode(r: &mut Reader) -> Result<TaskKind, String> {
+        Ok(match r.u8()? {
+            0 => TaskKind::Build,
+            1 => TaskKind::Fight,
+            2 => TaskKind::AnimalHusbandry,
+            3 => TaskKind::Industry,
+            4 => TaskKind::Farm,
+            5 => TaskKind::OilRig,
+            tag => return invalid("task", tag),
+        })
+    }
+}
+impl Codec for Job {
+    fn encode(&self, w: &mut Writer) {
+        self.kind.encode(w);
+        match self.target {
+            Target::Tile(i) => {
+                w.u8(0);
+                w.varint(i as u64);
+            }
+            Target::Entity(id) => {
+                w.u8(1);
+                w.varint(id as u64);
+            }
+        }
+        w.varint(self.progress as u64);
+    }
+    fn decode(r: &mut Reader) -> Result<Job, String> {
+        let kind = TaskKind::decode(r)?;
+        let target = match r.u8()? {
+            0 => Target::Tile(r.varint()? as usize),
+            1 => Target::Entity(r.varint()? as usize),
+            tag => return invalid("task target", tag),
+        };
+        Ok(Job {
+            kind,
+            target,
+            progress: r.varint()? as u16,
+        })
+    }
+}
+impl Codec for Entity {
+    fn encode(&self, w: &mut Writer) {
+        self.coords.encode(w);
+        w.f32(self.vel.0);
+        w.f32(self.vel.1);
+        w.f32(self.heading);
+        self.target.encode(w);
+        w.varint(self.path.len() as u64);
+        for waypoint in &self.path {
+            waypoint.encode(w);
+        }
+        self.etype.encode(w);
+        self.stats.encode(w);
+        self.status.encode(w);
+        w.varint(self.index as u64);
+        self.alignment.encode(w);
+        self.inventory.encode(w);
+        w.str(&self.name);
+        self.gender.encode(w);
+        self.tasks.encode(w);
+        self.job.encode(w);
+    }
+    fn decode(r: &mut Reader) -> Result<Entity, String> {
+        Ok(Entity {
+            coords: Coords::decode(r)?,
+            vel: (r.f32()?, r.f32()?),
+            heading: r.f32()?,
+            target: Option::<Coords>::decode(r)?,
+            path: (0..r.varint()?)
+                .map(|_| Coords::decode(r))
+                .collect::<Result<Vec<Coords>, String>>()?,
+            etype: EntityType::decode(r)?,
+            stats: Stats::decode(r)?,
+            status: Status::decode(r)?,
+            index: r.varint()? as usize,
+            alignment: Alignment::decode(r)?,
+            inventory: Inventory::decode(r)?,
+            name: r.str()?,
+            gender: Gender::decode(r)?,
+            tasks: Tasks::decode(r)?,
+            job: Option::<Job>::decode(r)?,
+        })
+    }
+}
+// tiles are stored column by column rather than one struct after another:
+// positions are implied by the chunk, heights and climate are xor delta coded
+// and tile types are run length coded
+impl Codec for Chunk {
+    fn encode(&self, w: &mut Writer) {
+        w.bytes.extend_from_slice(CHUNK_MAGIC);
+        w.u8(FORMAT_VERSION);
+        self.coords.encode(w);
+        w.varint(self.index as u64);
+        w.bytes.extend_from_slice(&self.rng.get_seed());
+        w.u64(self.rng.get_stream());
+        let word_pos = self.rng.get_word_pos();
+        w.u64(word_pos as u64);
+        w.u64((word_pos >> 64) as u64);
+        w.varint(self.tiles.len() as u64);
+        if let Some(first) = self.tiles.first() {
+            first.coords.encode(w);
+            first.size.encode(w);
+        }
+        let side = (self.tiles.len() as f64).sqrt() as u64;
+        w.varint(side);
+        let heights: Vec<f32> = self.tiles.iter().map(|t| t.height).collect();
+        let temperature: Vec<f32> = self.tiles.iter().map(|t| t.temperature).collect();
+        let moisture: Vec<f32> = self.tiles.iter().map(|t| t.moisture).collect();
+        w.f32_deltas(&heights);
+        w.f32_deltas(&temperature);
+        w.f32_deltas(&moisture);
+        let mut i = 0;
+        while i < self.tiles.len() {
+            let run = self.tiles[i..]
+                .iter()
+                .take_while(|t| t.ttype == self.tiles[i].ttype)
+                .count();
+            w.varint(run as u64);
+            self.tiles[i].ttype.encode(w);
+            i += run;
+        }
+        // designed and held slots are sparse, only the occupied ones are written
+        let sparse: Vec<&Tile> = self
+            .tiles
+            .iter()
+            .filter(|t| t.designed.is_some() || t.holds.is_some())
+            .collect();
+        w.varint(sparse.len() as u64);
+        for tile in sparse {
+            w.varint(tile.index as u64);
+            tile.designed.encode(w);
+            tile.holds.encode(w);
+        }
+        w.varint(self.entities.len() as u64);
+        for entity in &self.entities {
+            entity.encode(w);
+        }
+        // sorted so equal chunks encode to equal bytes
+        let mut stores: Vec<(&Faction, &Inventory)> = self.stores.iter().collect();
+        stores.sort_by_key(|(faction, _)| encode(*faction));
+        w.varint(stores.len() as u64);
+        for (faction, store) in stores {
+            faction.encode(w);
+            store.encode(w);
+        }
+    }
+    fn decode(r: &mut Reader) -> Result<Chunk, String> {
+        if r.take(4)? != CHUNK_MAGIC {
+            return Err("not a chunk".to_string());
+        }
+        let version = r.u8()?;
+        if version != FORMAT_VERSION {
+            return Err(format!("unsupported chunk format version {}", version));
+        }
+        let coords = Coords::decode(r)?;
+        let index = r.varint()? as usize;
+        let seed: [u8; 32] = r.take(32)?.try_into().unwrap();
+        let mut rng = ChaCha12Rng::from_seed(seed);
+        rng.set_stream(r.u64()?);
+        let low = r.u64()? as u128;
+        let high = r.u64()? as u128;
+        rng.set_word_pos(low | (high << 64));
+        let count = r.varint()? as usize;
+        let (origin, size) = if count > 0 {
+            (Coords::decode(r)?, Size::decode(r)?)
+        } else {
+            (Coords::new(), Size::from((1, 1)))
+        };
+        let side = (r.varint()? as usize).max(1);
+        let heights = r.f32_deltas(count)?;
+        let temperature = r.f32_deltas(count)?;
+        let moisture = r.f32_deltas(count)?;
+        // grown run by run, a damaged run fails before it is allocated
+        let mut ttypes = vec![];
+        while ttypes.len() < count {
+            let run = r.varint()? as usize;
+            let ttype = TileType::decode(r)?;
+            if run == 0 || ttypes.len() + run > count {
+                return Err("invalid tile type run".to_string());
+            }
+            ttypes.extend(std::iter::repeat_n(ttype, run));
+        }
+        let mut tiles: Vec<Tile> = (0..count)
+            .map(|i| {
+                let mut tile = Tile::from(
+                    Coords::from((origin.x + (i % side) as f32, origin.y + (i / side) as f32)),
+                    i,
+                    size.clone(),
+                    heights[i],
+                    ttypes[i].clone(),
+                    None,
+                );
+                tile.temperature = temperature[i];
+                tile.moisture = moisture[i];
+                tile
+            })
+            .collect();
+        for _ in 0..r.varint()? {
+            let i = r.varint()? as usize;
+            if i >= count {
+                return Err("tile index out of range".to_string());
+            }
+            tiles[i].designed = Option::<TileType>::decode(r)?;
+            tiles[i].holds = Option::<Entity>::decode(r)?;
+        }
+        let mut entities = vec![];
+        for _ in 0..r.varint()? {
+            entities.push(Entity::decode(r)?);
+        }
+        let mut stores = HashMap::new();
+        for _ in 0..r.varint()? {
+            let faction = Faction::decode(r)?;
+            stores.insert(faction, Inventory::decode(r)?);
+        }
+        let mut chunk = Chunk::from(tiles, entities, coords, index);
+        chunk.rng = rng;
+        chunk.stores = stores;
+        Ok(chunk)
+    }
+}
+impl Codec for Octave {
+    fn encode(&self, w: &mut Writer) {
+        w.u8(match self.ntype {
+            NoiseType::Perlin => 0,
+            NoiseType::Simplex => 1,
+            NoiseType::Worley => 2,
+            NoiseType::Ridged => 3,
+            NoiseType::Billow => 4,
+        });
+        w.f64(self.frequency);
+        w.f64(self.amplitude);
+        w.bool(self.warp.is_some());
+        if let Some(warp) = &self.warp {
+            w.f64(warp.frequency);
+            w.f64(warp.amplitude);
+        }
+    }
+    fn decode(r: &mut Reader) -> Result<Octave, String> {
+        let ntype = match r.u8()? {
+            0 => NoiseType::Perlin,
+            1 => NoiseType::Simplex,
+            2 => NoiseType::Worley,
+            3 => NoiseType::Ridged,
+            4 => NoiseType::Billow,
+            tag => return invalid("noise type", tag),
+        };
+        let frequency = r.f64()?;
+        let amplitude = r.f64()?;
+        let warp = if r.bool()? {
+            Some(Warp {
+                frequency: r.f64()?,
+                amplitude: r.f64()?,
+            })
+        } else {
+            None
+        };
+        Ok(Octave::from(ntype, frequency, amplitude, warp))
+    }
+}
+impl Codec for WorldConfig {
+    fn encode(&self, w: &mut Writer) {
+        w.u32(self.world_size);
+        w.u32(self.chunk_size);
+        w.u32(self.tile_size);
+        w.f64(self.noise_scale);
+        w.varint(self.octaves.len() as u64);
+        for octave in &self.octaves {
+            octave.encode(w);
+        }
+        w.f32(self.jitter);
+        w.u32(self.plates);
+        w.u32(self.erosion_droplets);
+        w.u32(self.thermal_iterations);
+        w.f32(self.talus);
+        w.f32(self.sea_level);
+        w.u32(self.river_threshold);
+        w.zigzag(self.vicinity_dist as i64);
+        w.varint(self.memory_budget as u64);
+        w.str(&self.page_dir);
+        w.varint(self.region_size as u64);
+        w.u32(self.window_width);
+        w.u32(self.window_height);
+        w.f32(self.camera_step);
+    }
+    fn decode(r: &mut Reader) -> Result<WorldConfig, String> {
+        let world_size = r.u32()?;
+        let chunk_size = r.u32()?;
+        let tile_size = r.u32()?;
+        let noise_scale = r.f64()?;
+        let mut octaves = vec![];
+        for _ in 0..r.varint()? {
+            octaves.push(Octave::decode(r)?);
+        }
+        Ok(WorldConfig {
+            world_size,
+            chunk_size,
+            tile_size,
+            noise_scale,
+            octaves,
+            jitter: r.f32()?,
+            plates: r.u32()?,
+            erosion_droplets: r.u32()?,
+            thermal_iterations: r.u32()?,
+            talus: r.f32()?,
+            sea_level: r.f32()?,
+            river_threshold: r.u32()?,
+            vicinity_dist: r.zigzag()? as i32,
+            memory_budget: r.varint()? as usize,
+            page_dir: r.str()?,
+            region_size: r.varint()? as usize,
+            window_width: r.u32()?,
+            window_height: r.u32()?,
+            camera_step: r.f32()?,
+        })
+    }
+}
+pub fn encode<T: Codec>(value: &T) -> Vec<u8> {
+    let mut w = Writer::new();
+    value.encode(&mut w);
+    w.bytes
+}
+pub fn decode<T: Codec>(bytes: &[u8]) -> Result<T, String> {
+    T::decode(&mut Reader::from(bytes))
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::height::Warp;
+    use crate::worldgen::worldgen;
+
+    fn small() -> WorldConfig {
+        let mut config = WorldConfig::new();
+        config.world_size = 2;
+        config.chunk_size = 16;
+        config.erosion_droplets = 100;
+        config
+    }
+    fn entity() -> Entity {
+        let mut entity = Entity::new(42);
+        entity.coords = Coords::from((3.25, 7.5));
+        entity.vel = (0.5, -1.0);
+        entity.target = Some(Coords::from((4.0, 8.0)));
+        entity.path = vec![Coords::from((5.0, 9.0)), Coords::from((6.0, 9.0))];
+        entity.stats = Stats::from(3, 4, 5);
+        entity.stats.hurt(7);
+        entity.inventory.add(Item::Bread, 2);
+        entity.inventory.add(Item::Coin, -1);
+        entity.name = "Väinö".to_string();
+        entity.gender = Gender::Other;
+        entity.job = Some(Job::from(TaskKind::Farm, Target::Tile(9)));
+        entity
+    }
+    fn chunk() -> Chunk {
+        let mut world = worldgen(3, &small()).unwrap();
+        let chunk = world.fetch_chunk_mut(1);
+        chunk.entities.push(entity());
+        chunk.tiles[5].designed = Some(TileType::WoodenDoor);
+        chunk.tiles[6].holds = Some(entity());
+        chunk
+            .stores
+            .entry(Faction::Kalevala)
+            .or_insert_with(Inventory::new)
+            .add(Item::Wood, 3);
+        chunk.clone()
+    }
+    // decoding and encoding again gives the same bytes, so nothing was lost
+    fn round_trip<T: Codec>(value: &T) -> T {
+        let bytes = encode(value);
+        let decoded: T = decode(&bytes).unwrap();
+        assert_eq!(encode(&decoded), bytes);
+        decoded
+    }
+    fn truncated_fails<T: Codec>(value: &T) {
+        let bytes = encode(value);
+        let step = (bytes.len() / 200).max(1);
+        for len in (0..bytes.len()).step_by(step) {
+            assert!(decode::<T>(&bytes[..len]).is_err(), "{} bytes", len);
+        }
+    }
+    #[test]
+    fn chunk_round_trip() {
+        let chunk = chunk();
+        let decoded = round_trip(&chunk);
+        assert_eq!(decoded.index, chunk.index);
+        assert_eq!(decoded.coords, chunk.coords);
+        assert_eq!(decoded.tiles.len(), chunk.tiles.len());
+        assert_eq!(decoded.entities.len(), chunk.entities.len());
+        for (a, b) in decoded.tiles.iter().zip(&chunk.tiles) {
+            assert_eq!(a.coords, b.coords);
+            assert_eq!(a.height.to_bits(), b.height.to_bits());
+            assert_eq!(a.ttype, b.ttype);
+        }
+        assert_eq!(decoded.tiles[5].designed, Some(TileType::WoodenDoor));
+        assert_eq!(decoded.tiles[6].holds.as_ref().unwrap().name, "Väinö");
+        assert_eq!(decoded.stores[&Faction::Kalevala].count(&Item::Wood), 3);
+        assert_eq!(decoded.rng, chunk.rng);
+    }
+    #[test]
+    fn entity_round_trip() {
+        let entity = entity();
+        let decoded = round_trip(&entity);
+        assert_eq!(decoded.index, 42);
+        assert_eq!(decoded.coords, entity.coords);
+        assert_eq!(decoded.path, entity.path);
+        assert_eq!(decoded.stats.health, entity.stats.health);
+        assert_eq!(decoded.inventory.count(&Item::Coin), -1);
+        assert_eq!(decoded.name, entity.name);
+        assert!(matches!(decoded.job.unwrap().target, Target::Tile(9)));
+    }
+    #[test]
+    fn tile_round_trip() {
+        for tag in 0..=15 {
+            let ttype: TileType = decode(&[tag]).unwrap();
+            assert_eq!(encode(&ttype), vec![tag]);
+        }
+        assert!(decode::<TileType>(&[16]).is_err());
+        let designed = Some(TileType::Bridge);
+        assert_eq!(round_trip(&designed), designed);
+    }
+    #[test]
+    fn config_round_trip() {
+        let mut config = small();
+        config
+            .set("octaves", "ridged 2 0.5 warp 4 1.5; worley 8 0.25")
+            .unwrap();
+        config.sea_level = -0.125;
+        config.page_dir = "pages".to_string();
+        let decoded = round_trip(&config);
+        assert_eq!(decoded.world_size, 2);
+        assert_eq!(decoded.octaves.len(), 2);
+        assert_eq!(decoded.sea_level, -0.125);
+        assert_eq!(decoded.page_dir, "pages");
+        assert!(matches!(
+            decoded.octaves[0].warp,
+            Some(Warp {
+                frequency: 4.0,
+                amplitude: 1.5
+            })
+        ));
+    }
+    #[test]
+    fn heights_compress() {
+        let mut config = small();
+        config.world_size = 1;
+        config.chunk_size = 64;
+        let mut world = worldgen(3, &config).unwrap();
+        let heights: Vec<f32> = world
+            .fetch_chunk(0)
+            .tiles
+            .iter()
+            .map(|t| t.height)
+            .collect();
+        let mut w = Writer::new();
+        w.f32_deltas(&heights);
+        assert!(
+            w.bytes.len() < 4 * heights.len(),
+            "{} bytes for {} heights",
+            w.bytes.len(),
+            heights.len()
+        );
+        let decoded = Reader::from(&w.bytes).f32_deltas(heights.len()).unwrap();
+        assert!(decoded
+            .iter()
+            .zip(&heights)
+            .all(|(a, b)| a.to_bits() == b.to_bits()));
+    }
+    #[test]
+    fn truncated_input_fails() {
+        truncated_fails(&chunk());
+        truncated_fails(&entity());
+        truncated_fails(&small());
+    }
+    #[test]
+    fn corrupt_input_fails() {
+        let bytes = encode(&chunk());
+        let mut magic = bytes.clone();
+        magic[0] = b'X';
+        assert!(decode::<Chunk>(&magic).is_err());
+        let mut version = bytes.clone();
+        version[4] = FORMAT_VERSION + 1;
+        assert!(decode::<Chunk>(&version).is_err());
+        // a tile count far beyond the data is refused, not allocated
+        let mut w = Writer::new();
+        w.varint(chunk().tiles.len() as u64);
+        let count = 5 + 8 + 1 + 32 + 8 + 16;
+        let mut huge = Writer::new();
+        huge.varint(1 << 40);
+        let mut large = bytes[..count].to_vec();
+        large.extend_from_slice(&huge.bytes);
+        large.extend_from_slice(&bytes[count + w.bytes.len()..]);
+        assert_eq!(
+            decode::<Chunk>(&large).err(),
+            Some("unexpected end of data".to_string())
+        );
+        let mut entity = encode(&entity());
+        // the entity type tag follows coords, velocity, heading and target
+        entity[8 + 8 + 4 + 9 + 1 + 16] = 9;
+        assert!(decode::<Entity>(&entity).is_err());
+        assert!(decode::<WorldConfig>(&[0xff; 64]).is_err());
+    }
+    #[test]
+    fn corrupt_length_fails() {
+        // a length near usize::MAX must not overflow the bounds check
+        let mut w = Writer::new();
+        w.varint(u64::MAX);
+        w.u8(b'a');
+        let mut r = Reader::from(&w.bytes);
+        assert_eq!(r.str().err(), Some("unexpected end of data".to_string()));
+        let mut r = Reader::from(&w.bytes);
+        r.u8().unwrap();
+        assert!(r.take(usize::MAX).is_err());
+    }
+}
@@ -0,0 +1,295 @@
+use crate::height::Octave;
+use crate::worldgen::TileType;
+use std::fs;
+use std::mem::size_of;
+use std::ops::Range;
+
+// tiles a world holds at most, 32768 a side
+const MAX_TILES: usize = 1 << 30;
+// bytes a tile takes in the terrain layers: height, temperature, moisture and type
+pub const TERRAIN_BYTES: usize = 3 * size_of::<f32>() + size_of::<TileType>();
+
+// every key `set` takes, in the order of the fields
+pub const KEYS: [&str; 19] = [
+    "world_size",
+    "chunk_size",
+    "tile_size",
+    "noise_scale",
+    "octaves",
+    "jitter",
+    "plates",
+    "erosion_droplets",
+    "thermal_iterations",
+    "talus",
+    "sea_level",
+    "river_threshold",
+    "vicinity_dist",
+    "memory_budget",
+    "page_dir",
+    "region_size",
+    "window_width",
+    "window_height",
+    "camera_step",
+];
+
+#[derive(Clone)]
+pub struct WorldConfig {
+    pub world_size: u32,
+    pub chunk_size: u32,
+    pub tile_size: u32,
+    pub noise_scale: f64,
+    pub octaves: Vec<Octave>,
+    pub jitter: f32,
+    pub plates: u32,
+    pub erosion_droplets: u32,
+    pub thermal_iterations: u32,
+    pub talus: f32,
+    pub sea_level: f32,
+    pub river_threshold: u32,
+    pub vicinity_dist: i32,
+    // megabytes of memory for the world, half of it bounds the terrain blocks
+    // and half the chunks kept in memory
+    pub memory_budget: usize,
+    pub page_dir: String,
+    pub region_size: usize,
+    pub window_width: u32,
+    pub window_height: u32,
+    pub camera_step: f32,
+}
+impl Default for WorldConfig {
+    fn default() -> WorldConfig {
+        WorldConfig::new()
+    }
+}
+impl WorldConfig {
+    pub fn new() -> WorldConfig {
+        WorldConfig {
+            world_size: 16,
+            chunk_size: 128,
+            tile_size: 1,
+            noise_scale: 64.0,
+            octaves: Octave::defaults(),
+            jitter: 1.0,
+            plates: 0,
+            erosion_droplets: 50_000,
+            thermal_iterations: 4,
+            talus: 0.6,
+            sea_level: 0.0,
+            river_threshold: 500,
+            vicinity_dist: 4,
+            memory_budget: 512,
+            page_dir: String::new(),
+            region_size: 8,
+            window_width: 1240,
+            window_height: 760,
+            camera_step: 32.0,
+        }
+    }
+    // amount of chunks pushed to the renderer per main loop iteration
+    pub fn partition_size(&self) -> usize {
+        ((self.world_size as usize * self.world_size as usize) / 16).max(1)
+    }
+    // chunk indices of the given partition, rounded up so the partitions
+    // together cover every chunk and the last one may come out shorter
+    pub fn partition(&self, partition: usize) -> Range<usize> {
+        let chunk_count = self.world_size as usize * self.world_size as usize;
+        let per = chunk_count.div_ceil(self.partition_size());
+        let start = (per * partition).min(chunk_count);
+        start..(start + per).min(chunk_count)
+    }
+    // dashes in a key are read as underscores
+    pub fn is_key(key: &str) -> bool {
+        KEYS.contains(&key.trim().replace('-', "_").as_str())
+    }
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let key = key.trim().replace('-', "_");
+        let value = value.trim();
+        match key.as_str() {
+            "world_size" => self.world_size = parse(&key, value)?,
+            "chunk_size" => self.chunk_size = parse(&key, value)?,
+            "tile_size" => self.tile_size = parse(&key, value)?,
+            "noise_scale" => self.noise_scale = parse(&key, value)?,
+            "octaves" => {
+                self.octaves = value
+                    .split(';')
+                    .filter(|o| !o.trim().is_empty())
+                    .map(Octave::parse)
+                    .collect::<Result<Vec<Octave>, String>>()?
+            }
+            "jitter" => self.jitter = parse(&key, value)?,
+            "plates" => self.plates = parse(&key, value)?,
+            "erosion_droplets" => self.erosion_droplets = parse(&key, value)?,
+            "thermal_iterations" => self.thermal_iterations = parse(&key, value)?,
+            "talus" => self.talus = parse(&key, value)?,
+            "sea_level" => self.sea_level = parse(&key, value)?,
+            "river_threshold" => self.river_threshold = parse(&key, value)?,
+            "vicinity_dist" => self.vicinity_dist = parse(&key, value)?,
+            "memory_budget" => self.memory_budget = parse(&key, value)?,
+            "page_dir" => self.page_dir = value.to_string(),
+            "region_size" => self.region_size = parse(&key, value)?,
+            "window_width" => self.window_width = parse(&key, value)?,
+            "window_height" => self.window_height = parse(&key, value)?,
+            "camera_step" => self.camera_step = parse(&key, value)?,
+            _ => return Err(format!("unknown config key '{}'", key)),
+        }
+        Ok(())
+    }
+    // catches values the generator cannot work with
+    pub fn validate(&self) -> Result<(), String> {
+        if self.world_size == 0 {
+            return Err("world_size must be at least 1".to_string());
+        }
+        if self.chunk_size == 0 {
+            return Err("chunk_size must be at least 1".to_string());
+        }
+        if self.tile_size == 0 {
+            return Err("tile_size must be at least 1".to_string());
+        }
+        // the world side in pixels has to fit the camera's i32 coordinates
+        let side = self
+            .world_size
+            .checked_mul(self.chunk_size)
+            .filter(|side| {
+                side.checked_mul(self.tile_size)
+                    .is_some_and(|px| px <= i32::MAX as u32)
+            })
+            .ok_or("world_size * chunk_size * tile_size is too large")?;
+        if (side as usize)
+            .checked_mul(side as usize)
+            .is_none_or(|tiles| tiles > MAX_TILES)
+        {
+            return Err(format!(
+                "world_size * chunk_size must be at most {} tiles a side",
+                (MAX_TILES as f64).sqrt() as usize
+            ));
+        }
+        // written so NaN fails as well
+        if !(self.noise_scale > 0.0 && self.noise_scale.is_finite()) {
+            return Err("noise_scale must be positive".to_string());
+        }
+        if self.octaves.is_empty() {
+            return Err("at least one octave is needed".to_string());
+        }
+        for octave in &self.octaves {
+            if !(octave.frequency > 0.0 && octave.frequency.is_finite()) {
+                return Err("octave frequencies must be positive".to_string());
+            }
+            if !octave.amplitude.is_finite() {
+                return Err("octave amplitudes must be numbers".to_string());
+            }
+            if let Some(warp) = &octave.warp {
+                if !(warp.frequency > 0.0
+                    && warp.frequency.is_finite()
+                    && warp.amplitude.is_finite())
+                {
+                    return Err("warp frequencies must be positive, amplitudes numbers".to_string());
+                }
+            }
+        }
+        if !(self.jitter >= 0.0 && self.jitter.is_finite()) {
+            return Err("jitter cannot be negative".to_string());
+        }
+        if !(self.talus >= 0.0 && self.talus.is_finite()) {
+            return Err("talus cannot be negative".to_string());
+        }
+        if !self.sea_level.is_finite() {
+            return Err("sea_level must be a number".to_string());
+        }
+        if !self.camera_step.is_finite() {
+            return Err("camera_step must be a number".to_string());
+        }
+        if self.vicinity_dist < 0 {
+            return Err("vicinity_dist cannot be negative".to_string());
+        }
+        if self.memory_budget == 0 {
+            return Err("memory_budget must be at least 1".to_string());
+        }
+        if self.region_size == 0 || self.region_size > 255 {
+            return Err("region_size must be between 1 and 255".to_string());
+        }
+        Ok(())
+    }
+    // reads `key = value` lines, `#` starts a comment
+    pub fn from_file(path: &str) -> Result<WorldConfig, String> {
+        let contents =
+            fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+        let mut config = WorldConfig::new();
+        for (n, line) in contents.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            match line.split_once('=') {
+                Some((key, value)) => config
+                    .set(key, value)
+                    .map_err(|e| format!("{}:{}: {}", path, n + 1, e))?,
+                None => return Err(format!("{}:{}: expected key = value", path, n + 1)),
+            }
+        }
+        Ok(config)
+    }
+    // `--config <file>` loads a base config, `--<key> <value>` overrides single keys
+    pub fn from_args(args: &[String]) -> Result<WorldConfig, String> {
+        let mut config = match args.iter().position(|a| a == "--config") {
+            Some(i) => match args.get(i + 1) {
+                Some(path) => WorldConfig::from_file(path)?,
+                None => return Err("missing value for --config".to_string()),
+            },
+            None => WorldConfig::new(),
+        };
+        let mut i = 0;
+        while i < args.len() {
+            let flag = match args[i].strip_prefix("--") {
+                Some(flag) => flag,
+                None => return Err(format!("unexpected argument '{}'", args[i])),
+            };
+            let value = match args.get(i + 1) {
+                Some(value) => value,
+                None => return Err(format!("missing value for --{}", flag)),
+            };
+            if flag != "config" {
+                config.set(flag, value)?;
+            }
+            i += 2;
+        }
+        config.validate()?;
+        Ok(config)
+    }
+}
+fn parse<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value '{}' for {}", value, key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_key_can_be_set() {
+        for key in KEYS {
+            assert!(WorldConfig::is_key(key));
+            let value = if key == "octaves" { "perlin 1 1" } else { "1" };
+            assert!(WorldConfig::new().set(key, value).is_ok(), "{}", key);
+        }
+        assert!(WorldConfig::is_key("sea-level"));
+        assert!(!WorldConfig::is_key("help"));
+        assert!(WorldConfig::new().set("help", "1").is_err());
+    }
+    #[test]
+    fn large_worlds_fit_any_budget() {
+        let mut config = WorldConfig::new();
+        // 8192 * 8192 tiles would take 832 MB of terrain in one piece
+        config.world_size = 64;
+        config.memory_budget = 1;
+        assert!(config.validate().is_ok());
+        config.world_size = 256;
+        assert!(config.validate().is_ok());
+        config.world_size = 257;
+        assert!(config.validate().is_err());
+        config.world_size = 64;
+        config.memory_budget = 0;
+        assert!(config.validate().is_err());
+    }
+}
@@ -0,0 +1,430 @@
+use crate::bitmap::BuildingType;
+use crate::camera::Camera;
+use crate::sim::Simulation;
+use crossbeam::channel::{unbounded, Receiver};
+use std::io::{self, BufRead};
+use std::thread;
+
+// steps one `step` command resolves at most, a typo must not hang the run
+const MAX_STEPS: u64 = 100_000;
+// steps per resolve at most, for the same reason
+const MAX_SPEED: i32 = 1000;
+
+pub const HELP: &str = "commands:
+  pause                 stop resolving steps
+  resume                continue resolving steps
+  step <n>              resolve n steps right away, also when paused, up to
+                        100000 and never past the step limit
+  speed <n>             steps per resolve, up to 1000
+  goto <x> <y>          move the camera to a tile
+  world <n>             watch another world
+  inspect chunk <i>     chunk position, terrain and population
+  inspect entity <id>   an entity's position, stats and state
+  spawn [<x> <y>]       put a human on a tile, a random dry one by default
+  walk <id> <x> <y>     send an entity to a tile, by road when it is far
+  build <type> <x> <y>  plan a smallhouse, longhouse or storehouse near a tile
+  settlements           one line per settlement of the watched world
+  roads                 one line per road of the watched world
+  factions              territory, treasury and relations of every faction
+  save                  write every world to the save path
+  stats                 one line per world
+  help                  print this help
+  quit                  stop the simulation";
+
+// commands read from stdin, one per line
+pub enum Command {
+    Pause,
+    Resume,
+    Step(u64),
+    Speed(i32),
+    Goto(f32, f32),
+    World(usize),
+    InspectChunk(usize),
+    InspectEntity(usize),
+    Spawn(Option<(usize, usize)>),
+    Walk(usize, usize, usize),
+    Build(BuildingType, usize, usize),
+    Settlements,
+    Roads,
+    Factions,
+    Save,
+    Stats,
+    Help,
+    Quit,
+}
+impl Command {
+    pub fn parse(line: &str) -> Result<Command, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let command = match words.as_slice() {
+            ["pause"] => Command::Pause,
+            ["resume"] => Command::Resume,
+            ["step"] => Command::Step(1),
+            ["step", n] => {
+                let n = parse(n)?;
+                if n > MAX_STEPS {
+                    return Err(format!("step takes at most {} steps at once", MAX_STEPS));
+                }
+                Command::Step(n)
+            }
+            ["speed", n] => {
+                let speed = parse(n)?;
+                if !(1..=MAX_SPEED).contains(&speed) {
+                    return Err(format!("speed must be between 1 and {}", MAX_SPEED));
+                }
+                Command::Speed(speed)
+            }
+            ["goto", x, y] => {
+                let (x, y): (f32, f32) = (parse(x)?, parse(y)?);
+                if !x.is_finite() || !y.is_finite() {
+                    return Err("goto takes finite coordinates".to_string());
+                }
+                Command::Goto(x, y)
+            }
+            ["world", n] => Command::World(parse(n)?),
+            ["inspect", "chunk", i] => Command::InspectChunk(parse(i)?),
+            ["inspect", "entity", id] => Command::InspectEntity(parse(id)?),
+            ["spawn"] => Command::Spawn(None),
+            ["spawn", x, y] => Command::Spawn(Some((parse(x)?, parse(y)?))),
+            ["walk", id, x, y] => Command::Walk(parse(id)?, parse(x)?, parse(y)?),
+            ["build", name, x, y] => Command::Build(
+                BuildingType::parse(name).ok_or(format!("unknown building '{}'", name))?,
+                parse(x)?,
+                parse(y)?,
+            ),
+            ["settlements"] => Command::Settlements,
+            ["roads"] => Command::Roads,
+            ["factions"] => Command::Factions,
+            ["save"] => Command::Save,
+            ["stats"] => Command::Stats,
+            ["help"] => Command::Help,
+            ["quit"] | ["exit"] => Command::Quit,
+            [] => return Err("empty command".to_string()),
+            _ => return Err(format!("unknown command '{}', try help", line.trim())),
+        };
+        Ok(command)
+    }
+}
+fn parse<T: std::str::FromStr>(word: &str) -> Result<T, String> {
+    word.parse()
+        .map_err(|_| format!("invalid number '{}'", word))
+}
+// reads stdin on its own thread, lines that do not parse are answered there.
+// the channel closes when stdin does
+pub fn listen() -> Receiver<Command> {
+    let (tx, rx) = unbounded();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            match Command::parse(&line) {
+                Ok(command) => {
+                    if tx.send(command).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => println!("error {}", e),
+            }
+        }
+    });
+    rx
+}
+// runs a command against the simulation and returns the lines to print, every
+// line starts with the kind of answer so scripts can pick them apart
+pub fn execute(sim: &mut Simulation, camera: &mut Camera, command: Command) -> Vec<String> {
+    match execute_inner(sim, camera, command) {
+        Ok(lines) => lines,
+        Err(e) => vec![format!("error {}", e)],
+    }
+}
+fn execute_inner(
+    sim: &mut Simulation,
+    camera: &mut Camera,
+    command: Command,
+) -> Result<Vec<String>, String> {
+    let lines = match command {
+        Command::Pause => {
+            sim.paused = true;
+            vec!["ok paused".to_string()]
+        }
+        Command::Resume => {
+            sim.paused = false;
+            vec!["ok resumed".to_string()]
+        }
+        Command::Step(n) => {
+            // like the main loop, stepping stops at the step limit
+            for _ in 0..n {
+                if sim.finished() {
+                    break;
+                }
+                sim.resolve();
+            }
+            vec![format!("ok step {}", sim.worlds[sim.vic_world].step)]
+        }
+        Command::Speed(n) => {
+            sim.step_increment = n;
+            vec![format!("ok speed {}", n)]
+        }
+        Command::Goto(x, y) => {
+            let config = &sim.worlds[sim.vic_world].config;
+            let scale = (config.tile_size as i32 * camera.zoom) as f32;
+            camera.coords.x = -x * scale;
+            camera.coords.y = -y * scale;
+            camera.tick(config.chunk_size);
+            vec![format!("ok goto {} {}", x, y)]
+        }
+        Command::World(n) => {
+            if n >= sim.worlds.len() {
+                return Err(format!("no world {}, there are {}", n, sim.worlds.len()));
+            }
+            sim.vic_world = n;
+            sim.partition = 0;
+            vec![format!("ok world {}", n)]
+        }
+        Command::InspectChunk(i) => {
+            let world = &sim.worlds[sim.vic_world];
+            if i >= world.chunk_count() {
+                return Err(format!("no chunk {}", i));
+            }
+            let loaded = world.store.is_loaded(i);
+            let modified = world.store.is_modified(i);
+            world
+                .store
+                .with_chunk(i, world.seed, &world.config, &world.terrain, |chunk| {
+                    let land = chunk.tiles.iter().filter(|t| !t.ttype.is_water()).count();
+                    let height = chunk.tiles.iter().map(|t| t.height).sum::<f32>()
+                        / chunk.tiles.len().max(1) as f32;
+                    let designed = chunk.tiles.iter().filter(|t| t.designed.is_some()).count();
+                    let mut lines = vec![format!(
+                        "chunk {} x {} y {} loaded {} modified {} land {} height {:.2} designed {} entities {}",
+                        i,
+                        chunk.coords.x,
+                        chunk.coords.y,
+                        loaded,
+                        modified,
+                        land,
+                        height,
+                        designed,
+                        chunk.entities.len()
+                    )];
+                    for e in &chunk.entities {
+                        lines.push(format!(
+                            "entity {} name {} x {} y {}",
+                            e.index, e.name, e.coords.x, e.coords.y
+                        ));
+                    }
+                    lines
+                })
+        }
+        Command::InspectEntity(id) => match sim.worlds[sim.vic_world].find_entity(id) {
+            Some((chunk, e)) => vec![format!(
+                "entity {} name {} chunk {} x {} y {} faction {:?} health {}/{} hunger {} strength {} intelligence {} agility {} status {:?} target {} waypoints {} task {}",
+                e.index,
+                e.name,
+                chunk,
+                e.coords.x,
+                e.coords.y,
+                e.alignment.faction,
+                e.stats.health,
+                e.stats.max_health,
+                e.stats.hunger,
+                e.stats.strength,
+                e.stats.intelligence,
+                e.stats.agility,
+                e.status,
+                match &e.target {
+                    Some(t) => format!("{} {}", t.x, t.y),
+                    None => "none".to_string(),
+                },
+                e.path.len(),
+                match &e.job {
+                    Some(job) => format!("{:?} {}", job.kind, job.progress),
+                    None => "none".to_string(),
+                }
+            )],
+            None => return Err(format!("no entity {}", id)),
+        },
+        Command::Spawn(at) => {
+            let world = &mut sim.worlds[sim.vic_world];
+            let (x, y) = match at {
+                Some(at) => at,
+                None => world.spawn_tile().ok_or("no dry tile found")?,
+            };
+            let id = world.spawn(x, y)?;
+            vec![format!("ok spawned {} at {} {}", id, x, y)]
+        }
+        Command::Walk(id, x, y) => {
+            let length = sim.worlds[sim.vic_world].walk(id, x, y)?;
+            vec![format!("ok walking {} to {} {} path {}", id, x, y, length)]
+        }
+        Command::Build(btype, x, y) => {
+            let (x, y) = sim.worlds[sim.vic_world].plan(&btype, x, y)?;
+            vec![format!("ok planned {:?} at {} {}", btype, x, y)]
+        }
+        Command::Settlements => sim.worlds[sim.vic_world]
+            .settlements
+            .iter()
+            .map(|s| {
+                format!(
+                    "settlement {} {} faction {:?} at {} {} population {} buildings {} growth {:?}",
+                    s.id,
+                    s.name,
+                    s.faction,
+                    s.centre.0,
+                    s.centre.1,
+                    s.population,
+                    s.buildings.len(),
+                    s.growth
+                )
+            })
+            .collect(),
+        Command::Roads => sim.worlds[sim.vic_world]
+            .roads
+            .roads
+            .iter()
+            .enumerate()
+            .map(|(i, r)| {
+                format!(
+                    "road {} from {} to {} length {:.1} tiles {}",
+                    i,
+                    r.from,
+                    r.to,
+                    r.length,
+                    r.tiles.len()
+                )
+            })
+            .collect(),
+        Command::Factions => {
+            let world = &sim.worlds[sim.vic_world];
+            let mut lines = vec![];
+            for state in &world.factions {
+                lines.push(format!(
+                    "faction {:?} chunks {} capital {} treasury {}",
+                    state.faction,
+                    state.territory.len(),
+                    state
+                        .capital
+                        .map_or("none".to_string(), |id| world.settlements[id].name.clone()),
+                    state.treasury
+                ));
+                for other in &world.factions {
+                    if other.faction != state.faction {
+                        lines.push(format!(
+                            "relation {:?} {:?} {:?} opinion {}",
+                            state.faction,
+                            other.faction,
+                            state.relation(&other.faction),
+                            state.opinion(&other.faction)
+                        ));
+                    }
+                }
+            }
+            lines
+        }
+        Command::Save => {
+            sim.save()?;
+            (0..sim.worlds.len())
+                .map(|i| format!("ok saved {}", sim.save_path_for(i)))
+                .collect()
+        }
+        Command::Stats => sim
+            .worlds
+            .iter()
+            .enumerate()
+            .map(|(i, w)| {
+                format!(
+                    "stats world {} seed {} step {} population {} loaded {} modified {} speed {} paused {}",
+                    i,
+                    w.seed,
+                    w.step,
+                    w.population(),
+                    w.store.loaded(),
+                    w.store.modified().len(),
+                    sim.step_increment,
+                    sim.paused
+                )
+            })
+            .collect(),
+        Command::Help => HELP.lines().map(|l| l.to_string()).collect(),
+        Command::Quit => vec!["ok quit".to_string()],
+    };
+    Ok(lines)
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(line: &str) -> String {
+        match Command::parse(line) {
+            Ok(_) => panic!("'{}' parsed", line),
+            Err(e) => e,
+        }
+    }
+    #[test]
+    fn parses_every_command() {
+        let parse = |line: &str| Command::parse(line).unwrap();
+        assert!(matches!(parse("pause"), Command::Pause));
+        assert!(matches!(parse("resume"), Command::Resume));
+        assert!(matches!(parse("step"), Command::Step(1)));
+        assert!(matches!(parse("  step   25 "), Command::Step(25)));
+        assert!(matches!(parse("step 100000"), Command::Step(MAX_STEPS)));
+        assert!(matches!(parse("speed 4"), Command::Speed(4)));
+        assert!(matches!(parse("speed 1000"), Command::Speed(MAX_SPEED)));
+        assert!(matches!(parse("goto 1.5 -2"), Command::Goto(x, y) if x == 1.5 && y == -2.0));
+        assert!(matches!(parse("world 2"), Command::World(2)));
+        assert!(matches!(parse("inspect chunk 3"), Command::InspectChunk(3)));
+        assert!(matches!(
+            parse("inspect entity 9"),
+            Command::InspectEntity(9)
+        ));
+        assert!(matches!(parse("spawn"), Command::Spawn(None)));
+        assert!(matches!(parse("spawn 4 5"), Command::Spawn(Some((4, 5)))));
+        assert!(matches!(parse("walk 7 8 9"), Command::Walk(7, 8, 9)));
+        assert!(matches!(
+            parse("build longhouse 10 11"),
+            Command::Build(BuildingType::LongHouse, 10, 11)
+        ));
+        assert!(matches!(parse("settlements"), Command::Settlements));
+        assert!(matches!(parse("roads"), Command::Roads));
+        assert!(matches!(parse("factions"), Command::Factions));
+        assert!(matches!(parse("save"), Command::Save));
+        assert!(matches!(parse("stats"), Command::Stats));
+        assert!(matches!(parse("help"), Command::Help));
+        assert!(matches!(parse("quit"), Command::Quit));
+        assert!(matches!(parse("exit"), Command::Quit));
+    }
+    #[test]
+    fn bad_commands_are_explained() {
+        assert_eq!(error(""), "empty command");
+        assert_eq!(error("  jump 3 "), "unknown command 'jump 3', try help");
+        assert_eq!(error("pause now"), "unknown command 'pause now', try help");
+        assert_eq!(
+            error("inspect tile 3"),
+            "unknown command 'inspect tile 3', try help"
+        );
+        assert_eq!(error("step x"), "invalid number 'x'");
+        assert_eq!(error("step -1"), "invalid number '-1'");
+        assert_eq!(
+            error("step 100001"),
+            "step takes at most 100000 steps at once"
+        );
+        assert_eq!(error("speed 0"), "speed must be between 1 and 1000");
+        assert_eq!(error("speed 1001"), "speed must be between 1 and 1000");
+        assert_eq!(error("goto 1 y"), "invalid number 'y'");
+        assert_eq!(error("goto NaN 2"), "goto takes finite coordinates");
+        assert_eq!(error("goto 1 inf"), "goto takes finite coordinates");
+        assert_eq!(error("world -1"), "invalid number '-1'");
+        assert_eq!(error("inspect chunk a"), "invalid number 'a'");
+        assert_eq!(error("inspect entity 1.5"), "invalid number '1.5'");
+        assert_eq!(error("spawn 4"), "unknown command 'spawn 4', try help");
+        assert_eq!(error("spawn 4 -5"), "invalid number '-5'");
+        assert_eq!(error("walk 1 2"), "unknown command 'walk 1 2', try help");
+        assert_eq!(error("walk a 2 3"), "invalid number 'a'");
+        assert_eq!(error("build castle 1 2"), "unknown building 'castle'");
+        assert_eq!(error("build storehouse 1 b"), "invalid number 'b'");
+    }
+}
@@ -0,0 +1,289 @@
+use crate::bitmap::{Bitmap, BuildingType, BUILDINGS};
+use crate::config::WorldConfig;
+use crate::math::{chunk_index, tile_in_chunk, tile_to_chunk};
+use crate::terrain::Terrain;
+use crate::worldgen::{TileType, World};
+use std::collections::HashMap;
+
+// height difference allowed between the lowest and highest tile of a site
+const FLATNESS: f32 = 2.0;
+// how far from the requested spot a site is looked for, in tiles
+const SITE_SEARCH: i64 = 32;
+
+// the tiles a building is made of, row by row. None leaves a tile as it is
+#[derive(Clone)]
+pub struct Blueprint {
+    pub width: usize,
+    pub height: usize,
+    pub tiles: Vec<Option<TileType>>,
+}
+impl Blueprint {
+    // `#` becomes a wall and `x` a floor. a gap in the outline, drawn as a
+    // space or a floor, becomes a door
+    pub fn from(bitmap: &Bitmap) -> Blueprint {
+        let grid = bitmap.grid();
+        let height = grid.len();
+        let width = grid.iter().map(|row| row.len()).max().unwrap_or(0);
+        let mut tiles = vec![None; width * height];
+        for (y, row) in grid.iter().enumerate() {
+            for (x, c) in row.iter().enumerate() {
+                let edge = x == 0 || y == 0 || x == width - 1 || y == height - 1;
+                tiles[y * width + x] = match c {
+                    '#' => Some(TileType::WoodenWall),
+                    'x' | ' ' if edge => Some(TileType::WoodenDoor),
+                    'x' => Some(TileType::WoodenFloor),
+                    _ => None,
+                };
+            }
+        }
+        Blueprint {
+            width,
+            height,
+            tiles,
+        }
+    }
+    // offsets from the top left corner and what goes there
+    pub fn cells(&self) -> impl Iterator<Item = (i64, i64, &TileType)> {
+        self.tiles.iter().enumerate().filter_map(|(i, t)| {
+            t.as_ref()
+                .map(|t| ((i % self.width) as i64, (i / self.width) as i64, t))
+        })
+    }
+}
+// the buildings that can be put up, the ones in `BUILDINGS` to begin with
+pub struct Blueprints {
+    pub map: HashMap<BuildingType, Blueprint>,
+}
+impl Default for Blueprints {
+    fn default() -> Blueprints {
+        Blueprints::new()
+    }
+}
+impl Blueprints {
+    pub fn new() -> Blueprints {
+        let mut blueprints = Blueprints {
+            map: HashMap::new(),
+        };
+        for (btype, bitmap) in BUILDINGS.iter() {
+            blueprints.register(*btype, bitmap);
+        }
+        blueprints
+    }
+    // adds a building type or replaces its blueprint
+    pub fn register(&mut self, btype: BuildingType, bitmap: &Bitmap) {
+        self.map.insert(btype, Blueprint::from(bitmap));
+    }
+    pub fn get(&self, btype: &BuildingType) -> Option<&Blueprint> {
+        self.map.get(btype)
+    }
+}
+// wood it takes to build a tile
+pub fn materials(ttype: &TileType) -> i32 {
+    match ttype {
+        TileType::WoodenWall => 2,
+        TileType::WoodenFloor | TileType::WoodenDoor => 1,
+        _ => 0,
+    }
+}
+// tile types that can be built on, roads are kept clear
+fn buildable(ttype: &TileType) -> bool {
+    ttype.is_passable()
+        && !matches!(
+            ttype,
+            TileType::WoodenFloor
+                | TileType::WoodenDoor
+                | TileType::Road
+                | TileType::Bridge
+                | TileType::Glacier
+                | TileType::Marsh
+        )
+}
+// the chunk of a site with its top left corner at `corner`, if the whole site
+// is inside the world, on one chunk, dry, flat and not built on yet
+pub fn fits(
+    terrain: &Terrain,
+    config: &WorldConfig,
+    blueprint: &Blueprint,
+    corner: (i64, i64),
+) -> Option<usize> {
+    let chunk = tile_to_chunk(corner, config.chunk_size);
+    let far = (
+        corner.0 + blueprint.width as i64 - 1,
+        corner.1 + blueprint.height as i64 - 1,
+    );
+    if tile_to_chunk(far, config.chunk_size) != chunk {
+        return None;
+    }
+    let index = chunk_index(chunk, config.world_size)?;
+    let (mut low, mut high) = (f32::MAX, f32::MIN);
+    for (dx, dy, _) in blueprint.cells() {
+        let (x, y) = ((corner.0 + dx) as usize, (corner.1 + dy) as usize);
+        if !buildable(&terrain.ttype(x, y)) {
+            return None;
+        }
+        let height = terrain.height(x, y);
+        low = low.min(height);
+        high = high.max(height);
+    }
+    if high - low > FLATNESS {
+        return None;
+    }
+    Some(index)
+}
+// walls, floors and doors of a blueprint put straight into the terrain, for
+// buildings that stand from the start
+pub fn stamp(terrain: &mut Terrain, blueprint: &Blueprint, corner: (usize, usize)) {
+    for (dx, dy, ttype) in blueprint.cells() {
+        terrain.set_ttype(
+            corner.0 + dx as usize,
+            corner.1 + dy as usize,
+            ttype.clone(),
+        );
+    }
+}
+// top left corners around a tile, ring by ring out to `reach`
+pub fn corners_around(x: usize, y: usize, reach: i64) -> impl Iterator<Item = (i64, i64)> {
+    (0..=reach).flat_map(move |r| {
+        (-r..=r).flat_map(move |dy| {
+            (-r..=r)
+                // only the ring at distance r, the inside was tried before
+                .filter(move |dx| dx.abs() == r || dy.abs() == r)
+                .map(move |dx| (x as i64 + dx, y as i64 + dy))
+                .filter(|(x, y)| *x >= 0 && *y >= 0)
+        })
+    })
+}
+impl World {
+    // like `fits`, and none of the tiles is designed for something else
+    fn site(&mut self, blueprint: &Blueprint, corner: (i64, i64)) -> Option<usize> {
+        let chunk_size = self.config.chunk_size;
+        let index = fits(&self.terrain, &self.config, blueprint, corner)?;
+        let tiles = &self.fetch_chunk(index).tiles;
+        let free = blueprint.cells().all(|(dx, dy, _)| {
+            let (x, y) = tile_in_chunk((corner.0 + dx, corner.1 + dy), chunk_size);
+            tiles[y * chunk_size as usize + x].designed.is_none()
+        });
+        if free {
+            Some(index)
+        } else {
+            None
+        }
+    }
+    // the top left corner of the closest site for a building around a tile
+    pub fn find_site(
+        &mut self,
+        btype: &BuildingType,
+        x: usize,
+        y: usize,
+    ) -> Option<(usize, usize)> {
+        let blueprint = self.blueprints.get(btype)?.clone();
+        corners_around(x, y, SITE_SEARCH)
+            .find(|corner| self.site(&blueprint, *corner).is_some())
+            .map(|(x, y)| (x as usize, y as usize))
+    }
+    // finds a site for a building around a tile and marks its tiles as
+    // designed, builders turn them into the building over time
+    pub fn plan(
+        &mut self,
+        btype: &BuildingType,
+        x: usize,
+        y: usize,
+    ) -> Result<(usize, usize), String> {
+        let blueprint = self
+            .blueprints
+            .get(btype)
+            .ok_or(format!("no blueprint for {:?}", btype))?
+            .clone();
+        let (cx, cy) = self
+            .find_site(btype, x, y)
+            .ok_or(format!("no site for {:?} near {} {}", btype, x, y))?;
+        let chunk_size = self.config.chunk_size;
+        let index = self.site(&blueprint, (cx as i64, cy as i64)).unwrap();
+        let chunk = self.fetch_chunk_mut(index);
+        for (dx, dy, ttype) in blueprint.cells() {
+            let (x, y) = tile_in_chunk((cx as i64 + dx, cy as i64 + dy), chunk_size);
+            chunk.tiles[y * chunk_size as usize + x].designed = Some(ttype.clone());
+        }
+        Ok((cx, cy))
+    }
+    // copies tiles built during the last steps into the world wide terrain and
+    // drops the cached paths through their chunks
+    pub fn settle_built(&mut self) {
+        let built = self.store.collect(|chunk| {
+            chunk
+                .built
+                .iter()
+                .map(|i| {
+                    let tile = &chunk.tiles[*i];
+                    (chunk.index, tile.coords, tile.ttype.clone())
+                })
+                .collect()
+        });
+        if built.is_empty() {
+            return;
+        }
+        self.store.apply(|chunk| {
+            chunk.built.clear();
+            false
+        });
+        for (chunk, coords, ttype) in built {
+            self.terrain
+                .set_ttype(coords.x as usize, coords.y as usize, ttype);
+            self.paths.invalidate(chunk);
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gaps_keep_their_column() {
+        let bitmap = Bitmap::from("## #\n    #x #\n    ####\n");
+        assert_eq!(
+            bitmap.grid(),
+            vec![
+                vec!['#', '#', ' ', '#'],
+                vec!['#', 'x', ' ', '#'],
+                vec!['#', '#', '#', '#'],
+            ]
+        );
+    }
+    #[test]
+    fn gaps_in_the_outline_are_doors() {
+        let blueprint = Blueprint::from(&Bitmap::from("## #\n    #x #\n    ####\n"));
+        assert_eq!((blueprint.width, blueprint.height), (4, 3));
+        let door =
+            |x: usize, y: usize| matches!(blueprint.tiles[y * 4 + x], Some(TileType::WoodenDoor));
+        assert!(door(2, 0));
+        assert!(matches!(blueprint.tiles[5], Some(TileType::WoodenFloor)));
+        assert!(blueprint.tiles[6].is_none());
+        assert!(matches!(blueprint.tiles[0], Some(TileType::WoodenWall)));
+        assert!(!door(2, 2));
+    }
+    #[test]
+    fn gaps_in_side_walls_are_doors() {
+        let bitmap = Bitmap::from("####\n    #xx#\n     xx \n    #xx#\n    ####\n");
+        assert_eq!(bitmap.grid()[2], vec![' ', 'x', 'x', ' ']);
+        let blueprint = Blueprint::from(&bitmap);
+        assert_eq!((blueprint.width, blueprint.height), (4, 5));
+        let door =
+            |x: usize, y: usize| matches!(blueprint.tiles[y * 4 + x], Some(TileType::WoodenDoor));
+        assert!(door(0, 2) && door(3, 2));
+        assert!(!door(0, 1) && !door(3, 3));
+        assert!(matches!(
+            blueprint.tiles[2 * 4 + 1],
+            Some(TileType::WoodenFloor)
+        ));
+    }
+    #[test]
+    fn buildings_have_a_door() {
+        let blueprints = Blueprints::default();
+        for (btype, _) in BUILDINGS.iter() {
+            let blueprint = blueprints.get(btype).unwrap();
+            assert!(blueprint
+                .cells()
+                .any(|(_, _, t)| matches!(t, TileType::WoodenDoor)));
+        }
+    }
+}
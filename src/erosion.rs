@@ -0,0 +1,209 @@
+use crate::height::HeightMap;
+use rand::Rng;
+use rayon::prelude::*;
+
+const INERTIA: f32 = 0.05;
+const CAPACITY: f32 = 4.0;
+const MIN_CAPACITY: f32 = 0.01;
+const DEPOSITION: f32 = 0.3;
+const EROSION: f32 = 0.3;
+const EVAPORATION: f32 = 0.02;
+const GRAVITY: f32 = 4.0;
+const LIFETIME: u32 = 30;
+const THERMAL_RATE: f32 = 0.25;
+
+// height and gradient at a fractional position, bilinear over the four corners
+fn sample(map: &HeightMap, x: f32, y: f32) -> (f32, f32, f32) {
+    let (cx, cy) = (x as usize, y as usize);
+    let (u, v) = (x - cx as f32, y - cy as f32);
+    let nw = map.get(cx, cy);
+    let ne = map.get(cx + 1, cy);
+    let sw = map.get(cx, cy + 1);
+    let se = map.get(cx + 1, cy + 1);
+    let gx = (ne - nw) * (1.0 - v) + (se - sw) * v;
+    let gy = (sw - nw) * (1.0 - u) + (se - ne) * u;
+    let h = nw * (1.0 - u) * (1.0 - v) + ne * u * (1.0 - v) + sw * (1.0 - u) * v + se * u * v;
+    (h, gx, gy)
+}
+fn deposit(map: &mut HeightMap, x: f32, y: f32, amount: f32) {
+    let (cx, cy) = (x as usize, y as usize);
+    let (u, v) = (x - cx as f32, y - cy as f32);
+    let corners = [
+        (cx, cy, (1.0 - u) * (1.0 - v)),
+        (cx + 1, cy, u * (1.0 - v)),
+        (cx, cy + 1, (1.0 - u) * v),
+        (cx + 1, cy + 1, u * v),
+    ];
+    for (px, py, w) in corners {
+        let i = map.index(px, py);
+        map.data[i] += amount * w;
+    }
+}
+// droplet based hydraulic erosion, droplets are simulated one after another so
+// the result only depends on the rng
+pub fn hydraulic<R: Rng>(map: &mut HeightMap, droplets: u32, rng: &mut R) {
+    if map.width < 2 || map.height < 2 {
+        return;
+    }
+    let max_x = (map.width - 1) as f32;
+    let max_y = (map.height - 1) as f32;
+    for _ in 0..droplets {
+        let mut x = rng.gen_range(0.0..max_x);
+        let mut y = rng.gen_range(0.0..max_y);
+        let (mut dx, mut dy) = (0.0f32, 0.0f32);
+        let (mut speed, mut water, mut sediment) = (1.0f32, 1.0f32, 0.0f32);
+        for _ in 0..LIFETIME {
+            let (h, gx, gy) = sample(map, x, y);
+            dx = dx * INERTIA - gx * (1.0 - INERTIA);
+            dy = dy * INERTIA - gy * (1.0 - INERTIA);
+            let len = (dx * dx + dy * dy).sqrt();
+            if len == 0.0 {
+                break;
+            }
+            dx /= len;
+            dy /= len;
+            let (ox, oy) = (x, y);
+            x += dx;
+            y += dy;
+            if x < 0.0 || y < 0.0 || x >= max_x || y >= max_y {
+                break;
+            }
+            let (nh, _, _) = sample(map, x, y);
+            let dh = nh - h;
+            let capacity = (-dh).max(MIN_CAPACITY) * speed * water * CAPACITY;
+            if sediment > capacity || dh > 0.0 {
+                // fill the pit we are climbing out of or drop what we cannot carry
+                let amount = if dh > 0.0 {
+                    dh.min(sediment)
+                } else {
+                    (sediment - capacity) * DEPOSITION
+                };
+                sediment -= amount;
+                deposit(map, ox, oy, amount);
+            } else {
+                let amount = ((capacity - sediment) * EROSION).min(-dh);
+                sediment += amount;
+                deposit(map, ox, oy, -amount);
+            }
+            speed = (speed * speed + dh.abs() * GRAVITY).sqrt();
+            water *= 1.0 - EVAPORATION;
+        }
+    }
+}
+// moves material downhill wherever the slope to a neighbour exceeds `talus`,
+// every iteration reads the previous state so it is order independent
+pub fn thermal(map: &mut HeightMap, iterations: u32, talus: f32) {
+    let (w, h) = (map.width as i64, map.height as i64);
+    for _ in 0..iterations {
+        let old = map.data.clone();
+        map.data.par_iter_mut().enumerate().for_each(|(i, cell)| {
+            let (x, y) = (i as i64 % w, i as i64 / w);
+            let here = old[i];
+            let mut delta = 0.0;
+            for (nx, ny) in [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)] {
+                if nx < 0 || ny < 0 || nx >= w || ny >= h {
+                    continue;
+                }
+                let there = old[(ny * w + nx) as usize];
+                let diff = here - there;
+                if diff > talus {
+                    delta -= (diff - talus) * THERMAL_RATE / 4.0;
+                } else if -diff > talus {
+                    delta += (-diff - talus) * THERMAL_RATE / 4.0;
+                }
+            }
+            *cell = here + delta;
+        });
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::height::{HeightGenerator, Octave};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    // rolling hills 64 tiles a side
+    fn hills() -> HeightMap {
+        let generator = HeightGenerator::from(5, 16.0, &Octave::defaults());
+        let mut map = HeightMap::new(64, 64);
+        for y in 0..64 {
+            for x in 0..64 {
+                map.set(x, y, generator.get(x as f64, y as f64));
+            }
+        }
+        map
+    }
+    // steepest drop between two neighbouring tiles
+    fn steepest(map: &HeightMap) -> f32 {
+        let mut steepest = 0.0f32;
+        for y in 0..map.height {
+            for x in 0..map.width {
+                if x + 1 < map.width {
+                    steepest = steepest.max((map.get(x, y) - map.get(x + 1, y)).abs());
+                }
+                if y + 1 < map.height {
+                    steepest = steepest.max((map.get(x, y) - map.get(x, y + 1)).abs());
+                }
+            }
+        }
+        steepest
+    }
+
+    #[test]
+    fn hydraulic_depends_only_on_the_seed() {
+        let erode = |seed| {
+            let mut map = hills();
+            hydraulic(&mut map, 2000, &mut StdRng::seed_from_u64(seed));
+            map.data
+        };
+        let eroded = erode(1);
+        assert!(eroded.iter().all(|h| h.is_finite()));
+        assert_ne!(eroded, hills().data);
+        assert_eq!(eroded, erode(1));
+        assert_ne!(eroded, erode(2));
+    }
+
+    #[test]
+    fn thermal_is_deterministic_and_keeps_material() {
+        let slump = || {
+            let mut map = hills();
+            thermal(&mut map, 8, 0.05);
+            map.data
+        };
+        let slumped = slump();
+        assert!(slumped.iter().all(|h| h.is_finite()));
+        assert_eq!(slumped, slump());
+        let before: f64 = hills().data.iter().map(|h| *h as f64).sum();
+        let after: f64 = slumped.iter().map(|h| *h as f64).sum();
+        assert!((before - after).abs() < 1e-2);
+    }
+
+    #[test]
+    fn talus_holds_across_chunk_seams() {
+        // a cliff right on the seam between two chunks of 8 tiles
+        let talus = 0.6;
+        let mut map = HeightMap::new(16, 16);
+        for y in 0..16 {
+            for x in 0..16 {
+                map.set(x, y, if x < 8 { 3.0 } else { 0.0 });
+            }
+        }
+        thermal(&mut map, 1000, talus);
+        assert!(steepest(&map) <= talus + 0.01, "{}", steepest(&map));
+        // every row slumped the same, the seam made no difference along it
+        for y in 1..16 {
+            for x in 0..16 {
+                assert!((map.get(x, y) - map.get(x, 0)).abs() < 1e-4);
+            }
+        }
+        // slopes under the talus are left alone
+        let mut ramp = HeightMap::new(16, 16);
+        for i in 0..256 {
+            ramp.data[i] = (i % 16) as f32 * talus * 0.9;
+        }
+        let before = ramp.data.clone();
+        thermal(&mut ramp, 10, talus);
+        assert_eq!(ramp.data, before);
+    }
+}
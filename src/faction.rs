@@ -0,0 +1,533 @@
+use crate::config::WorldConfig;
+use crate::settlement::{Growth, Settlement};
+use crate::worldgen::{chunk_faction, pass_seed, Faction, Status, World};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha12Rng;
+use std::collections::{HashMap, HashSet};
+
+// every faction that can hold land
+const FACTIONS: [Faction; 6] = [
+    Faction::Hiisi,
+    Faction::Virumaa,
+    Faction::Pohjola,
+    Faction::Tapiola,
+    Faction::Kalevala,
+    Faction::Novgorod,
+];
+// opinions start out somewhere in -START_OPINION..=START_OPINION and stay
+// within -MAX_OPINION..=MAX_OPINION
+const START_OPINION: i32 = 20;
+const MAX_OPINION: i32 = 100;
+// at or below WAR two factions go to war and make peace again once it is
+// back up to PEACE, at TRADE they sign a trade pact and at ALLY an alliance
+const WAR: i32 = -50;
+const PEACE: i32 = -20;
+const TRADE: i32 = 25;
+const ALLY: i32 = 60;
+// opinion lost for every fighter where two factions not at war meet, at most
+// MAX_CLASH in one chunk
+const CLASH: i32 = 2;
+const MAX_CLASH: i32 = 20;
+// opinion gained by neighbours at peace that did not clash
+const GOODWILL: i32 = 2;
+// opinion lost towards the enemies of an ally
+const ALLY_GRUDGE: i32 = 4;
+// opinion regained every update of a war, twice as much when a side is broke
+const WEARINESS: i32 = 3;
+// coins in a treasury at first, a coin of tax for every TAXPAYERS residents
+// of the territory, and what every war costs and every trade pact earns in an
+// update
+const START_TREASURY: i32 = 100;
+const TAXPAYERS: usize = 25;
+const WAR_COST: i32 = 50;
+const PACT_INCOME: i32 = 10;
+// residents a faction needs in an unheld chunk to claim it, and how many
+// times the residents of an enemy it needs to take a chunk from it
+const CLAIM: usize = 5;
+const CONQUEST: usize = 2;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Relation {
+    War,
+    Peace,
+    Alliance,
+    TradePact,
+}
+#[derive(Clone)]
+pub struct FactionState {
+    pub faction: Faction,
+    // indices of the chunks the faction holds
+    pub territory: HashSet<usize>,
+    // id of the settlement it is ruled from
+    pub capital: Option<usize>,
+    pub treasury: i32,
+    pub relations: HashMap<Faction, Relation>,
+    // how much it likes every other faction, the same both ways
+    pub opinions: HashMap<Faction, i32>,
+}
+impl FactionState {
+    pub fn relation(&self, other: &Faction) -> Relation {
+        *self.relations.get(other).unwrap_or(&Relation::Peace)
+    }
+    pub fn opinion(&self, other: &Faction) -> i32 {
+        *self.opinions.get(other).unwrap_or(&0)
+    }
+}
+// relation between two factions in a map of both orders, peace when there is
+// none and within a faction
+pub fn relation(
+    relations: &HashMap<(Faction, Faction), Relation>,
+    a: &Faction,
+    b: &Faction,
+) -> Relation {
+    *relations
+        .get(&(a.clone(), b.clone()))
+        .unwrap_or(&Relation::Peace)
+}
+// what a relation becomes at an opinion: wars last until the opinion is back
+// up to PEACE, everything else follows the opinion
+fn next(relation: Relation, opinion: i32) -> Relation {
+    if relation == Relation::War {
+        if opinion >= PEACE {
+            Relation::Peace
+        } else {
+            Relation::War
+        }
+    } else if opinion <= WAR {
+        Relation::War
+    } else if opinion >= ALLY {
+        Relation::Alliance
+    } else if opinion >= TRADE {
+        Relation::TradePact
+    } else {
+        Relation::Peace
+    }
+}
+// the factions living on the generated chunks with the land they live on,
+// ruled from their largest settlement, and at peace with mixed feelings
+pub fn found(seed: u32, config: &WorldConfig, settlements: &[Settlement]) -> Vec<FactionState> {
+    let mut rng = ChaCha12Rng::seed_from_u64(pass_seed(seed, 3));
+    let world_size = config.world_size as i32;
+    let mut factions: Vec<FactionState> = FACTIONS
+        .iter()
+        .map(|faction| FactionState {
+            faction: faction.clone(),
+            territory: HashSet::new(),
+            capital: None,
+            treasury: START_TREASURY,
+            relations: HashMap::new(),
+            opinions: HashMap::new(),
+        })
+        .collect();
+    for i in 0..world_size * world_size {
+        if let Some(faction) = chunk_faction(seed, i % world_size, i / world_size) {
+            let state = factions.iter_mut().find(|s| s.faction == faction).unwrap();
+            state.territory.insert(i as usize);
+        }
+    }
+    factions.retain(|s| !s.territory.is_empty());
+    for i in 0..factions.len() {
+        factions[i].capital = capital(&factions[i].faction, settlements);
+        for j in i + 1..factions.len() {
+            let opinion = rng.gen_range(-START_OPINION..=START_OPINION);
+            let (a, b) = (factions[i].faction.clone(), factions[j].faction.clone());
+            factions[i].opinions.insert(b.clone(), opinion);
+            factions[j].opinions.insert(a.clone(), opinion);
+            factions[i].relations.insert(b, Relation::Peace);
+            factions[j].relations.insert(a, Relation::Peace);
+        }
+    }
+    factions
+}
+// the settlement of a faction with the most residents, then buildings,
+// abandoned ones are not ruled from
+fn capital(faction: &Faction, settlements: &[Settlement]) -> Option<usize> {
+    settlements
+        .iter()
+        .filter(|s| s.faction == *faction && s.growth != Growth::Abandoned)
+        .max_by(|a, b| {
+            (a.population, a.buildings.len(), b.id).cmp(&(b.population, b.buildings.len(), a.id))
+        })
+        .map(|s| s.id)
+}
+// residents and fighters of every faction in a chunk
+type Census = HashMap<Faction, (usize, usize)>;
+impl World {
+    pub fn faction(&self, faction: &Faction) -> Option<&FactionState> {
+        self.factions.iter().find(|s| s.faction == *faction)
+    }
+    // the faction holding a chunk
+    pub fn owner(&self, chunk: usize) -> Option<&Faction> {
+        self.factions
+            .iter()
+            .find(|s| s.territory.contains(&chunk))
+            .map(|s| &s.faction)
+    }
+    // every relation between two factions, in both orders
+    pub fn relations(&self) -> HashMap<(Faction, Faction), Relation> {
+        let mut relations = HashMap::new();
+        for state in &self.factions {
+            for (other, relation) in &state.relations {
+                relations.insert((state.faction.clone(), other.clone()), *relation);
+            }
+        }
+        relations
+    }
+    fn change_opinion(&mut self, a: &Faction, b: &Faction, by: i32) {
+        for (from, to) in [(a, b), (b, a)] {
+            if let Some(state) = self.factions.iter_mut().find(|s| s.faction == *from) {
+                let opinion = state.opinions.entry(to.clone()).or_insert(0);
+                *opinion = (*opinion + by).clamp(-MAX_OPINION, MAX_OPINION);
+            }
+        }
+    }
+    // counts who lives and fights in the loaded chunks and lets the factions
+    // react: clashes sour opinions, quiet neighbours warm to each other and
+    // wars wear the sides down until they make peace. treasuries collect
+    // taxes, pay for wars and earn from trade pacts. a chunk changes hands
+    // when its holder has nobody left there, or when an enemy at war
+    // outnumbers the holder's residents there
+    pub fn resolve_factions(&mut self) {
+        let censuses: Vec<(usize, Census)> = self.store.collect(|chunk| {
+            let mut census = Census::new();
+            for e in &chunk.entities {
+                let count = census.entry(e.alignment.faction.clone()).or_insert((0, 0));
+                count.0 += 1;
+                count.1 += (e.status == Status::Fighting) as usize;
+            }
+            vec![(chunk.index, census)]
+        });
+        let relations = self.relations();
+        let at = |a: &Faction, b: &Faction| relation(&relations, a, b);
+        // taxes, wars and trade
+        for state in &mut self.factions {
+            let residents: usize = censuses
+                .iter()
+                .filter(|(chunk, _)| state.territory.contains(chunk))
+                .filter_map(|(_, census)| census.get(&state.faction))
+                .map(|count| count.0)
+                .sum();
+            let wars = state
+                .relations
+                .values()
+                .filter(|r| **r == Relation::War)
+                .count() as i32;
+            let pacts = state
+                .relations
+                .values()
+                .filter(|r| **r == Relation::TradePact)
+                .count() as i32;
+            state.treasury =
+                (state.treasury + (residents / TAXPAYERS) as i32 + pacts * PACT_INCOME
+                    - wars * WAR_COST)
+                    .max(0);
+        }
+        // clashes where factions meet
+        let mut clashed = HashSet::new();
+        for (_, census) in &censuses {
+            let mut present: Vec<(&Faction, &(usize, usize))> = census.iter().collect();
+            present.sort_by_key(|(f, _)| FACTIONS.iter().position(|x| x == *f));
+            for (i, (a, count_a)) in present.iter().enumerate() {
+                for (b, count_b) in &present[i + 1..] {
+                    if **a == Faction::Empty || **b == Faction::Empty {
+                        continue;
+                    }
+                    let fighters = (count_a.1 + count_b.1) as i32;
+                    if fighters > 0 && at(a, b) != Relation::War {
+                        self.change_opinion(a, b, -(fighters * CLASH).min(MAX_CLASH));
+                        clashed.insert(((*a).clone(), (*b).clone()));
+                        clashed.insert(((*b).clone(), (*a).clone()));
+                    }
+                }
+            }
+        }
+        // neighbours, wars and allies
+        let factions: Vec<Faction> = self.factions.iter().map(|s| s.faction.clone()).collect();
+        for (i, a) in factions.iter().enumerate() {
+            for b in &factions[i + 1..] {
+                match at(a, b) {
+                    Relation::War => {
+                        let broke = [a, b]
+                            .iter()
+                            .any(|f| self.faction(f).is_some_and(|s| s.treasury == 0));
+                        let weariness = if broke { 2 * WEARINESS } else { WEARINESS };
+                        self.change_opinion(a, b, weariness);
+                        // the allies of either side turn against the other
+                        for c in &factions {
+                            if at(a, c) == Relation::Alliance && c != b {
+                                self.change_opinion(c, b, -ALLY_GRUDGE);
+                            }
+                            if at(b, c) == Relation::Alliance && c != a {
+                                self.change_opinion(c, a, -ALLY_GRUDGE);
+                            }
+                        }
+                    }
+                    _ => {
+                        if !clashed.contains(&(a.clone(), b.clone())) && self.borders(a, b) {
+                            self.change_opinion(a, b, GOODWILL);
+                        }
+                    }
+                }
+            }
+        }
+        for state in &mut self.factions {
+            for (other, relation) in state.relations.iter_mut() {
+                let opinion = *state.opinions.get(other).unwrap_or(&0);
+                *relation = next(*relation, opinion);
+            }
+        }
+        self.resolve_borders(&censuses);
+    }
+    // whether the territories of two factions touch
+    fn borders(&self, a: &Faction, b: &Faction) -> bool {
+        let (a, b) = match (self.faction(a), self.faction(b)) {
+            (Some(a), Some(b)) => (a, b),
+            _ => return false,
+        };
+        let world_size = self.config.world_size as usize;
+        a.territory.iter().any(|chunk| {
+            let (x, y) = (chunk % world_size, chunk / world_size);
+            [(1, 0), (-1, 0), (0, 1), (0, -1)].iter().any(|(dx, dy)| {
+                let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+                nx >= 0
+                    && ny >= 0
+                    && nx < world_size as i64
+                    && ny < world_size as i64
+                    && b.territory
+                        .contains(&(ny as usize * world_size + nx as usize))
+            })
+        })
+    }
+    // hands chunks to whoever holds them now and moves capitals that were lost
+    // or abandoned
+    fn resolve_borders(&mut self, censuses: &[(usize, Census)]) {
+        let relations = self.relations();
+        for (chunk, census) in censuses {
+            let strongest = census
+                .iter()
+                .filter(|(f, _)| self.faction(f).is_some())
+                .max_by(|a, b| {
+                    a.1 .0.cmp(&b.1 .0).then_with(|| {
+                        let position = |f: &Faction| FACTIONS.iter().position(|x| x == f);
+                        position(b.0).cmp(&position(a.0))
+                    })
+                })
+                .map(|(f, count)| (f.clone(), count.0));
+            let (taker, residents) = match strongest {
+                Some(strongest) => strongest,
+                None => continue,
+            };
+            let holder = self.owner(*chunk).cloned();
+            let holders = holder
+                .as_ref()
+                .and_then(|h| census.get(h))
+                .map_or(0, |count| count.0);
+            let taken = match &holder {
+                Some(holder) if *holder == taker => false,
+                Some(holder) => {
+                    holders == 0 && residents >= CLAIM
+                        || relation(&relations, holder, &taker) == Relation::War
+                            && residents >= CONQUEST * holders.max(1)
+                }
+                None => residents >= CLAIM,
+            };
+            if !taken {
+                continue;
+            }
+            for state in &mut self.factions {
+                if state.faction == taker {
+                    state.territory.insert(*chunk);
+                } else {
+                    state.territory.remove(chunk);
+                }
+            }
+            for settlement in &mut self.settlements {
+                if settlement.chunk == *chunk {
+                    settlement.faction = taker.clone();
+                }
+            }
+        }
+        for i in 0..self.factions.len() {
+            let faction = self.factions[i].faction.clone();
+            let held = self.factions[i]
+                .capital
+                .is_some_and(|id| capital(&faction, &self.settlements[id..=id]).is_some());
+            if !held {
+                self.factions[i].capital = capital(&faction, &self.settlements);
+            }
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::{Layers, Terrain};
+    use crate::worldgen::Entity;
+
+    // 2 x 2 loaded chunks of 8 tiles with nobody in them, held by Hiisi and
+    // Virumaa
+    fn world(hiisi: &[usize], virumaa: &[usize]) -> World {
+        let mut config = WorldConfig::new();
+        config.world_size = 2;
+        config.chunk_size = 8;
+        let mut world = World::from(Terrain::from(Layers::new(16)), 0, config).unwrap();
+        for chunk in 0..4 {
+            world.fetch_chunk_mut(chunk).entities.clear();
+        }
+        world.factions = [(Faction::Hiisi, hiisi), (Faction::Virumaa, virumaa)]
+            .into_iter()
+            .map(|(faction, territory)| FactionState {
+                faction,
+                territory: territory.iter().cloned().collect(),
+                capital: None,
+                treasury: START_TREASURY,
+                relations: HashMap::new(),
+                opinions: HashMap::new(),
+            })
+            .collect();
+        world
+    }
+    // how Hiisi and Virumaa stand with each other, both ways
+    fn stand(world: &mut World, relation: Relation, opinion: i32) {
+        let (a, b) = (Faction::Hiisi, Faction::Virumaa);
+        for (state, other) in world.factions.iter_mut().zip([b, a]) {
+            state.relations.insert(other.clone(), relation);
+            state.opinions.insert(other, opinion);
+        }
+    }
+    fn settle(world: &mut World, chunk: usize, faction: Faction, count: usize, fighting: bool) {
+        let chunk = world.fetch_chunk_mut(chunk);
+        for _ in 0..count {
+            let mut entity = Entity::new(chunk.entities.len());
+            entity.alignment.faction = faction.clone();
+            if fighting {
+                entity.status = Status::Fighting;
+            }
+            chunk.entities.push(entity);
+        }
+    }
+    fn hiisi(world: &World) -> &FactionState {
+        world.faction(&Faction::Hiisi).unwrap()
+    }
+    fn holder(world: &World, chunk: usize) -> Option<Faction> {
+        world.owner(chunk).cloned()
+    }
+
+    #[test]
+    fn relations_follow_opinion() {
+        assert_eq!(next(Relation::Peace, WAR), Relation::War);
+        assert_eq!(next(Relation::Peace, WAR + 1), Relation::Peace);
+        assert_eq!(next(Relation::War, PEACE - 1), Relation::War);
+        assert_eq!(next(Relation::War, PEACE), Relation::Peace);
+        // peace is made first, pacts come with the next updates
+        assert_eq!(next(Relation::War, ALLY), Relation::Peace);
+        assert_eq!(next(Relation::Peace, TRADE), Relation::TradePact);
+        assert_eq!(next(Relation::TradePact, ALLY), Relation::Alliance);
+        assert_eq!(next(Relation::Alliance, TRADE - 1), Relation::Peace);
+    }
+
+    #[test]
+    fn clashes_sour_and_quiet_borders_warm() {
+        let mut clashing = world(&[0], &[1]);
+        stand(&mut clashing, Relation::Peace, 0);
+        settle(&mut clashing, 0, Faction::Hiisi, 2, true);
+        settle(&mut clashing, 0, Faction::Virumaa, 1, false);
+        clashing.resolve_factions();
+        assert_eq!(hiisi(&clashing).opinion(&Faction::Virumaa), -2 * CLASH);
+        let virumaa = clashing.faction(&Faction::Virumaa).unwrap();
+        assert_eq!(virumaa.opinion(&Faction::Hiisi), -2 * CLASH);
+
+        let mut quiet = world(&[0], &[1]);
+        stand(&mut quiet, Relation::Peace, TRADE - GOODWILL);
+        quiet.resolve_factions();
+        assert_eq!(hiisi(&quiet).opinion(&Faction::Virumaa), TRADE);
+        assert_eq!(
+            hiisi(&quiet).relation(&Faction::Virumaa),
+            Relation::TradePact
+        );
+    }
+
+    #[test]
+    fn wars_cost_and_wear_down() {
+        let mut at_war = world(&[0], &[1]);
+        stand(&mut at_war, Relation::War, PEACE - WEARINESS);
+        settle(&mut at_war, 0, Faction::Hiisi, 2 * TAXPAYERS, true);
+        at_war.resolve_factions();
+        assert_eq!(hiisi(&at_war).treasury, START_TREASURY + 2 - WAR_COST);
+        let virumaa = at_war.faction(&Faction::Virumaa).unwrap();
+        assert_eq!(virumaa.treasury, START_TREASURY - WAR_COST);
+        // fighting in a war does not sour the opinion any further
+        assert_eq!(hiisi(&at_war).opinion(&Faction::Virumaa), PEACE);
+        assert_eq!(hiisi(&at_war).relation(&Faction::Virumaa), Relation::Peace);
+
+        let mut trading = world(&[0], &[1]);
+        stand(&mut trading, Relation::TradePact, TRADE);
+        trading.resolve_factions();
+        assert_eq!(hiisi(&trading).treasury, START_TREASURY + PACT_INCOME);
+    }
+
+    #[test]
+    fn enemies_conquer_at_the_ratio() {
+        for (relation, opinion, invaders, taken) in [
+            (Relation::War, -MAX_OPINION, CONQUEST * 2 - 1, false),
+            (Relation::War, -MAX_OPINION, CONQUEST * 2, true),
+            (Relation::Peace, 0, CONQUEST * 2, false),
+        ] {
+            let mut world = world(&[0], &[1]);
+            stand(&mut world, relation, opinion);
+            settle(&mut world, 0, Faction::Hiisi, 2, false);
+            settle(&mut world, 0, Faction::Virumaa, invaders, false);
+            world.resolve_factions();
+            let expected = if taken {
+                Faction::Virumaa
+            } else {
+                Faction::Hiisi
+            };
+            assert_eq!(holder(&world, 0), Some(expected));
+        }
+    }
+
+    #[test]
+    fn unheld_land_is_claimed() {
+        let mut world = world(&[0], &[1]);
+        stand(&mut world, Relation::Peace, 0);
+        settle(&mut world, 2, Faction::Hiisi, CLAIM - 1, false);
+        world.resolve_factions();
+        assert_eq!(holder(&world, 2), None);
+        settle(&mut world, 2, Faction::Hiisi, 1, false);
+        world.resolve_factions();
+        assert_eq!(holder(&world, 2), Some(Faction::Hiisi));
+        // land its holder left is claimed the same way
+        settle(&mut world, 1, Faction::Hiisi, CLAIM, false);
+        world.resolve_factions();
+        assert_eq!(holder(&world, 1), Some(Faction::Hiisi));
+    }
+
+    #[test]
+    fn capital_moves_when_lost() {
+        let mut world = world(&[0, 2], &[1]);
+        stand(&mut world, Relation::War, -MAX_OPINION);
+        world.settlements = [(0, 10), (2, 5)]
+            .into_iter()
+            .enumerate()
+            .map(|(id, (chunk, population))| Settlement {
+                id,
+                name: String::new(),
+                faction: Faction::Hiisi,
+                centre: (0, 0),
+                chunk,
+                population,
+                buildings: vec![],
+                growth: Growth::Stable,
+            })
+            .collect();
+        world.factions[0].capital = capital(&Faction::Hiisi, &world.settlements);
+        assert_eq!(hiisi(&world).capital, Some(0));
+        settle(&mut world, 0, Faction::Virumaa, CLAIM, false);
+        world.resolve_factions();
+        assert_eq!(world.settlements[0].faction, Faction::Virumaa);
+        assert_eq!(hiisi(&world).capital, Some(1));
+        let virumaa = world.faction(&Faction::Virumaa).unwrap();
+        assert_eq!(virumaa.capital, Some(0));
+    }
+}
@@ -0,0 +1,308 @@
+use crate::tectonics::TectonicMap;
+use noise::core::worley::{distance_functions, worley_2d, ReturnType};
+use noise::permutationtable::PermutationTable;
+use noise::{NoiseFn, Perlin, Simplex, Vector2};
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum NoiseType {
+    Perlin,
+    Simplex,
+    Worley,
+    Ridged,
+    Billow,
+}
+impl NoiseType {
+    pub fn parse(name: &str) -> Result<NoiseType, String> {
+        match name {
+            "perlin" => Ok(NoiseType::Perlin),
+            "simplex" => Ok(NoiseType::Simplex),
+            "worley" => Ok(NoiseType::Worley),
+            "ridged" => Ok(NoiseType::Ridged),
+            "billow" => Ok(NoiseType::Billow),
+            _ => Err(format!("unknown noise type '{}'", name)),
+        }
+    }
+}
+#[derive(Clone, PartialEq, Debug)]
+pub struct Warp {
+    pub frequency: f64,
+    pub amplitude: f64,
+}
+// frequency is relative to the world noise scale, amplitude is in height units
+#[derive(Clone, PartialEq, Debug)]
+pub struct Octave {
+    pub ntype: NoiseType,
+    pub frequency: f64,
+    pub amplitude: f64,
+    pub warp: Option<Warp>,
+}
+impl Octave {
+    pub fn from(ntype: NoiseType, frequency: f64, amplitude: f64, warp: Option<Warp>) -> Octave {
+        Octave {
+            ntype,
+            frequency,
+            amplitude,
+            warp,
+        }
+    }
+    // `<type> <frequency> <amplitude> [warp <frequency> <amplitude>]`
+    pub fn parse(input: &str) -> Result<Octave, String> {
+        let parts: Vec<&str> = input.split_whitespace().collect();
+        let number = |i: usize| -> Result<f64, String> {
+            parts
+                .get(i)
+                .ok_or(format!("octave '{}' is missing a value", input.trim()))?
+                .parse()
+                .map_err(|_| format!("invalid number in octave '{}'", input.trim()))
+        };
+        let warp = match parts.get(3) {
+            Some(&"warp") => Some(Warp {
+                frequency: number(4)?,
+                amplitude: number(5)?,
+            }),
+            Some(other) => return Err(format!("unexpected '{}' in octave", other)),
+            None => None,
+        };
+        // three words, or six with a warp, anything after is a typo
+        if let Some(extra) = parts.get(6) {
+            return Err(format!("unexpected '{}' in octave", extra));
+        }
+        let ntype = NoiseType::parse(parts.first().unwrap_or(&""))?;
+        Ok(Octave::from(ntype, number(1)?, number(2)?, warp))
+    }
+    // the three layers chunks were generated with before octaves were configurable
+    pub fn defaults() -> Vec<Octave> {
+        vec![
+            Octave::from(NoiseType::Perlin, 1.0, 2.0, None),
+            Octave::from(NoiseType::Perlin, 2.0, 0.25, None),
+            Octave::from(NoiseType::Perlin, 0.125, -16.0, None),
+        ]
+    }
+}
+struct Layer {
+    octave: Octave,
+    perlin: Perlin,
+    simplex: Simplex,
+    table: PermutationTable,
+    warp_x: Perlin,
+    warp_y: Perlin,
+}
+impl Layer {
+    fn sample(&self, point: [f64; 2]) -> f64 {
+        match self.octave.ntype {
+            NoiseType::Perlin => self.perlin.get(point),
+            NoiseType::Simplex => self.simplex.get(point),
+            NoiseType::Worley => worley_2d(
+                &self.table,
+                distance_functions::euclidean,
+                ReturnType::Distance,
+                Vector2::from(point),
+            ),
+            NoiseType::Ridged => 1.0 - 2.0 * self.perlin.get(point).abs(),
+            NoiseType::Billow => 2.0 * self.perlin.get(point).abs() - 1.0,
+        }
+    }
+}
+pub struct HeightGenerator {
+    layers: Vec<Layer>,
+    scale: f64,
+    base: Option<TectonicMap>,
+}
+impl HeightGenerator {
+    pub fn from(seed: u32, scale: f64, octaves: &[Octave]) -> HeightGenerator {
+        let layers = octaves
+            .iter()
+            .enumerate()
+            .map(|(i, octave)| {
+                let layer_seed = seed.wrapping_add(i as u32);
+                Layer {
+                    octave: octave.clone(),
+                    perlin: Perlin::new(layer_seed),
+                    simplex: Simplex::new(layer_seed),
+                    table: PermutationTable::new(layer_seed),
+                    warp_x: Perlin::new(layer_seed.wrapping_add(1000)),
+                    warp_y: Perlin::new(layer_seed.wrapping_add(2000)),
+                }
+            })
+            .collect();
+        HeightGenerator {
+            layers,
+            scale,
+            base: None,
+        }
+    }
+    // coarse elevation the octaves are added on top of
+    pub fn with_base(mut self, base: TectonicMap) -> HeightGenerator {
+        self.base = Some(base);
+        self
+    }
+    pub fn get(&self, x: f64, y: f64) -> f32 {
+        let mut height = match &self.base {
+            Some(base) => base.sample(x, y) as f64,
+            None => 0.0,
+        };
+        for layer in &self.layers {
+            let mut px = x / self.scale * layer.octave.frequency + 0.1;
+            let mut py = y / self.scale * layer.octave.frequency + 0.1;
+            if let Some(warp) = &layer.octave.warp {
+                let wx = x / self.scale * warp.frequency + 0.1;
+                let wy = y / self.scale * warp.frequency + 0.1;
+                px += layer.warp_x.get([wx, wy]) * warp.amplitude;
+                py += layer.warp_y.get([wx, wy]) * warp.amplitude;
+            }
+            height += layer.sample([px, py]) * layer.octave.amplitude;
+        }
+        height as f32
+    }
+}
+// heights of the whole world stitched into one row-major grid, used by passes
+// that need to see across chunk borders
+#[derive(Clone)]
+pub struct HeightMap {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<f32>,
+}
+impl HeightMap {
+    pub fn new(width: usize, height: usize) -> HeightMap {
+        HeightMap {
+            width,
+            height,
+            data: vec![0.0; width * height],
+        }
+    }
+    pub fn index(&self, x: usize, y: usize) -> usize {
+        y * self.width + x
+    }
+    pub fn get(&self, x: usize, y: usize) -> f32 {
+        self.data[self.index(x, y)]
+    }
+    pub fn set(&mut self, x: usize, y: usize, value: f32) {
+        let i = self.index(x, y);
+        self.data[i] = value;
+    }
+    pub fn contains(&self, x: i64, y: i64) -> bool {
+        x >= 0 && y >= 0 && (x as usize) < self.width && (y as usize) < self.height
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TYPES: [NoiseType; 5] = [
+        NoiseType::Perlin,
+        NoiseType::Simplex,
+        NoiseType::Worley,
+        NoiseType::Ridged,
+        NoiseType::Billow,
+    ];
+
+    // heights over a 32 x 32 grid of tiles
+    fn sample(generator: &HeightGenerator) -> Vec<f32> {
+        (0..32 * 32)
+            .map(|i| generator.get((i % 32) as f64 * 3.0, (i / 32) as f64 * 3.0))
+            .collect()
+    }
+    fn one(ntype: NoiseType, warp: Option<Warp>) -> HeightGenerator {
+        HeightGenerator::from(7, 64.0, &[Octave::from(ntype, 1.0, 1.0, warp)])
+    }
+
+    #[test]
+    fn same_seed_same_heights() {
+        let octaves = Octave::defaults();
+        let a = sample(&HeightGenerator::from(7, 64.0, &octaves));
+        assert_eq!(a, sample(&HeightGenerator::from(7, 64.0, &octaves)));
+        assert_ne!(a, sample(&HeightGenerator::from(8, 64.0, &octaves)));
+    }
+
+    #[test]
+    fn every_noise_type_is_its_own() {
+        let samples: Vec<Vec<f32>> = TYPES.iter().map(|t| sample(&one(*t, None))).collect();
+        for (ntype, heights) in TYPES.iter().zip(&samples) {
+            assert!(heights.iter().all(|h| h.is_finite()), "{:?}", ntype);
+            let (low, high) = heights
+                .iter()
+                .fold((f32::MAX, f32::MIN), |(l, h), x| (l.min(*x), h.max(*x)));
+            assert!(high - low > 0.1, "{:?} is flat", ntype);
+            assert!(low >= -1.0 && high <= 1.0 + f32::EPSILON, "{:?}", ntype);
+        }
+        for i in 0..TYPES.len() {
+            for j in i + 1..TYPES.len() {
+                assert_ne!(samples[i], samples[j], "{:?} {:?}", TYPES[i], TYPES[j]);
+            }
+        }
+        // ridges peak where perlin crosses zero, billows are its mirror image
+        let (perlin, ridged, billow) = (&samples[0], &samples[3], &samples[4]);
+        for ((p, r), b) in perlin.iter().zip(ridged).zip(billow) {
+            assert!((r - (1.0 - 2.0 * p.abs())).abs() < 1e-5);
+            assert!((r + b).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn warp_moves_the_samples() {
+        let plain = sample(&one(NoiseType::Perlin, None));
+        let still = Warp {
+            frequency: 1.0,
+            amplitude: 0.0,
+        };
+        assert_eq!(plain, sample(&one(NoiseType::Perlin, Some(still))));
+        let warp = Warp {
+            frequency: 2.0,
+            amplitude: 0.5,
+        };
+        let warped = sample(&one(NoiseType::Perlin, Some(warp)));
+        let moved = plain.iter().zip(&warped).filter(|(a, b)| a != b).count();
+        assert!(moved > plain.len() / 2);
+    }
+
+    #[test]
+    fn heights_are_not_quantised() {
+        let generator = HeightGenerator::from(7, 64.0, &Octave::defaults());
+        let mut heights = sample(&generator);
+        assert!(heights.iter().any(|h| h.fract() != 0.0));
+        heights.sort_by(|a, b| a.total_cmp(b));
+        heights.dedup();
+        assert!(heights.len() > 1000);
+        // a hundredth of a tile apart is a small step, not none or a whole one
+        for x in 0..32 {
+            let (x, y) = (x as f64 * 5.0, 11.0);
+            let d = (generator.get(x + 0.01, y) - generator.get(x, y)).abs();
+            assert!(d > 0.0 && d < 0.05, "{} at {}", d, x);
+        }
+    }
+
+    #[test]
+    fn octave_parses() {
+        assert_eq!(
+            Octave::parse("simplex 0.5 -2").unwrap(),
+            Octave::from(NoiseType::Simplex, 0.5, -2.0, None)
+        );
+        let warp = Warp {
+            frequency: 0.25,
+            amplitude: 4.0,
+        };
+        assert_eq!(
+            Octave::parse(" ridged 1 3 warp 0.25 4 ").unwrap(),
+            Octave::from(NoiseType::Ridged, 1.0, 3.0, Some(warp))
+        );
+        for (input, error) in [
+            ("", "unknown noise type ''"),
+            ("cubic 1 2", "unknown noise type 'cubic'"),
+            ("perlin 1", "octave 'perlin 1' is missing a value"),
+            ("perlin one 2", "invalid number in octave 'perlin one 2'"),
+            ("perlin 1 2 bend", "unexpected 'bend' in octave"),
+            (
+                "perlin 1 2 warp 1",
+                "octave 'perlin 1 2 warp 1' is missing a value",
+            ),
+            (
+                "perlin 1 2 warp 1 x",
+                "invalid number in octave 'perlin 1 2 warp 1 x'",
+            ),
+            ("perlin 1 2 warp 1 2 junk", "unexpected 'junk' in octave"),
+        ] {
+            assert_eq!(Octave::parse(input).unwrap_err(), error);
+        }
+    }
+}
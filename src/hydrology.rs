@@ -0,0 +1,212 @@
+use crate::height::HeightMap;
+use crate::worldgen::TileType;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+// minimum amount a depression has to be filled before it counts as a lake
+pub const LAKE_DEPTH: f32 = 0.5;
+// slope added while filling so that flats still drain somewhere
+const EPSILON: f32 = 1e-4;
+
+#[derive(PartialEq)]
+struct Cell {
+    height: f32,
+    index: usize,
+}
+impl Eq for Cell {}
+impl Ord for Cell {
+    // reversed so the BinaryHeap pops the lowest cell first
+    fn cmp(&self, other: &Cell) -> Ordering {
+        other
+            .height
+            .total_cmp(&self.height)
+            .then(other.index.cmp(&self.index))
+    }
+}
+impl PartialOrd for Cell {
+    fn partial_cmp(&self, other: &Cell) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+pub struct Hydrology {
+    pub filled: HeightMap,
+    // cell each cell drains into, outlets drain into themselves
+    pub receiver: Vec<usize>,
+    // amount of cells upstream of and including each cell
+    pub flow: Vec<u32>,
+    pub water: Vec<Option<TileType>>,
+}
+// priority flood from the map edges and the sea: every cell is reached from the
+// lowest neighbour that floods it, which gives the fill level and the flow
+// direction in one pass
+pub fn compute(map: &HeightMap, sea_level: f32, river_threshold: u32) -> Hydrology {
+    let (w, h) = (map.width as i64, map.height as i64);
+    let len = map.data.len();
+    let mut filled = map.clone();
+    let mut receiver: Vec<usize> = (0..len).collect();
+    let mut water: Vec<Option<TileType>> = vec![None; len];
+    let mut visited = vec![false; len];
+    let mut order = Vec::with_capacity(len);
+    let mut open = BinaryHeap::new();
+    for i in 0..len {
+        let (x, y) = (i as i64 % w, i as i64 / w);
+        let ocean = map.data[i] < sea_level;
+        if ocean {
+            water[i] = Some(TileType::Ocean);
+        }
+        if ocean || x == 0 || y == 0 || x == w - 1 || y == h - 1 {
+            visited[i] = true;
+            open.push(Cell {
+                height: map.data[i],
+                index: i,
+            });
+        }
+    }
+    while let Some(cell) = open.pop() {
+        order.push(cell.index);
+        let (x, y) = (cell.index as i64 % w, cell.index as i64 / w);
+        for (dx, dy) in [
+            (-1, -1),
+            (0, -1),
+            (1, -1),
+            (-1, 0),
+            (1, 0),
+            (-1, 1),
+            (0, 1),
+            (1, 1),
+        ] {
+            let (nx, ny) = (x + dx, y + dy);
+            if !map.contains(nx, ny) {
+                continue;
+            }
+            let n = (ny * w + nx) as usize;
+            if visited[n] {
+                continue;
+            }
+            visited[n] = true;
+            receiver[n] = cell.index;
+            filled.data[n] = map.data[n].max(filled.data[cell.index] + EPSILON);
+            if filled.data[n] - map.data[n] > LAKE_DEPTH {
+                water[n] = Some(TileType::Lake);
+            }
+            open.push(Cell {
+                height: filled.data[n],
+                index: n,
+            });
+        }
+    }
+    // cells were popped from low to high, so walking backwards visits every
+    // cell after everything that drains into it
+    let mut flow = vec![1u32; len];
+    for &i in order.iter().rev() {
+        if receiver[i] != i {
+            flow[receiver[i]] += flow[i];
+        }
+    }
+    for i in 0..len {
+        if water[i].is_none() && flow[i] >= river_threshold {
+            water[i] = Some(TileType::River);
+        }
+    }
+    Hydrology {
+        filled,
+        receiver,
+        flow,
+        water,
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 16 x 16 tiles at one height
+    fn plateau(height: f32) -> HeightMap {
+        let mut map = HeightMap::new(16, 16);
+        map.data.fill(height);
+        map
+    }
+    // outlet a cell drains to in the end
+    fn outlet(hydrology: &Hydrology, mut i: usize) -> usize {
+        for _ in 0..hydrology.receiver.len() {
+            if hydrology.receiver[i] == i {
+                return i;
+            }
+            i = hydrology.receiver[i];
+        }
+        panic!("{} drains in a circle", i);
+    }
+
+    #[test]
+    fn pits_fill_to_their_rim() {
+        let mut map = plateau(1.0);
+        for y in 4..8 {
+            for x in 4..8 {
+                map.set(x, y, 0.0);
+            }
+        }
+        // too shallow to be a lake
+        map.set(11, 11, 0.8);
+        let hydrology = compute(&map, -10.0, 1000);
+        for i in 0..map.data.len() {
+            assert!(hydrology.filled.data[i] >= map.data[i]);
+            let o = outlet(&hydrology, i);
+            let (x, y) = (o % 16, o / 16);
+            assert!(x == 0 || y == 0 || x == 15 || y == 15);
+        }
+        for y in 4..8 {
+            for x in 4..8 {
+                let level = hydrology.filled.get(x, y);
+                assert!((1.0..1.01).contains(&level), "{}", level);
+                assert_eq!(hydrology.water[y * 16 + x], Some(TileType::Lake));
+            }
+        }
+        assert!(hydrology.filled.get(11, 11) >= 1.0);
+        assert_eq!(hydrology.water[11 * 16 + 11], None);
+        assert_eq!(hydrology.water[0], None);
+    }
+
+    #[test]
+    fn sea_is_ocean() {
+        let mut map = plateau(1.0);
+        for y in 0..16 {
+            map.set(0, y, -1.0);
+            map.set(1, y, -1.0);
+        }
+        let hydrology = compute(&map, 0.0, 1000);
+        for y in 0..16 {
+            assert_eq!(hydrology.water[y * 16 + 1], Some(TileType::Ocean));
+            assert_eq!(hydrology.water[y * 16 + 2], None);
+        }
+    }
+
+    #[test]
+    fn rivers_carry_their_flow_across_a_chunk_border() {
+        // a valley along row 8 falling east through the border between two
+        // chunks of 8 tiles, between high ground
+        let mut map = plateau(20.0);
+        for x in 0..16 {
+            map.set(x, 8, 15.0 - x as f32);
+        }
+        let valley = |x: usize| 8 * 16 + x;
+        let hydrology = compute(&map, -10.0, 1000);
+        // the west end is on the edge of the map and drains off it
+        for x in 1..15 {
+            assert_eq!(hydrology.receiver[valley(x)], valley(x + 1));
+            assert!(hydrology.flow[valley(x + 1)] > hydrology.flow[valley(x)]);
+        }
+        // every cell drains somewhere, the outlets hold all the flow
+        let outlets: u32 = (0..256)
+            .filter(|i| hydrology.receiver[*i] == *i)
+            .map(|i| hydrology.flow[i])
+            .sum();
+        assert_eq!(outlets, 256);
+        // the river starts where the flow reaches the threshold and goes on
+        // over the border
+        let threshold = hydrology.flow[valley(6)];
+        let hydrology = compute(&map, -10.0, threshold);
+        for x in 1..16 {
+            let river = hydrology.water[valley(x)] == Some(TileType::River);
+            assert_eq!(river, x >= 6, "{}", x);
+        }
+    }
+}
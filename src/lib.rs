@@ -1,7 +1,33 @@
 pub mod bitmap;
+pub mod camera;
+pub mod cli;
+pub mod climate;
+pub mod codec;
+pub mod config;
+pub mod console;
+pub mod construction;
+pub mod erosion;
+pub mod faction;
+pub mod height;
+pub mod hydrology;
 pub mod lang;
 pub mod math;
+pub mod movement;
+pub mod needs;
+pub mod path;
 pub mod plot;
+pub mod region;
+#[cfg(feature = "gui")]
 pub mod renderer;
+pub mod roads;
+pub mod save;
+pub mod settlement;
+pub mod sim;
+pub mod spatial;
+pub mod stats;
+pub mod store;
+pub mod task;
+pub mod tectonics;
+pub mod terrain;
 pub mod util;
 pub mod worldgen;
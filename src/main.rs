@@ -1,102 +1,163 @@
+#[cfg(feature = "gui")]
 use crossbeam::channel::unbounded;
+use crossbeam::channel::Receiver;
 use rand::Rng;
-use rayon::prelude::*;
-use std::io;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+#[cfg(feature = "gui")]
 use std::thread;
+#[cfg(feature = "gui")]
 use std::time::Duration;
-use U::plot::plot;
-use U::renderer::{render_server, Camera};
-use U::util::RenderMsg;
-use U::worldgen::{worldgen, News, CHUNK_SIZE, WORLD_SIZE};
+use U::camera::Camera;
+use U::cli::{Options, USAGE};
+use U::config::WorldConfig;
+use U::console::{self, Command};
+#[cfg(feature = "gui")]
+use U::renderer::render_server;
+use U::sim::Simulation;
+use U::worldgen::{worldgen, World};
 
-use lazy_static::lazy_static;
-lazy_static! {
-    pub static ref PARTITION_SIZE: usize = (*WORLD_SIZE as usize * *WORLD_SIZE as usize) / 16;
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(2);
 }
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = Options::from_args(&args)
+        .unwrap_or_else(|e| fail(&format!("{}\nrun with --help for usage", e)));
+    if options.help {
+        println!("{}", USAGE);
+        return;
+    }
+    if let Err(e) = fs::create_dir_all(&options.output_dir) {
+        fail(&format!("cannot create {}: {}", options.output_dir, e));
+    }
+    let mut worlds = vec![];
+    match &options.load {
+        Some(path) => {
+            for i in 0..options.worlds {
+                let path = if options.worlds > 1 {
+                    format!("{}.{}", path, i)
+                } else {
+                    path.clone()
+                };
+                worlds.push(World::load(&path).unwrap_or_else(|e| fail(&e)));
+            }
+        }
+        None => {
+            let first = options.seed.unwrap_or_else(|| rand::thread_rng().gen());
+            for i in 0..options.worlds {
+                let world = worldgen(first.wrapping_add(i as u32), &options.config);
+                worlds.push(world.unwrap_or_else(|e| fail(&e)));
+            }
+        }
+    }
+    let config = worlds[0].config.clone();
+    let mut sim = Simulation::from(worlds);
+    sim.step_increment = options.step_increment;
+    sim.autosave = options.autosave;
+    sim.save_path = options.save_path();
+    sim.step_limit = options.steps.map(|steps| sim.worlds[0].step + steps);
+    if options.headless {
+        if sim.step_limit.is_none() {
+            sim.step_limit = Some(sim.worlds[0].step + 1000);
+        }
+        run_headless(sim, &options.output_dir);
+    } else {
+        for world in &sim.worlds {
+            println!("world seed {}", world.seed);
+        }
+        run_gui(sim, config);
+    }
+}
+// simulates until the step limit or until every entity is dead, then writes
+// the worlds to the save path and a summary of each to the output directory
+fn run_headless(mut sim: Simulation, output_dir: &str) {
+    let commands = console::listen();
+    let mut camera = Camera::overview(&sim.worlds[0].config);
+    // counting the population pages chunks in, so it is only done once a
+    // step has been resolved
+    let mut counted = None;
+    sim.run(|sim| {
+        if run_commands(sim, &mut camera, &commands) {
+            return true;
+        }
+        let step = sim.worlds[0].step;
+        if counted == Some(step) {
+            return false;
+        }
+        counted = Some(step);
+        sim.worlds.iter().all(|w| w.step > 0 && w.population() == 0)
+    });
+    if let Err(e) = sim.save() {
+        fail(&e);
+    }
+    let mut summary = String::new();
+    for (i, world) in sim.worlds.iter().enumerate() {
+        summary += &format!(
+            "world {} seed {} step {} population {} digest {:016x} saved to {}\n",
+            i,
+            world.seed,
+            world.step,
+            world.population(),
+            world.digest(),
+            sim.save_path_for(i)
+        );
+    }
+    print!("{}", summary);
+    let path = Path::new(output_dir).join("summary.txt");
+    if let Err(e) = fs::write(&path, summary) {
+        fail(&format!("cannot write {}: {}", path.display(), e));
+    }
+}
+// runs the console commands that came in since the last frame, true on quit
+fn run_commands(sim: &mut Simulation, camera: &mut Camera, commands: &Receiver<Command>) -> bool {
+    for command in commands.try_iter() {
+        let quit = matches!(command, Command::Quit);
+        for line in console::execute(sim, camera, command) {
+            println!("{}", line);
+        }
+        if quit {
+            return true;
+        }
+    }
+    false
+}
+#[cfg(not(feature = "gui"))]
+fn run_gui(_sim: Simulation, _config: WorldConfig) {
+    fail("built without the gui feature, run with --headless");
+}
+#[cfg(feature = "gui")]
+fn run_gui(mut sim: Simulation, config: WorldConfig) {
     let (tx, rx) = unbounded();
     let (tx2, rx2) = unbounded();
+    let (tx3, rx3) = unbounded();
+    let render_config = config.clone();
     thread::spawn(move || {
-        render_server(&tx2, &rx);
+        render_server(&tx2, &rx, &rx3, render_config);
     });
-    let mut worlds = vec![];
-    let mut rng = rand::thread_rng();
-    for _ in 0..1 {
-        let seed = rng.gen_range(0..1000);
-        worlds.push(worldgen(seed));
-    }
-    let mut state: Vec<RenderMsg> = vec![];
-    let mut step = 0;
-    let mut step_increment = 1;
-    let mut camera = Camera::new();
-    let mut vic_world = 0;
-    let mut render = false;
+    let mut camera = Camera::new(&config);
+    let commands = console::listen();
     thread::spawn(move || {
         //plot();
     });
-
-    thread::spawn(move || {
-        let mut input = String::new();
-        match io::stdin().read_line(&mut input) {
-            Ok(e) => e,
-            Err(e) => panic!("End..."),
-        };
-        match input.as_str() {
-            "" => "joinks",
-            &_ => todo!(),
-        }
-    });
-    let mut partition = 0;
     loop {
-        if partition >= *PARTITION_SIZE {
-            partition = 0;
-            // stage 1: resolve all tensions in the world
-            worlds
-                .par_iter_mut()
-                .for_each(|c| c.resolve(step_increment));
-            //println!("Stage 1 conducted succesfully!");
-            // stage 2: resolve all tensions between chunks
-            worlds
-                .par_iter_mut()
-                .for_each(|c| c.resolve_between(step_increment));
+        let (x, y) = (camera.coords.x, camera.coords.y);
+        if run_commands(&mut sim, &mut camera, &commands) {
+            return;
         }
-        //println!("Stage 2 conducted succesfully!");
-        // stage 3: push into rendering buffer
-        let mut world = &mut worlds[vic_world];
-        let mut news = News::from(vec!["news ".to_string()]);
-        for i in ((world.chunks.len() / *PARTITION_SIZE) * partition)
-            ..(((world.chunks.len() / *PARTITION_SIZE) * partition)
-                + (world.chunks.len() / *PARTITION_SIZE))
-        {
-            let chunk = world.fetch_chunk(i as usize);
-            if chunk.tiles[0].coords.x < camera.coords.x
-                || chunk.tiles[0].coords.y < camera.coords.y
-                || chunk.tiles[chunk.tiles.len() - 1].coords.x
-                    > (-camera.coords.x as i32 + camera.render_distance_w + *CHUNK_SIZE as i32)
-                        as f32
-                || chunk.tiles[chunk.tiles.len() - 1].coords.y as i32 + *CHUNK_SIZE as i32
-                    > -camera.coords.y as i32
-                        + camera.render_distance_h as i32
-                        + *CHUNK_SIZE as i32
-                        + *CHUNK_SIZE as i32
-            {
-                continue;
-            }
-            news.newscast.append(&mut chunk.inquire_news().newscast);
-            state.push(RenderMsg::from(chunk.clone(), news.clone()));
+        if (x, y) != (camera.coords.x, camera.coords.y) {
+            let _ = tx3.send(camera.clone());
         }
-        let _ = tx.send(state.clone());
-        println!("Stage 3 conducted succesfully!");
+        // stages 1 to 3, stage 1 and 2 only when the partitions went around
+        let state = sim.frame(&camera);
+        let _ = tx.send(state);
         // stage 4: render
-        println!("Stage 4 conducted succesfully!");
-        step += step_increment;
         if let Ok(x) = rx2.recv() {
             camera = x.camera;
         }
         ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
-        // cleanup
-        state.clear();
-        news.newscast.clear();
-        partition += 1;
     }
 }
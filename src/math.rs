@@ -1,4 +1,234 @@
 use crate::worldgen::Coords;
+use std::ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign};
+
+// offsets to the 4 tiles sharing an edge and to all 8 tiles around a tile
+pub const NEIGHBOURS_4: [(i64, i64); 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+pub const NEIGHBOURS_8: [(i64, i64); 8] = [
+    (-1, 0),
+    (1, 0),
+    (0, -1),
+    (0, 1),
+    (-1, -1),
+    (1, -1),
+    (-1, 1),
+    (1, 1),
+];
+
+// euclidean distance rounded down, for integer vicinity checks
 pub fn dist(c1: &Coords, c2: &Coords) -> i32 {
-    ((c1.x - c2.x + c1.y - c2.y) as f32).sqrt() as i32
+    euclid(c1, c2) as i32
+}
+pub fn euclid(c1: &Coords, c2: &Coords) -> f32 {
+    (*c1 - *c2).length()
+}
+pub fn manhattan(c1: &Coords, c2: &Coords) -> f32 {
+    (c1.x - c2.x).abs() + (c1.y - c2.y).abs()
+}
+pub fn chebyshev(c1: &Coords, c2: &Coords) -> f32 {
+    (c1.x - c2.x).abs().max((c1.y - c2.y).abs())
+}
+// shortest offset from `a` to `b` on an axis that wraps around after `size`
+pub fn wrapped_delta(a: f32, b: f32, size: f32) -> f32 {
+    let d = (b - a).rem_euclid(size);
+    if d > size / 2.0 {
+        d - size
+    } else {
+        d
+    }
+}
+// euclidean distance on a world whose edges wrap around
+pub fn toroidal_euclid(c1: &Coords, c2: &Coords, width: f32, height: f32) -> f32 {
+    let dx = wrapped_delta(c1.x, c2.x, width);
+    let dy = wrapped_delta(c1.y, c2.y, height);
+    (dx * dx + dy * dy).sqrt()
+}
+// world positions are in tiles, a tile covers [x, x + 1) and a chunk covers
+// `chunk_size` tiles per side
+pub fn world_to_tile(c: &Coords) -> (i64, i64) {
+    (c.x.floor() as i64, c.y.floor() as i64)
+}
+// centre of the tile
+pub fn tile_to_world(tile: (i64, i64)) -> Coords {
+    Coords::from((tile.0 as f32 + 0.5, tile.1 as f32 + 0.5))
+}
+pub fn tile_to_chunk(tile: (i64, i64), chunk_size: u32) -> (i64, i64) {
+    (
+        tile.0.div_euclid(chunk_size as i64),
+        tile.1.div_euclid(chunk_size as i64),
+    )
+}
+// first tile of the chunk
+pub fn chunk_to_tile(chunk: (i64, i64), chunk_size: u32) -> (i64, i64) {
+    (chunk.0 * chunk_size as i64, chunk.1 * chunk_size as i64)
+}
+pub fn world_to_chunk(c: &Coords, chunk_size: u32) -> (i64, i64) {
+    tile_to_chunk(world_to_tile(c), chunk_size)
+}
+// position of a tile inside its chunk
+pub fn tile_in_chunk(tile: (i64, i64), chunk_size: u32) -> (usize, usize) {
+    (
+        tile.0.rem_euclid(chunk_size as i64) as usize,
+        tile.1.rem_euclid(chunk_size as i64) as usize,
+    )
+}
+// index into `World` chunks, None outside the world
+pub fn chunk_index(chunk: (i64, i64), world_size: u32) -> Option<usize> {
+    let size = world_size as i64;
+    if chunk.0 < 0 || chunk.1 < 0 || chunk.0 >= size || chunk.1 >= size {
+        return None;
+    }
+    Some((chunk.1 * size + chunk.0) as usize)
+}
+pub fn chunk_coords(index: usize, world_size: u32) -> (i64, i64) {
+    (
+        (index % world_size as usize) as i64,
+        (index / world_size as usize) as i64,
+    )
+}
+// neighbours of a tile from one of the offset lists that lie inside a
+// `width` by `height` grid
+pub fn neighbours(
+    tile: (usize, usize),
+    offsets: &'static [(i64, i64)],
+    width: usize,
+    height: usize,
+) -> impl Iterator<Item = (usize, usize)> {
+    offsets.iter().filter_map(move |(dx, dy)| {
+        let (x, y) = (tile.0 as i64 + dx, tile.1 as i64 + dy);
+        if x < 0 || y < 0 || x >= width as i64 || y >= height as i64 {
+            None
+        } else {
+            Some((x as usize, y as usize))
+        }
+    })
+}
+impl Coords {
+    pub fn length(&self) -> f32 {
+        (self.x * self.x + self.y * self.y).sqrt()
+    }
+    // same direction with length 1, zero stays zero
+    pub fn normalized(&self) -> Coords {
+        let length = self.length();
+        if length == 0.0 {
+            *self
+        } else {
+            *self / length
+        }
+    }
+}
+impl Add for Coords {
+    type Output = Coords;
+    fn add(self, other: Coords) -> Coords {
+        Coords::from((self.x + other.x, self.y + other.y))
+    }
+}
+impl Sub for Coords {
+    type Output = Coords;
+    fn sub(self, other: Coords) -> Coords {
+        Coords::from((self.x - other.x, self.y - other.y))
+    }
+}
+impl Mul<f32> for Coords {
+    type Output = Coords;
+    fn mul(self, s: f32) -> Coords {
+        Coords::from((self.x * s, self.y * s))
+    }
+}
+impl Div<f32> for Coords {
+    type Output = Coords;
+    fn div(self, s: f32) -> Coords {
+        Coords::from((self.x / s, self.y / s))
+    }
+}
+impl Neg for Coords {
+    type Output = Coords;
+    fn neg(self) -> Coords {
+        Coords::from((-self.x, -self.y))
+    }
+}
+impl AddAssign for Coords {
+    fn add_assign(&mut self, other: Coords) {
+        self.x += other.x;
+        self.y += other.y;
+    }
+}
+impl SubAssign for Coords {
+    fn sub_assign(&mut self, other: Coords) {
+        self.x -= other.x;
+        self.y -= other.y;
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(x: f32, y: f32) -> Coords {
+        Coords::from((x, y))
+    }
+    #[test]
+    fn distances() {
+        let (a, b) = (at(1.0, 2.0), at(4.0, 6.0));
+        assert_eq!(euclid(&a, &b), 5.0);
+        assert_eq!(manhattan(&a, &b), 7.0);
+        assert_eq!(chebyshev(&a, &b), 4.0);
+        assert_eq!(dist(&a, &at(2.9, 2.0)), 1);
+        assert_eq!(euclid(&a, &a), 0.0);
+        assert_eq!(manhattan(&b, &a), manhattan(&a, &b));
+    }
+    #[test]
+    fn wrapped_distance_crosses_the_seam() {
+        assert_eq!(wrapped_delta(1.0, 99.0, 100.0), -2.0);
+        assert_eq!(wrapped_delta(99.0, 1.0, 100.0), 2.0);
+        assert_eq!(wrapped_delta(10.0, 40.0, 100.0), 30.0);
+        assert_eq!(wrapped_delta(0.0, 50.0, 100.0), 50.0);
+        assert_eq!(
+            toroidal_euclid(&at(1.0, 1.0), &at(97.0, 98.0), 100.0, 100.0),
+            5.0
+        );
+    }
+    #[test]
+    fn conversions_at_boundaries() {
+        assert_eq!(world_to_tile(&at(0.0, 0.999)), (0, 0));
+        assert_eq!(world_to_tile(&at(1.0, -0.001)), (1, -1));
+        assert_eq!(tile_to_world((3, 0)), at(3.5, 0.5));
+        assert_eq!(tile_to_chunk((63, 64), 64), (0, 1));
+        assert_eq!(tile_to_chunk((-1, 0), 64), (-1, 0));
+        assert_eq!(tile_in_chunk((63, 64), 64), (63, 0));
+        assert_eq!(tile_in_chunk((-1, 0), 64), (63, 0));
+        assert_eq!(chunk_to_tile((2, 1), 64), (128, 64));
+        assert_eq!(world_to_chunk(&at(127.99, 128.0), 64), (1, 2));
+        assert_eq!(chunk_index((3, 3), 4), Some(15));
+        assert_eq!(chunk_index((4, 0), 4), None);
+        assert_eq!(chunk_index((0, -1), 4), None);
+        assert_eq!(chunk_coords(15, 4), (3, 3));
+        assert_eq!(chunk_coords(4, 4), (0, 1));
+    }
+    #[test]
+    fn neighbour_offsets() {
+        let mut four: Vec<(usize, usize)> = neighbours((1, 1), &NEIGHBOURS_4, 3, 3).collect();
+        four.sort();
+        assert_eq!(four, vec![(0, 1), (1, 0), (1, 2), (2, 1)]);
+        assert_eq!(neighbours((1, 1), &NEIGHBOURS_8, 3, 3).count(), 8);
+        assert_eq!(neighbours((0, 0), &NEIGHBOURS_4, 3, 3).count(), 2);
+        assert_eq!(neighbours((0, 0), &NEIGHBOURS_8, 3, 3).count(), 3);
+        assert_eq!(neighbours((2, 2), &NEIGHBOURS_8, 3, 3).count(), 3);
+        assert_eq!(neighbours((0, 0), &NEIGHBOURS_8, 1, 1).count(), 0);
+    }
+    #[test]
+    fn coords_arithmetic() {
+        let (a, b) = (at(1.0, 2.0), at(3.0, -4.0));
+        assert_eq!(a + b, at(4.0, -2.0));
+        assert_eq!(a - b, at(-2.0, 6.0));
+        assert_eq!(a * 2.0, at(2.0, 4.0));
+        assert_eq!(b / 2.0, at(1.5, -2.0));
+        assert_eq!(-a, at(-1.0, -2.0));
+        let mut c = a;
+        c += b;
+        assert_eq!(c, at(4.0, -2.0));
+        c -= b;
+        assert_eq!(c, a);
+        assert_eq!(at(3.0, 4.0).length(), 5.0);
+        assert_eq!(at(3.0, 4.0).normalized(), at(0.6, 0.8));
+        assert_eq!(at(0.0, 0.0).normalized(), at(0.0, 0.0));
+    }
 }
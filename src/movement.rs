@@ -0,0 +1,320 @@
+use crate::config::WorldConfig;
+use crate::math::euclid;
+use crate::spatial::SpatialHash;
+use crate::stats::Stats;
+use crate::terrain::Terrain;
+use crate::worldgen::{Chunk, Coords, Entity, Status, TileType};
+use rand::Rng;
+use std::f32::consts::PI;
+
+// tiles per step at zero agility, each point of agility adds AGILITY_SPEED
+const BASE_SPEED: f32 = 0.3;
+const AGILITY_SPEED: f32 = 0.07;
+// how much a step turns the wander heading at most, in radians
+const WANDER_TURN: f32 = 0.4;
+// fraction of the max speed used when just wandering around
+const WANDER_SPEED: f32 = 0.5;
+// other entities closer than this push an entity away
+const SEPARATION_RADIUS: f32 = 1.5;
+const SEPARATION_WEIGHT: f32 = 1.0;
+const FLEE_WEIGHT: f32 = 1.5;
+// speed lost per unit of height climbed
+const UPHILL_COST: f32 = 0.5;
+// walking on a road is this much faster
+const ROAD_SPEED: f32 = 1.5;
+// a seek target counts as reached within this distance
+const ARRIVAL: f32 = 0.5;
+
+pub fn max_speed(stats: &Stats) -> f32 {
+    BASE_SPEED + stats.agility as f32 * AGILITY_SPEED
+}
+// keeps on turning a little so wandering entities walk in curves
+pub fn wander<R: Rng>(entity: &mut Entity, rng: &mut R) -> (f32, f32) {
+    entity.heading += rng.gen_range(-WANDER_TURN..WANDER_TURN);
+    let speed = max_speed(&entity.stats) * WANDER_SPEED;
+    let desired = (entity.heading.cos() * speed, entity.heading.sin() * speed);
+    (desired.0 - entity.vel.0, desired.1 - entity.vel.1)
+}
+pub fn seek(entity: &Entity, target: &Coords) -> (f32, f32) {
+    let desired = scale(
+        normalize((target.x - entity.coords.x, target.y - entity.coords.y)),
+        max_speed(&entity.stats),
+    );
+    (desired.0 - entity.vel.0, desired.1 - entity.vel.1)
+}
+pub fn flee(entity: &Entity, threat: &Coords) -> (f32, f32) {
+    let desired = scale(
+        normalize((entity.coords.x - threat.x, entity.coords.y - threat.y)),
+        max_speed(&entity.stats),
+    );
+    (desired.0 - entity.vel.0, desired.1 - entity.vel.1)
+}
+// pushes away from everyone too close, harder the closer they are
+pub fn separation(entity: &Entity, others: &[Coords]) -> (f32, f32) {
+    let mut push = (0.0, 0.0);
+    for other in others {
+        let away = (entity.coords.x - other.x, entity.coords.y - other.y);
+        let d = length(away);
+        if d > 0.0 && d < SEPARATION_RADIUS {
+            push.0 += away.0 / (d * d);
+            push.1 += away.1 / (d * d);
+        }
+    }
+    push
+}
+// the tile under a point, None outside the world. the chunk's own tiles are
+// preferred so changes made during the simulation count
+fn tile_at(
+    chunk: &Chunk,
+    terrain: &Terrain,
+    config: &WorldConfig,
+    x: f32,
+    y: f32,
+) -> Option<(TileType, f32)> {
+    let side = terrain.side() as f32;
+    if x < 0.0 || y < 0.0 || x >= side || y >= side {
+        return None;
+    }
+    let (x, y) = (x as usize, y as usize);
+    let chunk_size = config.chunk_size as usize;
+    let (cx, cy) = (chunk.coords.x as usize, chunk.coords.y as usize);
+    if !chunk.tiles.is_empty() && x / chunk_size == cx && y / chunk_size == cy {
+        let tile = &chunk.tiles[(y % chunk_size) * chunk_size + x % chunk_size];
+        return Some((tile.ttype.clone(), tile.height));
+    }
+    Some((terrain.ttype(x, y), terrain.height(x, y)))
+}
+// height of a tile that can be walked on, None for water, walls and
+// anything outside the world
+pub fn ground(
+    chunk: &Chunk,
+    terrain: &Terrain,
+    config: &WorldConfig,
+    x: f32,
+    y: f32,
+) -> Option<f32> {
+    tile_at(chunk, terrain, config, x, y)
+        .filter(|(ttype, _)| ttype.is_passable())
+        .map(|(_, height)| height)
+}
+// one step of steering and movement for every entity in the chunk: fleeing
+// from fights the entity does not want, seeking its target or wandering, and
+// keeping its distance from the others
+pub fn resolve(chunk: &mut Chunk, terrain: &Terrain, config: &WorldConfig) {
+    // positions and states from before anyone moved this step
+    let others: Vec<(Coords, Status)> = chunk
+        .entities
+        .iter()
+        .map(|e| (e.coords, e.status.clone()))
+        .collect();
+    let vicinity = config.vicinity_dist as f32;
+    let grid = SpatialHash::from(&chunk.entities, vicinity);
+    for i in 0..chunk.entities.len() {
+        let mut entity = chunk.entities[i].clone();
+        let near: Vec<&(Coords, Status)> = grid
+            .near(&others[i].0, vicinity)
+            .into_iter()
+            .filter(|j| *j != i)
+            .map(|j| &others[j])
+            .collect();
+        let threat = near
+            .iter()
+            .filter(|(_, status)| *status == Status::Fighting)
+            .min_by(|a, b| euclid(&entity.coords, &a.0).total_cmp(&euclid(&entity.coords, &b.0)));
+        let mut steer = match (&threat, &entity.target) {
+            (Some((threat, _)), _) if entity.status != Status::Fighting => {
+                scale(flee(&entity, threat), FLEE_WEIGHT)
+            }
+            (_, Some(target)) => {
+                if euclid(&entity.coords, target) < ARRIVAL {
+                    // walk on to the next waypoint without stopping
+                    if entity.path.is_empty() {
+                        entity.target = None;
+                        (-entity.vel.0, -entity.vel.1)
+                    } else {
+                        let next = entity.path.remove(0);
+                        let steer = seek(&entity, &next);
+                        entity.target = Some(next);
+                        steer
+                    }
+                } else {
+                    seek(&entity, target)
+                }
+            }
+            _ => wander(&mut entity, &mut chunk.rng),
+        };
+        let near: Vec<Coords> = near.iter().map(|(c, _)| *c).collect();
+        let push = scale(separation(&entity, &near), SEPARATION_WEIGHT);
+        steer = (steer.0 + push.0, steer.1 + push.1);
+        let max = max_speed(&entity.stats);
+        entity.vel = truncate((entity.vel.0 + steer.0, entity.vel.1 + steer.1), max);
+        step(&mut entity, chunk, terrain, config);
+        chunk.entities[i] = entity;
+    }
+}
+// moves along the velocity, slower uphill and faster on roads. when the way
+// is blocked the entity slides along the blocking tiles or stops and turns
+// around
+fn step(entity: &mut Entity, chunk: &Chunk, terrain: &Terrain, config: &WorldConfig) {
+    let (x, y) = (entity.coords.x, entity.coords.y);
+    let (here, road) = match tile_at(chunk, terrain, config, x, y) {
+        Some((ttype, height)) if ttype.is_passable() => (height, ttype == TileType::Road),
+        // standing somewhere it should not be, wait for a way out
+        _ => return,
+    };
+    let pace = if road { ROAD_SPEED } else { 1.0 };
+    let side = terrain.side() as f32;
+    for vel in [entity.vel, (entity.vel.0, 0.0), (0.0, entity.vel.1)] {
+        let next = (x + vel.0, y + vel.1);
+        let height = match ground(chunk, terrain, config, next.0, next.1) {
+            Some(height) => height,
+            None => continue,
+        };
+        let slow = pace / (1.0 + (height - here).max(0.0) * UPHILL_COST);
+        let next = (
+            (x + vel.0 * slow).clamp(0.0, side - 0.001),
+            (y + vel.1 * slow).clamp(0.0, side - 0.001),
+        );
+        if ground(chunk, terrain, config, next.0, next.1).is_some() {
+            entity.coords.x = next.0;
+            entity.coords.y = next.1;
+            entity.vel = scale(vel, slow);
+            return;
+        }
+    }
+    entity.vel = (0.0, 0.0);
+    entity.heading += PI;
+}
+fn length(v: (f32, f32)) -> f32 {
+    (v.0 * v.0 + v.1 * v.1).sqrt()
+}
+fn normalize(v: (f32, f32)) -> (f32, f32) {
+    let l = length(v);
+    if l == 0.0 {
+        (0.0, 0.0)
+    } else {
+        (v.0 / l, v.1 / l)
+    }
+}
+fn scale(v: (f32, f32), s: f32) -> (f32, f32) {
+    (v.0 * s, v.1 * s)
+}
+fn truncate(v: (f32, f32), max: f32) -> (f32, f32) {
+    let l = length(v);
+    if l > max {
+        scale(v, max / l)
+    } else {
+        v
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::Layers;
+
+    // 2 x 2 chunks of 8 tiles, the chunk moved in has no tiles of its own
+    fn config() -> WorldConfig {
+        let mut config = WorldConfig::new();
+        config.world_size = 2;
+        config.chunk_size = 8;
+        config
+    }
+    // an entity walking 1 tile a step at most
+    fn walker(x: f32, y: f32) -> Entity {
+        let mut entity = Entity::new(0);
+        entity.coords = Coords::from((x, y));
+        entity.stats = Stats::from(10, 10, 10);
+        entity
+    }
+    // x a step from the middle of tile 4 4, on flat grass with one tile changed
+    fn pace(changed: (usize, usize), ttype: TileType, height: f32) -> f32 {
+        let mut layers = Layers::new(16);
+        layers.heights.set(changed.0, changed.1, height);
+        layers.ttypes[changed.1 * 16 + changed.0] = ttype;
+        let terrain = Terrain::from(layers);
+        let mut entity = walker(4.5, 4.5);
+        entity.vel = (0.5, 0.0);
+        step(&mut entity, &Chunk::new(), &terrain, &config());
+        entity.coords.x - 4.5
+    }
+
+    #[test]
+    fn positions_stay_in_the_world() {
+        let terrain = Terrain::from(Layers::new(16));
+        let mut chunk = Chunk::new();
+        for (x, y, tx, ty) in [
+            (0.2, 0.2, -10.0, -10.0),
+            (15.8, 0.2, 30.0, -10.0),
+            (0.2, 15.8, -10.0, 30.0),
+            (15.8, 15.8, 30.0, 30.0),
+        ] {
+            let mut entity = walker(x, y);
+            entity.target = Some(Coords::from((tx, ty)));
+            chunk.entities.push(entity);
+        }
+        chunk.entities.push(walker(8.0, 8.0));
+        for _ in 0..100 {
+            resolve(&mut chunk, &terrain, &config());
+            for e in &chunk.entities {
+                assert!((0.0..16.0).contains(&e.coords.x), "{}", e.coords.x);
+                assert!((0.0..16.0).contains(&e.coords.y), "{}", e.coords.y);
+            }
+        }
+    }
+
+    #[test]
+    fn water_and_walls_are_not_entered() {
+        for ttype in [TileType::Lake, TileType::River, TileType::WoodenWall] {
+            let mut layers = Layers::new(16);
+            for y in 0..16 {
+                layers.ttypes[y * 16 + 8] = ttype.clone();
+            }
+            let terrain = Terrain::from(layers);
+            let mut chunk = Chunk::new();
+            let mut entity = walker(5.5, 8.5);
+            entity.target = Some(Coords::from((12.5, 8.5)));
+            chunk.entities.push(entity);
+            for _ in 0..50 {
+                resolve(&mut chunk, &terrain, &config());
+                let e = &chunk.entities[0];
+                assert!(e.coords.x < 8.0, "{:?} at {}", ttype, e.coords.x);
+            }
+            // it got as close as it could
+            assert!(chunk.entities[0].coords.x > 7.0);
+        }
+    }
+
+    #[test]
+    fn uphill_is_slower_and_road_is_faster() {
+        let flat = pace((5, 4), TileType::Grass, 0.0);
+        assert!((flat - 0.5).abs() < 1e-5);
+        let uphill = pace((5, 4), TileType::Grass, 1.0);
+        assert!((uphill - 0.5 / (1.0 + UPHILL_COST)).abs() < 1e-5);
+        // going down is not slower
+        assert!((pace((5, 4), TileType::Grass, -1.0) - flat).abs() < 1e-5);
+        let road = pace((4, 4), TileType::Road, 0.0);
+        assert!((road - 0.5 * ROAD_SPEED).abs() < 1e-5);
+    }
+
+    #[test]
+    fn seek_reaches_its_target() {
+        let entity = walker(2.5, 2.5);
+        let steer = seek(&entity, &Coords::from((5.5, 6.5)));
+        assert!((steer.0 - 0.6).abs() < 1e-5 && (steer.1 - 0.8).abs() < 1e-5);
+
+        let terrain = Terrain::from(Layers::new(16));
+        let mut chunk = Chunk::new();
+        let mut entity = walker(2.5, 2.5);
+        entity.target = Some(Coords::from((13.5, 3.5)));
+        entity.path = vec![Coords::from((13.5, 11.5))];
+        chunk.entities.push(entity);
+        let arrived = (0..100).find(|_| {
+            resolve(&mut chunk, &terrain, &config());
+            chunk.entities[0].target.is_none()
+        });
+        assert!(arrived.is_some());
+        let e = &chunk.entities[0];
+        assert!(euclid(&e.coords, &Coords::from((13.5, 11.5))) < 1.0);
+        assert!(e.path.is_empty());
+    }
+}
@@ -0,0 +1,120 @@
+use crate::config::WorldConfig;
+use crate::math::euclid;
+use crate::roads;
+use crate::spatial::SpatialHash;
+use crate::worldgen::{Chunk, Entity, Inventory, Item};
+
+// below this hunger an entity eats, or looks for food when it has none
+const HUNGRY: u8 = 40;
+// hunger one bread fills
+const BREAD: u8 = 60;
+// bread a farmer carries at most, it stops farming with its hands full
+pub const CARRY: i32 = 5;
+// bread a farmer keeps for itself, the rest goes to its faction's store
+const KEEP: i32 = 2;
+// bread a faction's store in one chunk holds at most
+const STORE_LIMIT: i32 = 200;
+// coins one bread costs when bought from someone else
+const PRICE: i32 = 2;
+
+pub fn is_hungry(entity: &Entity) -> bool {
+    entity.stats.hunger < HUNGRY
+}
+pub fn is_farmer(entity: &Entity) -> bool {
+    entity.tasks.farm.1 && entity.tasks.farm.0 > 0
+}
+// bread an entity can give away without going hungry itself
+fn spare(entity: &Entity) -> i32 {
+    if is_hungry(entity) {
+        0
+    } else {
+        (entity.inventory.count(&Item::Bread) - 1).max(0)
+    }
+}
+// one step of the food loop in a chunk: farmers bring the bread they do not
+// need to their faction's store, hungry entities eat what they
+// carry, take from the store or buy from someone nearby, and walk to the
+// closest seller when nobody is near, by road when it is far
+pub fn resolve(chunk: &mut Chunk, config: &WorldConfig) {
+    let stores = &mut chunk.stores;
+    for entity in &mut chunk.entities {
+        let faction = &entity.alignment.faction;
+        let bread = entity.inventory.count(&Item::Bread);
+        if is_hungry(entity) {
+            if let Some(store) = stores.get_mut(faction) {
+                if bread == 0 && store.count(&Item::Bread) > 0 {
+                    store.add(Item::Bread, -1);
+                    entity.inventory.add(Item::Bread, 1);
+                }
+            }
+        } else if is_farmer(entity) && bread > KEEP {
+            let store = stores.entry(faction.clone()).or_insert_with(Inventory::new);
+            let given = (bread - KEEP).min(STORE_LIMIT - store.count(&Item::Bread));
+            if given > 0 {
+                entity.inventory.add(Item::Bread, -given);
+                store.add(Item::Bread, given);
+            }
+        }
+        eat(entity);
+    }
+    trade(chunk, config);
+}
+fn eat(entity: &mut Entity) {
+    if is_hungry(entity) && entity.inventory.count(&Item::Bread) > 0 {
+        entity.inventory.add(Item::Bread, -1);
+        entity.stats.feed(BREAD);
+    }
+}
+// hungry entities that still have nothing to eat buy a bread from the
+// closest entity within the vicinity distance that has one to spare. the
+// trades are all agreed on first and carried out afterwards
+fn trade(chunk: &mut Chunk, config: &WorldConfig) {
+    let vicinity = config.vicinity_dist as f32;
+    let grid = SpatialHash::from(&chunk.entities, vicinity);
+    let mut stock: Vec<i32> = chunk.entities.iter().map(spare).collect();
+    let mut trades = vec![];
+    let mut seeking = vec![];
+    for (i, buyer) in chunk.entities.iter().enumerate() {
+        if !is_hungry(buyer)
+            || buyer.inventory.count(&Item::Bread) > 0
+            || buyer.inventory.get_coins() < PRICE
+        {
+            continue;
+        }
+        let closest = |candidates: &mut dyn Iterator<Item = usize>| {
+            candidates
+                .filter(|j| *j != i && stock[*j] > 0)
+                .min_by(|a, b| {
+                    euclid(&buyer.coords, &chunk.entities[*a].coords)
+                        .total_cmp(&euclid(&buyer.coords, &chunk.entities[*b].coords))
+                })
+        };
+        match closest(&mut grid.near(&buyer.coords, vicinity).into_iter()) {
+            Some(seller) => {
+                stock[seller] -= 1;
+                trades.push((i, seller));
+            }
+            None => {
+                if buyer.target.is_none() {
+                    if let Some(seller) = closest(&mut (0..chunk.entities.len())) {
+                        seeking.push((i, seller));
+                    }
+                }
+            }
+        }
+    }
+    for (buyer, seller) in trades {
+        chunk.entities[seller].inventory.add(Item::Bread, -1);
+        chunk.entities[seller].inventory.add(Item::Coin, PRICE);
+        chunk.entities[buyer].inventory.add(Item::Coin, -PRICE);
+        chunk.entities[buyer].inventory.add(Item::Bread, 1);
+        eat(&mut chunk.entities[buyer]);
+    }
+    for (buyer, seller) in seeking {
+        let (at, to) = (chunk.entities[buyer].coords, chunk.entities[seller].coords);
+        chunk.entities[buyer].target = Some(to);
+        if roads::is_long(&at, &to) {
+            chunk.walks.push((chunk.entities[buyer].index, to));
+        }
+    }
+}
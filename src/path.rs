@@ -0,0 +1,501 @@
+use crate::config::WorldConfig;
+use crate::math::{NEIGHBOURS_4, NEIGHBOURS_8};
+use crate::terrain::Terrain;
+use crate::worldgen::{Coords, TileType};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+// component label of tiles that cannot be walked on
+const BLOCKED: u32 = u32::MAX;
+// cost of climbing one unit of height, going down is free
+const UPHILL: f32 = 2.0;
+// cheapest cost of a straight step, keeps the heuristic admissible
+pub const MIN_COST: f32 = 0.5;
+
+// cost of walking across one tile of the type, None when it cannot be entered
+pub fn tile_cost(ttype: &TileType) -> Option<f32> {
+    match ttype {
+        TileType::Road | TileType::Bridge => Some(0.5),
+        TileType::Grass | TileType::Steppe => Some(1.0),
+        TileType::WoodenFloor | TileType::WoodenDoor => Some(1.0),
+        TileType::Desert | TileType::Tundra => Some(1.5),
+        TileType::TemperateForest | TileType::Taiga => Some(2.0),
+        TileType::Marsh => Some(3.0),
+        TileType::Glacier => Some(4.0),
+        TileType::River | TileType::Lake | TileType::Ocean | TileType::WoodenWall => None,
+    }
+}
+// cost of a step between two neighbouring tiles
+pub fn step_cost(terrain: &Terrain, from: (usize, usize), to: (usize, usize)) -> Option<f32> {
+    let cost = tile_cost(&terrain.ttype(to.0, to.1))?;
+    let length = if from.0 != to.0 && from.1 != to.1 {
+        std::f32::consts::SQRT_2
+    } else {
+        1.0
+    };
+    let climb = (terrain.height(to.0, to.1) - terrain.height(from.0, from.1)).max(0.0);
+    Some(length * cost + climb * UPHILL)
+}
+// a group of walkable tiles in one chunk touching a group in the next chunk
+type Link = ((usize, u32), (usize, u32));
+
+// connectivity of every chunk that has been looked at: which tiles of a chunk
+// reach each other without leaving it, and which of those groups touch
+// groups in the neighbouring chunks. long searches first look for a route
+// over these groups and then only search the tiles along it
+pub struct PathCache {
+    chunk_size: usize,
+    world_size: usize,
+    labels: HashMap<usize, Vec<u32>>,
+    links: HashMap<usize, Vec<Link>>,
+}
+impl PathCache {
+    pub fn from(config: &WorldConfig) -> PathCache {
+        PathCache {
+            chunk_size: config.chunk_size as usize,
+            world_size: config.world_size as usize,
+            labels: HashMap::new(),
+            links: HashMap::new(),
+        }
+    }
+    // drops what is known about a chunk after its tiles changed
+    pub fn invalidate(&mut self, chunk: usize) {
+        self.labels.remove(&chunk);
+        self.links.remove(&chunk);
+        for n in self.neighbours(chunk) {
+            self.links.remove(&n);
+        }
+    }
+    fn chunk_of(&self, x: usize, y: usize) -> usize {
+        y / self.chunk_size * self.world_size + x / self.chunk_size
+    }
+    fn neighbours(&self, chunk: usize) -> Vec<usize> {
+        let (cx, cy) = (chunk % self.world_size, chunk / self.world_size);
+        let mut neighbours = vec![];
+        if cx > 0 {
+            neighbours.push(chunk - 1);
+        }
+        if cx + 1 < self.world_size {
+            neighbours.push(chunk + 1);
+        }
+        if cy > 0 {
+            neighbours.push(chunk - self.world_size);
+        }
+        if cy + 1 < self.world_size {
+            neighbours.push(chunk + self.world_size);
+        }
+        neighbours
+    }
+    // flood fills the chunk's walkable tiles into groups
+    fn labels(&mut self, terrain: &Terrain, chunk: usize) -> &Vec<u32> {
+        let size = self.chunk_size;
+        let (ox, oy) = (
+            chunk % self.world_size * size,
+            chunk / self.world_size * size,
+        );
+        self.labels.entry(chunk).or_insert_with(|| {
+            let mut labels = vec![BLOCKED; size * size];
+            let mut next = 0;
+            for start in 0..size * size {
+                let (x, y) = (ox + start % size, oy + start / size);
+                if labels[start] != BLOCKED || tile_cost(&terrain.ttype(x, y)).is_none() {
+                    continue;
+                }
+                labels[start] = next;
+                let mut open = vec![start];
+                while let Some(t) = open.pop() {
+                    let (tx, ty) = (t % size, t / size);
+                    for (nx, ny) in [
+                        (tx.wrapping_sub(1), ty),
+                        (tx + 1, ty),
+                        (tx, ty.wrapping_sub(1)),
+                        (tx, ty + 1),
+                    ] {
+                        if nx >= size || ny >= size {
+                            continue;
+                        }
+                        let n = ny * size + nx;
+                        if labels[n] == BLOCKED
+                            && tile_cost(&terrain.ttype(ox + nx, oy + ny)).is_some()
+                        {
+                            labels[n] = next;
+                            open.push(n);
+                        }
+                    }
+                }
+                next += 1;
+            }
+            labels
+        })
+    }
+    fn label(&mut self, terrain: &Terrain, x: usize, y: usize) -> u32 {
+        let chunk = self.chunk_of(x, y);
+        let size = self.chunk_size;
+        self.labels(terrain, chunk)[(y % size) * size + x % size]
+    }
+    // groups of this chunk and the groups across its borders they touch
+    fn links(&mut self, terrain: &Terrain, chunk: usize) -> Vec<Link> {
+        if let Some(links) = self.links.get(&chunk) {
+            return links.clone();
+        }
+        let size = self.chunk_size;
+        let (ox, oy) = (
+            chunk % self.world_size * size,
+            chunk / self.world_size * size,
+        );
+        let (cx, cy) = (chunk % self.world_size, chunk / self.world_size);
+        let mut links = HashSet::new();
+        for (dx, dy) in NEIGHBOURS_4 {
+            let (nx, ny) = (cx as i64 + dx, cy as i64 + dy);
+            if nx < 0 || ny < 0 || nx >= self.world_size as i64 || ny >= self.world_size as i64 {
+                continue;
+            }
+            let n = ny as usize * self.world_size + nx as usize;
+            for i in 0..size {
+                // the tile on this side of the border and the one across
+                let (here, there) = match (dx, dy) {
+                    (-1, _) => ((ox, oy + i), (ox - 1, oy + i)),
+                    (1, _) => ((ox + size - 1, oy + i), (ox + size, oy + i)),
+                    (_, -1) => ((ox + i, oy), (ox + i, oy - 1)),
+                    _ => ((ox + i, oy + size - 1), (ox + i, oy + size)),
+                };
+                let a = self.label(terrain, here.0, here.1);
+                let b = self.label(terrain, there.0, there.1);
+                if a != BLOCKED && b != BLOCKED {
+                    links.insert(((chunk, a), (n, b)));
+                }
+            }
+        }
+        // sorted so searches do not depend on hash order
+        let mut links: Vec<Link> = links.into_iter().collect();
+        links.sort();
+        self.links.insert(chunk, links.clone());
+        links
+    }
+    // chunks on a route between two groups, None when there is no route
+    fn corridor(
+        &mut self,
+        terrain: &Terrain,
+        from: (usize, u32),
+        to: (usize, u32),
+    ) -> Option<Vec<usize>> {
+        let ws = self.world_size;
+        let centre = |c: usize| ((c % ws) as f32, (c / ws) as f32);
+        let estimate = |c: usize| {
+            let (a, b) = (centre(c), centre(to.0));
+            ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt()
+        };
+        let mut open = BinaryHeap::new();
+        let mut cost: HashMap<(usize, u32), f32> = HashMap::new();
+        let mut came_from: HashMap<(usize, u32), (usize, u32)> = HashMap::new();
+        cost.insert(from, 0.0);
+        open.push(Open(estimate(from.0), from.0, from.1));
+        while let Some(Open(_, chunk, label)) = open.pop() {
+            let node = (chunk, label);
+            if node == to {
+                let mut chunks = vec![chunk];
+                let mut node = node;
+                while let Some(previous) = came_from.get(&node) {
+                    chunks.push(previous.0);
+                    node = *previous;
+                }
+                chunks.reverse();
+                return Some(chunks);
+            }
+            let here = cost[&node];
+            for (a, b) in self.links(terrain, chunk) {
+                if a != node {
+                    continue;
+                }
+                let next = here + 1.0;
+                if next < *cost.get(&b).unwrap_or(&f32::INFINITY) {
+                    cost.insert(b, next);
+                    came_from.insert(b, node);
+                    open.push(Open(next + estimate(b.0), b.0, b.1));
+                }
+            }
+        }
+        None
+    }
+    // cheapest walk between two tiles as tile centres, without the start.
+    // None when either end cannot be walked on or the two are not connected
+    pub fn find(
+        &mut self,
+        terrain: &Terrain,
+        from: (usize, usize),
+        to: (usize, usize),
+    ) -> Option<Vec<Coords>> {
+        let side = terrain.side();
+        if from.0 >= side || from.1 >= side || to.0 >= side || to.1 >= side {
+            return None;
+        }
+        let start = (
+            self.chunk_of(from.0, from.1),
+            self.label(terrain, from.0, from.1),
+        );
+        let goal = (self.chunk_of(to.0, to.1), self.label(terrain, to.0, to.1));
+        if start.1 == BLOCKED || goal.1 == BLOCKED {
+            return None;
+        }
+        // the tile search may also use the chunks next to the route so it is
+        // not forced to hug chunk borders
+        let mut allowed = HashSet::new();
+        for chunk in self.corridor(terrain, start, goal)? {
+            let (cx, cy) = (
+                (chunk % self.world_size) as i64,
+                (chunk / self.world_size) as i64,
+            );
+            for (dx, dy) in [
+                (-1, -1),
+                (0, -1),
+                (1, -1),
+                (-1, 0),
+                (0, 0),
+                (1, 0),
+                (-1, 1),
+                (0, 1),
+                (1, 1),
+            ] {
+                let (nx, ny) = (cx + dx, cy + dy);
+                if nx >= 0 && ny >= 0 && nx < self.world_size as i64 && ny < self.world_size as i64
+                {
+                    allowed.insert(ny as usize * self.world_size + nx as usize);
+                }
+            }
+        }
+        let path = astar(
+            side,
+            from,
+            to,
+            |x, y| allowed.contains(&self.chunk_of(x, y)),
+            |a, b| step_cost(terrain, a, b),
+            MIN_COST,
+        )?;
+        Some(
+            path.into_iter()
+                .map(|i| Coords::from(((i % side) as f32 + 0.5, (i / side) as f32 + 0.5)))
+                .collect(),
+        )
+    }
+}
+// a* over tiles with diagonal steps, a diagonal step needs both tiles beside
+// it to be enterable so paths never squeeze between two corners. `cost` is the
+// cost of a step between neighbours, None when it cannot be taken, and
+// `min_cost` the cheapest a straight step can be. returns the indices of the
+// tiles after the start in a world `side` tiles wide
+pub fn astar(
+    side: usize,
+    from: (usize, usize),
+    to: (usize, usize),
+    allowed: impl Fn(usize, usize) -> bool,
+    cost: impl Fn((usize, usize), (usize, usize)) -> Option<f32>,
+    min_cost: f32,
+) -> Option<Vec<usize>> {
+    let came_from = search(side, from, Some(to), allowed, cost, min_cost);
+    let (start, goal) = (from.1 * side + from.0, to.1 * side + to.0);
+    if !came_from.contains_key(&goal) {
+        return None;
+    }
+    let mut path = vec![];
+    let mut i = goal;
+    while i != start {
+        path.push(i);
+        i = came_from[&i];
+    }
+    path.reverse();
+    Some(path)
+}
+// every tile that can be walked to from a tile over the allowed tiles, with
+// the same steps as astar, each with the tile it is entered from on the
+// cheapest way there. the start is entered from itself
+pub fn reachable(
+    side: usize,
+    from: (usize, usize),
+    allowed: impl Fn(usize, usize) -> bool,
+    cost: impl Fn((usize, usize), (usize, usize)) -> Option<f32>,
+) -> HashMap<usize, usize> {
+    search(side, from, None, allowed, cost, 0.0)
+}
+// the tile each tile reached is entered from, until the goal is taken out of
+// the open set or, without one, until every tile that can be reached is
+fn search(
+    side: usize,
+    from: (usize, usize),
+    to: Option<(usize, usize)>,
+    allowed: impl Fn(usize, usize) -> bool,
+    step_cost: impl Fn((usize, usize), (usize, usize)) -> Option<f32>,
+    min_cost: f32,
+) -> HashMap<usize, usize> {
+    let estimate = |x: usize, y: usize| match to {
+        Some(to) => {
+            let dx = (x as f32 - to.0 as f32).abs();
+            let dy = (y as f32 - to.1 as f32).abs();
+            (dx.max(dy) + (std::f32::consts::SQRT_2 - 1.0) * dx.min(dy)) * min_cost
+        }
+        None => 0.0,
+    };
+    let mut open = BinaryHeap::new();
+    let mut cost: HashMap<usize, f32> = HashMap::new();
+    let mut came_from: HashMap<usize, usize> = HashMap::new();
+    let start = from.1 * side + from.0;
+    let goal = to.map(|to| to.1 * side + to.0);
+    cost.insert(start, 0.0);
+    came_from.insert(start, start);
+    open.push(Open(estimate(from.0, from.1), start, 0));
+    while let Some(Open(_, i, _)) = open.pop() {
+        if Some(i) == goal {
+            break;
+        }
+        let (x, y) = (i % side, i / side);
+        let here = cost[&i];
+        for (dx, dy) in NEIGHBOURS_8 {
+            let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+            if nx < 0 || ny < 0 || nx >= side as i64 || ny >= side as i64 {
+                continue;
+            }
+            let (nx, ny) = (nx as usize, ny as usize);
+            if !allowed(nx, ny) {
+                continue;
+            }
+            if dx != 0
+                && dy != 0
+                && (step_cost((x, y), (nx, y)).is_none() || step_cost((x, y), (x, ny)).is_none())
+            {
+                continue;
+            }
+            let step = match step_cost((x, y), (nx, ny)) {
+                Some(step) => step,
+                None => continue,
+            };
+            let n = ny * side + nx;
+            let next = here + step;
+            if next < *cost.get(&n).unwrap_or(&f32::INFINITY) {
+                cost.insert(n, next);
+                came_from.insert(n, i);
+                open.push(Open(next + estimate(nx, ny), n, 0));
+            }
+        }
+    }
+    came_from
+}
+// entry of the open sets, the cheapest estimate comes out of the heap first
+struct Open(f32, usize, u32);
+impl PartialEq for Open {
+    fn eq(&self, other: &Open) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for Open {}
+impl PartialOrd for Open {
+    fn partial_cmp(&self, other: &Open) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Open {
+    fn cmp(&self, other: &Open) -> Ordering {
+        other
+            .0
+            .total_cmp(&self.0)
+            .then_with(|| other.1.cmp(&self.1))
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terrain::Layers;
+
+    // flat grass, 2 x 2 chunks of 8 tiles
+    fn world() -> (Terrain, PathCache) {
+        let mut config = WorldConfig::new();
+        config.world_size = 2;
+        config.chunk_size = 8;
+        let terrain = Terrain::from(Layers::new(16));
+        (terrain, PathCache::from(&config))
+    }
+    fn set(terrain: &mut Terrain, x: usize, y: usize, ttype: TileType) {
+        terrain.set_ttype(x, y, ttype);
+    }
+    fn tile(c: &Coords) -> (usize, usize) {
+        (c.x as usize, c.y as usize)
+    }
+    // every step goes to a neighbouring walkable tile
+    fn walkable(terrain: &Terrain, from: (usize, usize), path: &[Coords]) -> bool {
+        let mut here = from;
+        path.iter().all(|c| {
+            let next = tile(c);
+            let near = here.0.abs_diff(next.0) <= 1 && here.1.abs_diff(next.1) <= 1;
+            here = next;
+            near && tile_cost(&terrain.ttype(next.0, next.1)).is_some()
+        })
+    }
+
+    #[test]
+    fn path_crosses_chunk_seam() {
+        let (terrain, mut cache) = world();
+        let path = cache.find(&terrain, (2, 3), (13, 12)).unwrap();
+        assert!(walkable(&terrain, (2, 3), &path));
+        assert_eq!(tile(path.last().unwrap()), (13, 12));
+        // straight across on flat grass, diagonals first
+        assert_eq!(path.len(), 11);
+    }
+
+    #[test]
+    fn unreachable_goal_found_from_corridor() {
+        let (mut terrain, mut cache) = world();
+        for y in 0..16 {
+            set(&mut terrain, 8, y, TileType::River);
+        }
+        let start = (0, cache.label(&terrain, 2, 2));
+        let goal = (1, cache.label(&terrain, 12, 2));
+        assert!(cache.corridor(&terrain, start, goal).is_none());
+        assert!(cache.find(&terrain, (2, 2), (12, 2)).is_none());
+    }
+
+    #[test]
+    fn path_detours_around_water_and_walls() {
+        let (mut terrain, mut cache) = world();
+        for y in 0..13 {
+            set(&mut terrain, 5, y, TileType::Lake);
+            set(&mut terrain, 10, 15 - y, TileType::WoodenWall);
+        }
+        let path = cache.find(&terrain, (2, 2), (13, 2)).unwrap();
+        assert!(walkable(&terrain, (2, 2), &path));
+        assert_eq!(tile(path.last().unwrap()), (13, 2));
+        assert!(path.iter().any(|c| tile(c) == (5, 13)));
+        assert!(path.iter().any(|c| tile(c) == (10, 2)));
+    }
+
+    #[test]
+    fn building_invalidates_cache() {
+        let (mut terrain, mut cache) = world();
+        for y in 0..16 {
+            set(&mut terrain, 3, y, TileType::WoodenWall);
+        }
+        assert!(cache.find(&terrain, (1, 1), (6, 1)).is_none());
+        set(&mut terrain, 3, 4, TileType::WoodenDoor);
+        // the cached groups still split the chunk until it is invalidated
+        assert!(cache.find(&terrain, (1, 1), (6, 1)).is_none());
+        cache.invalidate(0);
+        let path = cache.find(&terrain, (1, 1), (6, 1)).unwrap();
+        assert!(path.iter().any(|c| tile(c) == (3, 4)));
+    }
+
+    #[test]
+    fn reachable_stops_at_water_and_squeezed_corners() {
+        let (mut terrain, _) = world();
+        // a diagonal river, its corners touch and cannot be slipped between
+        for i in 0..16 {
+            set(&mut terrain, i, 15 - i, TileType::River);
+        }
+        let reached = reachable(16, (2, 2), |_, _| true, |a, b| step_cost(&terrain, a, b));
+        for y in 0..16 {
+            for x in 0..16 {
+                assert_eq!(reached.contains_key(&(y * 16 + x)), x + y < 15);
+            }
+        }
+        assert_eq!(reached[&(2 * 16 + 2)], 2 * 16 + 2);
+        // a tile is entered from a neighbour
+        let (x, y) = (reached[&(3 * 16 + 4)] % 16, reached[&(3 * 16 + 4)] / 16);
+        assert!(x.abs_diff(4) <= 1 && y.abs_diff(3) <= 1);
+    }
+}
@@ -0,0 +1,255 @@
+use crate::codec::{self, FORMAT_VERSION};
+use crate::worldgen::Chunk;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+pub const REGION_MAGIC: &[u8; 4] = b"WGR2";
+// magic, version and region size
+const HEADER_LEN: u64 = 6;
+// offset, length and room of one chunk
+const ENTRY_LEN: u64 = 16;
+// room left after a chunk for it to grow into, a quarter of its length
+const SLACK: usize = 4;
+
+// chunks grouped `region_size` by `region_size` into one file each. a file
+// starts with an offset table with one entry per chunk slot followed by the
+// encoded chunks. a rewritten chunk goes back into its old place when it
+// fits there or that place is at the end of the file, otherwise it is
+// appended and the table is pointed at the new copy
+pub struct RegionStore {
+    pub dir: PathBuf,
+    pub region_size: usize,
+    pub world_size: usize,
+}
+impl RegionStore {
+    pub fn from(dir: &Path, region_size: usize, world_size: usize) -> Result<RegionStore, String> {
+        fs::create_dir_all(dir).map_err(|e| format!("cannot create {}: {}", dir.display(), e))?;
+        Ok(RegionStore {
+            dir: dir.to_path_buf(),
+            region_size: region_size.clamp(1, 255),
+            world_size,
+        })
+    }
+    // file holding a chunk and the chunk's slot in its offset table
+    fn locate(&self, index: usize) -> (PathBuf, u64) {
+        let (cx, cy) = (index % self.world_size, index / self.world_size);
+        let (rx, ry) = (cx / self.region_size, cy / self.region_size);
+        let slot = (cy % self.region_size) * self.region_size + cx % self.region_size;
+        let path = self.dir.join(format!("r.{}.{}.bin", rx, ry));
+        (path, HEADER_LEN + slot as u64 * ENTRY_LEN)
+    }
+    fn open(&self, path: &Path) -> Result<File, String> {
+        let exists = path.exists();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(|e| format!("cannot open {}: {}", path.display(), e))?;
+        if !exists {
+            let slots = (self.region_size * self.region_size) as u64;
+            let mut header = REGION_MAGIC.to_vec();
+            header.push(FORMAT_VERSION);
+            header.push(self.region_size as u8);
+            header.resize((HEADER_LEN + slots * ENTRY_LEN) as usize, 0);
+            file.write_all(&header).map_err(|e| e.to_string())?;
+        } else {
+            let mut header = [0u8; HEADER_LEN as usize];
+            file.read_exact(&mut header).map_err(|e| e.to_string())?;
+            if &header[0..4] != REGION_MAGIC
+                || header[4] != FORMAT_VERSION
+                || header[5] as usize != self.region_size
+            {
+                return Err(format!(
+                    "{} is not a compatible region file",
+                    path.display()
+                ));
+            }
+        }
+        Ok(file)
+    }
+    pub fn contains(&self, index: usize) -> bool {
+        matches!(self.read_entry(index), Ok(Some(_)))
+    }
+    fn read_entry(&self, index: usize) -> Result<Option<Entry>, String> {
+        let (path, slot) = self.locate(index);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let mut file = self.open(&path)?;
+        let entry = Entry::read(&mut file, slot)?;
+        Ok(if entry.len == 0 { None } else { Some(entry) })
+    }
+    pub fn read(&self, index: usize) -> Result<Option<Chunk>, String> {
+        let entry = match self.read_entry(index)? {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        let (path, _) = self.locate(index);
+        let mut file = self.open(&path)?;
+        // a damaged table must not make us allocate more than the file holds
+        let size = file.metadata().map_err(|e| e.to_string())?.len();
+        if entry.offset.saturating_add(entry.len as u64) > size {
+            return Err(format!(
+                "chunk {} lies past the end of {}",
+                index,
+                path.display()
+            ));
+        }
+        let mut bytes = vec![0u8; entry.len as usize];
+        file.seek(SeekFrom::Start(entry.offset))
+            .and_then(|_| file.read_exact(&mut bytes))
+            .map_err(|e| e.to_string())?;
+        codec::decode(&bytes).map(Some)
+    }
+    pub fn write(&self, chunk: &Chunk) -> Result<(), String> {
+        let (path, slot) = self.locate(chunk.index);
+        let mut file = self.open(&path)?;
+        let bytes = codec::encode(chunk);
+        let old = Entry::read(&mut file, slot)?;
+        let end = file.seek(SeekFrom::End(0)).map_err(|e| e.to_string())?;
+        let entry = if old.len > 0 && bytes.len() <= old.room as usize {
+            Entry {
+                len: bytes.len() as u32,
+                ..old
+            }
+        } else {
+            let room = (bytes.len() + bytes.len() / SLACK) as u32;
+            let last = old.len > 0 && old.offset + old.room as u64 == end;
+            Entry {
+                offset: if last { old.offset } else { end },
+                len: bytes.len() as u32,
+                room,
+            }
+        };
+        let mut data = bytes;
+        data.resize(entry.room as usize, 0);
+        file.seek(SeekFrom::Start(entry.offset))
+            .and_then(|_| file.write_all(&data))
+            .map_err(|e| e.to_string())?;
+        entry.write(&mut file, slot)
+    }
+}
+// where a chunk lies in its region file, `room` is the space it may fill
+// before it has to move
+struct Entry {
+    offset: u64,
+    len: u32,
+    room: u32,
+}
+impl Entry {
+    fn read(file: &mut File, slot: u64) -> Result<Entry, String> {
+        let mut entry = [0u8; ENTRY_LEN as usize];
+        file.seek(SeekFrom::Start(slot))
+            .and_then(|_| file.read_exact(&mut entry))
+            .map_err(|e| e.to_string())?;
+        Ok(Entry {
+            offset: u64::from_le_bytes(entry[0..8].try_into().unwrap()),
+            len: u32::from_le_bytes(entry[8..12].try_into().unwrap()),
+            room: u32::from_le_bytes(entry[12..16].try_into().unwrap()),
+        })
+    }
+    fn write(&self, file: &mut File, slot: u64) -> Result<(), String> {
+        let mut entry = self.offset.to_le_bytes().to_vec();
+        entry.extend_from_slice(&self.len.to_le_bytes());
+        entry.extend_from_slice(&self.room.to_le_bytes());
+        file.seek(SeekFrom::Start(slot))
+            .and_then(|_| file.write_all(&entry))
+            .map_err(|e| e.to_string())
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::WorldConfig;
+    use crate::worldgen::{worldgen, Entity};
+    use std::env;
+    use std::process;
+
+    fn store(name: &str) -> RegionStore {
+        let dir = env::temp_dir().join(format!("worldgen2-test-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        RegionStore::from(&dir, 2, 4).unwrap()
+    }
+    fn chunks() -> Vec<Chunk> {
+        let mut config = WorldConfig::new();
+        config.world_size = 4;
+        config.chunk_size = 8;
+        config.erosion_droplets = 100;
+        let mut world = worldgen(5, &config).unwrap();
+        (0..16).map(|i| world.fetch_chunk(i).clone()).collect()
+    }
+    fn size(store: &RegionStore, index: usize) -> u64 {
+        fs::metadata(store.locate(index).0).unwrap().len()
+    }
+    #[test]
+    fn write_and_read_back() {
+        let store = store("rw");
+        let chunks = chunks();
+        for i in [0, 5, 6, 15] {
+            store.write(&chunks[i]).unwrap();
+        }
+        for i in [0, 5, 6, 15] {
+            let read = store.read(i).unwrap().unwrap();
+            assert_eq!(codec::encode(&read), codec::encode(&chunks[i]));
+        }
+        assert!(store.contains(5));
+        assert!(!store.contains(4));
+        assert!(store.read(4).unwrap().is_none());
+        let _ = fs::remove_dir_all(&store.dir);
+    }
+    #[test]
+    fn rewrites_reuse_their_place() {
+        let store = store("reuse");
+        let mut chunks = chunks();
+        store.write(&chunks[0]).unwrap();
+        store.write(&chunks[1]).unwrap();
+        let before = size(&store, 0);
+        // the same chunk and a slightly smaller or larger one fit where it was
+        store.write(&chunks[0]).unwrap();
+        chunks[0].entities.push(Entity::new(1));
+        store.write(&chunks[0]).unwrap();
+        chunks[0].entities.clear();
+        store.write(&chunks[0]).unwrap();
+        assert_eq!(size(&store, 0), before);
+        // the last chunk of the file grows in place
+        for i in 0..100 {
+            chunks[1].entities.push(Entity::new(i));
+        }
+        store.write(&chunks[1]).unwrap();
+        let grown = size(&store, 0);
+        chunks[1].entities.truncate(50);
+        store.write(&chunks[1]).unwrap();
+        assert_eq!(size(&store, 0), grown);
+        assert_eq!(store.read(1).unwrap().unwrap().entities.len(), 50);
+        assert_eq!(store.read(0).unwrap().unwrap().entities.len(), 0);
+        let _ = fs::remove_dir_all(&store.dir);
+    }
+    #[test]
+    fn damaged_files_fail() {
+        let store = store("damaged");
+        let chunks = chunks();
+        store.write(&chunks[0]).unwrap();
+        let (path, slot) = store.locate(0);
+        let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+        // a length far past the end of the file
+        file.seek(SeekFrom::Start(slot + 8)).unwrap();
+        file.write_all(&u32::MAX.to_le_bytes()).unwrap();
+        assert!(store.read(0).is_err());
+        // garbage where the chunk is
+        file.seek(SeekFrom::Start(slot + 8)).unwrap();
+        file.write_all(&8u32.to_le_bytes()).unwrap();
+        let offset = HEADER_LEN + 4 * ENTRY_LEN;
+        file.seek(SeekFrom::Start(offset)).unwrap();
+        file.write_all(&[0xff; 8]).unwrap();
+        assert!(store.read(0).is_err());
+        // a file of another format
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.write_all(b"WGR1").unwrap();
+        assert!(store.read(0).is_err());
+        assert!(store.write(&chunks[0]).is_err());
+        let _ = fs::remove_dir_all(&store.dir);
+    }
+}
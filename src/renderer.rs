@@ -1,56 +1,42 @@
 use crate::bitmap::*;
+use crate::camera::Camera;
+use crate::config::WorldConfig;
 use crate::util::{MainMsg, RenderMsg};
-use crate::worldgen::{Chunk, Coords, Faction, CHUNK_SIZE, TILE_SIZE};
-use lazy_static::lazy_static;
+use crate::worldgen::{Chunk, Faction, TileType};
 use sdl2::event::{Event, WindowEvent};
 use sdl2::keyboard::Keycode;
 use sdl2::pixels::Color;
 use sdl2::rect::Rect;
-use std::collections::HashMap;
 use std::time::Duration;
-lazy_static! {
-    pub static ref WINDOW_WIDTH: u32 = 1240;
-    pub static ref WINDOW_HEIGHT: u32 = 760;
-    pub static ref DEFAULT_ZOOM: i32 = 1;
-    pub static ref CAMERA_STEP: f32 = 32.0;
-}
-#[derive(Clone)]
-pub struct Camera {
-    pub coords: Coords,
-    pub ccoords: Coords,
-    pub render_distance_w: i32,
-    pub render_distance_h: i32,
-    pub zoom: i32,
-}
-impl Camera {
-    pub fn new() -> Camera {
-        Camera {
-            coords: Coords::new(),
-            ccoords: Coords::new(),
-            render_distance_w: *WINDOW_WIDTH as i32,
-            render_distance_h: *WINDOW_HEIGHT as i32,
-            zoom: *DEFAULT_ZOOM,
-        }
-    }
-    pub fn tick(&mut self) {
-        self.ccoords.x = self.coords.x / *CHUNK_SIZE as f32;
-        self.ccoords.y = self.coords.y / *CHUNK_SIZE as f32;
+pub fn biome_color(ttype: &TileType) -> (u8, u8, u8) {
+    match ttype {
+        TileType::Grass => (110, 180, 70),
+        TileType::Tundra => (160, 170, 140),
+        TileType::Taiga => (40, 100, 70),
+        TileType::TemperateForest => (30, 130, 40),
+        TileType::Steppe => (190, 180, 100),
+        TileType::Desert => (230, 210, 140),
+        TileType::Marsh => (70, 110, 90),
+        TileType::Glacier => (240, 250, 255),
+        _ => (0, 0, 0),
     }
 }
 pub fn render_server(
     sx: &crossbeam::channel::Sender<MainMsg>,
     rx: &crossbeam::channel::Receiver<Vec<RenderMsg>>,
+    rcam: &crossbeam::channel::Receiver<Camera>,
+    config: WorldConfig,
 ) {
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
 
     let window = video_subsystem
-        .window("Baltia", *WINDOW_WIDTH, *WINDOW_HEIGHT)
+        .window("Baltia", config.window_width, config.window_height)
         .position_centered()
         .fullscreen_desktop()
         .build()
         .unwrap();
-    let mut camera = Camera::new();
+    let mut camera = Camera::new(&config);
     let ttf_context = sdl2::ttf::init().unwrap();
     let font_path = "fonts/VastShadow-Regular.ttf";
     let _font = ttf_context.load_font(font_path, 48).unwrap();
@@ -59,20 +45,28 @@ pub fn render_server(
     let _texture_creator = canvas.texture_creator();
     let mut event_pump = sdl_context.event_pump().unwrap();
     let mut factions = false;
+    let mut biomes = false;
     let mut news = false;
     let mut trigger_refresh = false;
     'main: loop {
-        camera.tick();
+        // the console can move the camera too
+        if let Ok(moved) = rcam.try_recv() {
+            camera = moved;
+            trigger_refresh = true;
+        }
+        camera.tick(config.chunk_size);
         if let Ok(r) = rx.try_recv() {
             for message in r {
                 let chunk = message.chunk;
                 if chunk.coords.x as i32 * camera.zoom < (camera.ccoords.x) as i32 * camera.zoom
                     || chunk.coords.y as i32 * camera.zoom < (camera.ccoords.y) as i32 * camera.zoom
                     || chunk.coords.x as i32 * camera.zoom
-                        > (camera.ccoords.x as i32 + *WINDOW_WIDTH as i32 * *CHUNK_SIZE as i32)
+                        > (camera.ccoords.x as i32
+                            + config.window_width as i32 * config.chunk_size as i32)
                             * camera.zoom
                     || chunk.coords.y as i32 * camera.zoom
-                        > (camera.ccoords.y as i32 + *WINDOW_HEIGHT as i32 * *CHUNK_SIZE as i32)
+                        > (camera.ccoords.y as i32
+                            + config.window_height as i32 * config.chunk_size as i32)
                             * camera.zoom
                 {
                     continue;
@@ -103,28 +97,28 @@ pub fn render_server(
                             keycode: Some(Keycode::Left),
                             ..
                         } => {
-                            camera.coords.x += *CAMERA_STEP;
+                            camera.coords.x += config.camera_step;
                             trigger_refresh = true;
                         }
                         Event::KeyDown {
                             keycode: Some(Keycode::Right),
                             ..
                         } => {
-                            camera.coords.x -= *CAMERA_STEP;
+                            camera.coords.x -= config.camera_step;
                             trigger_refresh = true;
                         }
                         Event::KeyDown {
                             keycode: Some(Keycode::Up),
                             ..
                         } => {
-                            camera.coords.y += *CAMERA_STEP;
+                            camera.coords.y += config.camera_step;
                             trigger_refresh = true;
                         }
                         Event::KeyDown {
                             keycode: Some(Keycode::Down),
                             ..
                         } => {
-                            camera.coords.y -= *CAMERA_STEP;
+                            camera.coords.y -= config.camera_step;
                             trigger_refresh = true;
                         }
                         Event::KeyDown {
@@ -133,6 +127,13 @@ pub fn render_server(
                         } => {
                             factions = !factions;
                         }
+                        Event::KeyDown {
+                            keycode: Some(Keycode::B),
+                            ..
+                        } => {
+                            biomes = !biomes;
+                            trigger_refresh = true;
+                        }
                         Event::KeyDown {
                             keycode: Some(Keycode::N),
                             ..
@@ -183,16 +184,16 @@ pub fn render_server(
                                 match v2 {
                                     '#' => {
                                         let _ = canvas.fill_rect(Rect::new(
-                                            k2.0 * *TILE_SIZE as i32 * camera.zoom
+                                            k2.0 * config.tile_size as i32 * camera.zoom
                                                 + camera.coords.x as i32
                                                 + index * char_span,
-                                            k2.1 * *TILE_SIZE as i32 * camera.zoom
+                                            k2.1 * config.tile_size as i32 * camera.zoom
                                                 + camera.coords.y as i32
                                                 + row * row_span
                                                 + 16
                                                 + i as i32 * row_span,
-                                            *TILE_SIZE * camera.zoom as u32,
-                                            *TILE_SIZE * camera.zoom as u32,
+                                            config.tile_size * camera.zoom as u32,
+                                            config.tile_size * camera.zoom as u32,
                                         ));
                                     }
                                     _ => {}
@@ -207,23 +208,32 @@ pub fn render_server(
                 }
                 for m in chunk.tiles {
                     let mut color = (
-                        (255.0 - (1.0 * m.height as f32 / 0.0) * 255.0) as u8,
-                        (255.0 - (1.0 * m.height as f32 / 10.0) * 255.0) as u8,
-                        (255.0 - (1.0 * m.height as f32 / 0.0) * 255.0) as u8,
+                        (255.0 - (1.0 * m.height / 0.0) * 255.0) as u8,
+                        (255.0 - (1.0 * m.height / 10.0) * 255.0) as u8,
+                        (255.0 - (1.0 * m.height / 0.0) * 255.0) as u8,
                     );
-                    if m.height < 0 {
-                        color.0 = 0;
-                        color.1 = 0;
-                        color.2 = 255;
+                    if biomes {
+                        color = biome_color(&m.ttype);
+                    }
+                    match m.ttype {
+                        TileType::Ocean => color = (0, 0, 255),
+                        TileType::Lake => color = (40, 90, 220),
+                        TileType::River => color = (60, 140, 255),
+                        TileType::WoodenWall => color = (110, 70, 30),
+                        TileType::WoodenFloor => color = (170, 130, 80),
+                        TileType::WoodenDoor => color = (80, 50, 20),
+                        TileType::Road => color = (150, 130, 100),
+                        TileType::Bridge => color = (120, 100, 80),
+                        _ => {}
                     }
                     canvas.set_draw_color(Color::RGB(color.0, color.1, color.2));
                     let _ = canvas.fill_rect(Rect::new(
-                        m.coords.x as i32 * *TILE_SIZE as i32 * camera.zoom
+                        m.coords.x as i32 * config.tile_size as i32 * camera.zoom
                             + camera.coords.x as i32,
-                        m.coords.y as i32 * *TILE_SIZE as i32 * camera.zoom
+                        m.coords.y as i32 * config.tile_size as i32 * camera.zoom
                             + camera.coords.y as i32,
-                        *TILE_SIZE * camera.zoom as u32,
-                        *TILE_SIZE * camera.zoom as u32,
+                        config.tile_size * camera.zoom as u32,
+                        config.tile_size * camera.zoom as u32,
                     ));
                 }
                 for m in &chunk.entities {
@@ -233,60 +243,46 @@ pub fn render_server(
                     color.2 = 0;
                     canvas.set_draw_color(Color::RGB(color.0, color.1, color.2));
                     let _ = canvas.fill_rect(Rect::new(
-                        m.coords.x as i32 * *TILE_SIZE as i32 * camera.zoom
+                        m.coords.x as i32 * config.tile_size as i32 * camera.zoom
                             + camera.coords.x as i32,
-                        m.coords.y as i32 * *TILE_SIZE as i32 * camera.zoom
+                        m.coords.y as i32 * config.tile_size as i32 * camera.zoom
                             + camera.coords.y as i32,
-                        *TILE_SIZE * camera.zoom as u32,
-                        *TILE_SIZE * camera.zoom as u32,
+                        config.tile_size * camera.zoom as u32,
+                        config.tile_size * camera.zoom as u32,
                     ));
                 }
+                // territory of the faction holding the chunk
                 if factions {
-                    let counts: HashMap<Faction, usize> =
-                        chunk
-                            .entities
-                            .clone()
-                            .iter()
-                            .fold(HashMap::new(), |mut acc, entity| {
-                                *acc.entry(entity.clone().alignment.faction).or_insert(0) += 1;
-                                acc
-                            });
-                    let max_value = counts
-                        .iter()
-                        .max_by_key(|&(_, v)| v)
-                        .map(|(k, _)| k)
-                        .unwrap_or(&Faction::Empty);
-
-                    match max_value {
-                        &Faction::Empty => {
+                    match message.owner {
+                        Faction::Empty => {
                             canvas.set_draw_color(Color::RGBA(0, 0, 0, 100));
                         }
-                        &Faction::Hiisi => {
+                        Faction::Hiisi => {
                             canvas.set_draw_color(Color::RGBA(255, 255, 255, 100));
                         }
-                        &Faction::Virumaa => {
+                        Faction::Virumaa => {
                             canvas.set_draw_color(Color::RGBA(0, 0, 255, 100));
                         }
-                        &Faction::Kalevala => {
+                        Faction::Kalevala => {
                             canvas.set_draw_color(Color::RGBA(255, 255, 0, 100));
                         }
-                        &Faction::Pohjola => {
+                        Faction::Pohjola => {
                             canvas.set_draw_color(Color::RGBA(0, 0, 255, 100));
                         }
-                        &Faction::Tapiola => {
+                        Faction::Tapiola => {
                             canvas.set_draw_color(Color::RGBA(0, 255, 0, 100));
                         }
-                        &Faction::Novgorod => {
+                        Faction::Novgorod => {
                             canvas.set_draw_color(Color::RGBA(255, 0, 0, 100));
                         }
                     };
                     let _ = canvas.fill_rect(Rect::new(
-                        chunk.coords.x as i32 * *CHUNK_SIZE as i32 * camera.zoom
+                        chunk.coords.x as i32 * config.chunk_size as i32 * camera.zoom
                             + camera.coords.x as i32,
-                        chunk.coords.y as i32 * *CHUNK_SIZE as i32 * camera.zoom
+                        chunk.coords.y as i32 * config.chunk_size as i32 * camera.zoom
                             + camera.coords.y as i32,
-                        *CHUNK_SIZE * *TILE_SIZE * camera.zoom as u32,
-                        *CHUNK_SIZE * *TILE_SIZE * camera.zoom as u32,
+                        config.chunk_size * config.tile_size * camera.zoom as u32,
+                        config.chunk_size * config.tile_size * camera.zoom as u32,
                     ));
                 }
             }
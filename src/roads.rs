@@ -0,0 +1,538 @@
+use crate::construction::corners_around;
+use crate::math::euclid;
+use crate::path::{astar, tile_cost};
+use crate::settlement::Settlement;
+use crate::terrain::Terrain;
+use crate::worldgen::{Coords, TileType, World};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+// settlements further apart than this, in tiles, are never joined directly
+const MAX_LENGTH: f32 = 300.0;
+// tiles a route may stray outside the box around its two ends
+const MARGIN: i64 = 24;
+// cost of one unit of height up or down, roads keep to the flat
+const CLIMB: f32 = 6.0;
+// cost of following a road that is already there, so roads merge
+const REUSE: f32 = 0.3;
+// cost of bridging one tile of river, lakes and the sea are not bridged
+const BRIDGE: f32 = 12.0;
+// extra roads are laid where the network makes a detour this many times
+// longer than the straight line, at most one for every EXTRA settlements
+const DETOUR: f32 = 2.0;
+const EXTRA: usize = 4;
+// how far from a settlement's centre its road may start
+const END_REACH: i64 = 8;
+// walks shorter than this, in tiles, never go by road
+pub const ROAD_TRIP: f32 = 64.0;
+
+// a road between two settlements and the tiles it runs over, from one end to
+// the other as terrain indices
+pub struct Road {
+    pub from: usize,
+    pub to: usize,
+    pub tiles: Vec<usize>,
+    pub length: f32,
+}
+// the road network as a graph, settlements are its nodes and roads its edges
+pub struct Roads {
+    pub roads: Vec<Road>,
+    // roads leaving each settlement
+    links: HashMap<usize, Vec<usize>>,
+}
+impl Default for Roads {
+    fn default() -> Roads {
+        Roads::new()
+    }
+}
+impl Roads {
+    pub fn new() -> Roads {
+        Roads {
+            roads: vec![],
+            links: HashMap::new(),
+        }
+    }
+    pub fn add(&mut self, road: Road) {
+        let index = self.roads.len();
+        self.links.entry(road.from).or_default().push(index);
+        self.links.entry(road.to).or_default().push(index);
+        self.roads.push(road);
+    }
+    // settlements one road away and the roads leading there
+    pub fn neighbours(&self, settlement: usize) -> impl Iterator<Item = (usize, &Road)> {
+        self.links
+            .get(&settlement)
+            .into_iter()
+            .flatten()
+            .map(move |r| {
+                let road = &self.roads[*r];
+                let other = if road.from == settlement {
+                    road.to
+                } else {
+                    road.from
+                };
+                (other, road)
+            })
+    }
+    // settlements passed on the shortest way by road and its length, None
+    // when no road leads there
+    pub fn route(&self, from: usize, to: usize) -> Option<(Vec<usize>, f32)> {
+        let mut open = BinaryHeap::new();
+        let mut cost: HashMap<usize, f32> = HashMap::new();
+        let mut came_from: HashMap<usize, usize> = HashMap::new();
+        cost.insert(from, 0.0);
+        open.push(Open(0.0, from));
+        while let Some(Open(here, node)) = open.pop() {
+            if node == to {
+                let mut route = vec![node];
+                let mut node = node;
+                while let Some(previous) = came_from.get(&node) {
+                    route.push(*previous);
+                    node = *previous;
+                }
+                route.reverse();
+                return Some((route, here));
+            }
+            if here > cost[&node] {
+                continue;
+            }
+            for (next, road) in self.neighbours(node) {
+                let next_cost = here + road.length;
+                if next_cost < *cost.get(&next).unwrap_or(&f32::INFINITY) {
+                    cost.insert(next, next_cost);
+                    came_from.insert(next, node);
+                    open.push(Open(next_cost, next));
+                }
+            }
+        }
+        None
+    }
+    // tiles of the roads along a route in walking order
+    pub fn tiles(&self, route: &[usize]) -> Vec<usize> {
+        let mut tiles: Vec<usize> = vec![];
+        for pair in route.windows(2) {
+            let road = match self
+                .neighbours(pair[0])
+                .filter(|(other, _)| *other == pair[1])
+                .min_by(|a, b| a.1.length.total_cmp(&b.1.length))
+            {
+                Some((_, road)) => road,
+                None => continue,
+            };
+            let way: Vec<usize> = if road.from == pair[0] {
+                road.tiles.clone()
+            } else {
+                road.tiles.iter().rev().cloned().collect()
+            };
+            for tile in way {
+                if tiles.last() != Some(&tile) {
+                    tiles.push(tile);
+                }
+            }
+        }
+        tiles
+    }
+}
+impl World {
+    // a long walk by road: to the road out of the settlement closest to the
+    // start, along the network to the settlement closest to the goal and on
+    // from there, without the start. None when the walk is short, no road
+    // leads there or the roads make too long a detour
+    pub fn road_path(&mut self, from: (usize, usize), to: (usize, usize)) -> Option<Vec<Coords>> {
+        let straight = tile_distance(from, to);
+        if straight < ROAD_TRIP {
+            return None;
+        }
+        let a = self.closest_settlement(from)?;
+        let b = self.closest_settlement(to)?;
+        if a == b {
+            return None;
+        }
+        let (route, length) = self.roads.route(a, b)?;
+        let tiles = self.roads.tiles(&route);
+        let side = self.terrain.side();
+        let on = (tiles[0] % side, tiles[0] / side);
+        let off = tiles[tiles.len() - 1];
+        let off = (off % side, off / side);
+        if tile_distance(from, on) + length + tile_distance(off, to) > straight * DETOUR {
+            return None;
+        }
+        let mut path = self.find_path(from, on)?;
+        path.extend(
+            tiles[1..]
+                .iter()
+                .map(|i| Coords::from(((i % side) as f32 + 0.5, (i / side) as f32 + 0.5))),
+        );
+        path.extend(self.find_path(off, to)?);
+        Some(path)
+    }
+    // sends the entities that set out on a long walk during the last steps by
+    // road, those road_path finds no road for keep walking straight there.
+    // walks that were given up or changed since are left alone
+    pub fn route_walks(&mut self) {
+        let walks = self.store.collect(|chunk| chunk.walks.clone());
+        if walks.is_empty() {
+            return;
+        }
+        self.store.apply(|chunk| {
+            chunk.walks.clear();
+            false
+        });
+        for (id, to) in walks {
+            let (chunk, entity) = match self.find_entity(id) {
+                Some(found) => found,
+                None => continue,
+            };
+            if entity.target != Some(to) || !entity.path.is_empty() {
+                continue;
+            }
+            let from = (entity.coords.x as usize, entity.coords.y as usize);
+            let mut path = match self.road_path(from, (to.x as usize, to.y as usize)) {
+                Some(path) if !path.is_empty() => path,
+                _ => continue,
+            };
+            let entity = self
+                .fetch_chunk_mut(chunk)
+                .entities
+                .iter_mut()
+                .find(|e| e.index == id)
+                .unwrap();
+            entity.target = Some(path.remove(0));
+            entity.path = path;
+        }
+    }
+    fn closest_settlement(&self, tile: (usize, usize)) -> Option<usize> {
+        self.settlements
+            .iter()
+            .min_by(|a, b| tile_distance(a.centre, tile).total_cmp(&tile_distance(b.centre, tile)))
+            .map(|s| s.id)
+    }
+}
+// whether a walk is long enough that the world may send it by road, see
+// `World::route_walks`
+pub fn is_long(from: &Coords, to: &Coords) -> bool {
+    euclid(from, to) >= ROAD_TRIP
+}
+fn tile_distance(a: (usize, usize), b: (usize, usize)) -> f32 {
+    let dx = a.0 as f32 - b.0 as f32;
+    let dy = a.1 as f32 - b.1 as f32;
+    (dx * dx + dy * dy).sqrt()
+}
+// cost of a road going from one tile to the next, roads stay out of
+// buildings and only leave dry land to bridge a river
+fn road_cost(terrain: &Terrain, from: (usize, usize), to: (usize, usize)) -> Option<f32> {
+    let ttype = terrain.ttype(to.0, to.1);
+    let cost = match ttype {
+        TileType::Road | TileType::Bridge => REUSE,
+        TileType::River => BRIDGE,
+        TileType::WoodenWall | TileType::WoodenFloor | TileType::WoodenDoor => return None,
+        _ => tile_cost(&ttype)?,
+    };
+    let length = if from.0 != to.0 && from.1 != to.1 {
+        std::f32::consts::SQRT_2
+    } else {
+        1.0
+    };
+    let climb = (terrain.height(to.0, to.1) - terrain.height(from.0, from.1)).abs();
+    Some(length * cost + climb * CLIMB)
+}
+// the tile closest to a settlement's centre a road can start on
+fn end(terrain: &Terrain, settlement: &Settlement) -> Option<(usize, usize)> {
+    let side = terrain.side() as i64;
+    let (x, y) = settlement.centre;
+    corners_around(x, y, END_REACH)
+        .filter(|(x, y)| *x < side && *y < side)
+        .map(|(x, y)| (x as usize, y as usize))
+        .find(|(x, y)| {
+            terrain.ttype(*x, *y).is_passable() && road_cost(terrain, (*x, *y), (*x, *y)).is_some()
+        })
+}
+// the cheapest way for a road between two tiles, kept to the box around them
+fn survey(terrain: &Terrain, from: (usize, usize), to: (usize, usize)) -> Option<Vec<usize>> {
+    let low = (
+        from.0.min(to.0) as i64 - MARGIN,
+        from.1.min(to.1) as i64 - MARGIN,
+    );
+    let high = (
+        from.0.max(to.0) as i64 + MARGIN,
+        from.1.max(to.1) as i64 + MARGIN,
+    );
+    let mut tiles = astar(
+        terrain.side(),
+        from,
+        to,
+        |x, y| {
+            let (x, y) = (x as i64, y as i64);
+            x >= low.0 && y >= low.1 && x <= high.0 && y <= high.1
+        },
+        |a, b| road_cost(terrain, a, b),
+        REUSE,
+    )?;
+    tiles.insert(0, terrain.index(from.0, from.1));
+    Some(tiles)
+}
+fn length(terrain: &Terrain, tiles: &[usize]) -> f32 {
+    let side = terrain.side();
+    tiles
+        .windows(2)
+        .map(|w| {
+            let (a, b) = ((w[0] % side, w[0] / side), (w[1] % side, w[1] / side));
+            if a.0 != b.0 && a.1 != b.1 {
+                std::f32::consts::SQRT_2
+            } else {
+                1.0
+            }
+        })
+        .sum()
+}
+// lays roads between the settlements and writes them into the terrain. the
+// closest pairs are joined first as long as no road connects them yet, which
+// gives a minimum spanning tree of every group of settlements that can reach
+// each other. then a few more roads go where the tree makes long detours
+pub fn lay(terrain: &mut Terrain, settlements: &[Settlement]) -> Roads {
+    let mut pairs = vec![];
+    for (i, a) in settlements.iter().enumerate() {
+        for b in &settlements[i + 1..] {
+            let d = tile_distance(a.centre, b.centre);
+            if d <= MAX_LENGTH {
+                pairs.push((d, a.id, b.id));
+            }
+        }
+    }
+    pairs.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)).then(a.2.cmp(&b.2)));
+    let ends: Vec<Option<(usize, usize)>> = settlements.iter().map(|s| end(terrain, s)).collect();
+    let mut roads = Roads::new();
+    let mut groups: Vec<usize> = (0..settlements.len()).collect();
+    let mut skipped = vec![];
+    for (d, a, b) in pairs {
+        if root(&mut groups, a) == root(&mut groups, b) {
+            skipped.push((d, a, b));
+            continue;
+        }
+        if build(terrain, &mut roads, &ends, a, b) {
+            let (ra, rb) = (root(&mut groups, a), root(&mut groups, b));
+            groups[ra] = rb;
+        }
+    }
+    let mut extra = settlements.len() / EXTRA;
+    for (d, a, b) in skipped {
+        if extra == 0 {
+            break;
+        }
+        let detour = roads.route(a, b).map_or(f32::INFINITY, |(_, l)| l);
+        if detour > d * DETOUR && build(terrain, &mut roads, &ends, a, b) {
+            extra -= 1;
+        }
+    }
+    roads
+}
+// surveys a road between two settlements and lays it, false when there is
+// no way
+fn build(
+    terrain: &mut Terrain,
+    roads: &mut Roads,
+    ends: &[Option<(usize, usize)>],
+    a: usize,
+    b: usize,
+) -> bool {
+    let (from, to) = match (ends[a], ends[b]) {
+        (Some(from), Some(to)) => (from, to),
+        _ => return false,
+    };
+    let tiles = match survey(terrain, from, to) {
+        Some(tiles) => tiles,
+        None => return false,
+    };
+    let side = terrain.side();
+    for i in &tiles {
+        let (x, y) = (i % side, i / side);
+        let ttype = match terrain.ttype(x, y) {
+            TileType::River | TileType::Bridge => TileType::Bridge,
+            _ => TileType::Road,
+        };
+        terrain.set_ttype(x, y, ttype);
+    }
+    roads.add(Road {
+        from: a,
+        to: b,
+        length: length(terrain, &tiles),
+        tiles,
+    });
+    true
+}
+// the group a settlement belongs to, with path halving
+fn root(groups: &mut [usize], mut i: usize) -> usize {
+    while groups[i] != i {
+        groups[i] = groups[groups[i]];
+        i = groups[i];
+    }
+    i
+}
+// entry of the open set, the shortest distance comes out of the heap first
+struct Open(f32, usize);
+impl PartialEq for Open {
+    fn eq(&self, other: &Open) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl Eq for Open {}
+impl PartialOrd for Open {
+    fn partial_cmp(&self, other: &Open) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Open {
+    fn cmp(&self, other: &Open) -> Ordering {
+        other
+            .0
+            .total_cmp(&self.0)
+            .then_with(|| other.1.cmp(&self.1))
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::WorldConfig;
+    use crate::settlement::Growth;
+    use crate::terrain::Layers;
+    use crate::worldgen::Faction;
+
+    // flat grass, 4 x 4 chunks of 64 tiles
+    fn world() -> World {
+        let mut config = WorldConfig::new();
+        config.world_size = 4;
+        config.chunk_size = 64;
+        World::from(Terrain::from(Layers::new(256)), 0, config).unwrap()
+    }
+    fn settlement(id: usize, centre: (usize, usize)) -> Settlement {
+        Settlement {
+            id,
+            name: String::new(),
+            faction: Faction::Empty,
+            centre,
+            chunk: 0,
+            population: 0,
+            buildings: vec![],
+            growth: Growth::Stable,
+        }
+    }
+    // settles the world and lays its roads
+    fn settle(world: &mut World, centres: &[(usize, usize)]) {
+        world.settlements = centres
+            .iter()
+            .enumerate()
+            .map(|(id, centre)| settlement(id, *centre))
+            .collect();
+        world.roads = lay(&mut world.terrain, &world.settlements);
+    }
+    // a ring of lake around a tile
+    fn moat(terrain: &mut Terrain, (x, y): (usize, usize), r: usize) {
+        for d in 0..=2 * r {
+            terrain.set_ttype(x - r + d, y - r, TileType::Lake);
+            terrain.set_ttype(x - r + d, y + r, TileType::Lake);
+            terrain.set_ttype(x - r, y - r + d, TileType::Lake);
+            terrain.set_ttype(x + r, y - r + d, TileType::Lake);
+        }
+    }
+
+    #[test]
+    fn tree_joins_every_reachable_settlement() {
+        let mut world = world();
+        moat(&mut world.terrain, (128, 40), 12);
+        settle(
+            &mut world,
+            &[
+                (30, 128),
+                (80, 128),
+                (130, 128),
+                (180, 128),
+                (230, 128),
+                (128, 40),
+            ],
+        );
+        for a in 0..5 {
+            for b in 0..5 {
+                assert!(world.roads.route(a, b).is_some(), "{} {}", a, b);
+            }
+            assert!(world.roads.route(a, 5).is_none());
+        }
+        // a line makes no detours, so the tree is all there is
+        assert_eq!(world.roads.roads.len(), 4);
+        for road in &world.roads.roads {
+            let (x, y) = (road.tiles[0] % 256, road.tiles[0] / 256);
+            assert_eq!(world.terrain.ttype(x, y), TileType::Road);
+        }
+    }
+
+    #[test]
+    fn extra_roads_cut_long_detours() {
+        let mut world = world();
+        // the tree goes around three sides of the square, the fourth side is
+        // three times shorter than going round
+        settle(&mut world, &[(50, 50), (150, 50), (150, 150), (50, 150)]);
+        assert_eq!(world.roads.roads.len(), 4);
+        let (route, length) = world.roads.route(2, 3).unwrap();
+        assert_eq!(route, vec![2, 3]);
+        assert!(length < 100.0 * DETOUR);
+        // across the square the sides are no detour worth a road
+        assert_eq!(world.roads.route(0, 2).unwrap().0.len(), 3);
+    }
+
+    #[test]
+    fn route_needs_a_road() {
+        let mut roads = Roads::new();
+        assert!(roads.route(0, 1).is_none());
+        roads.add(Road {
+            from: 0,
+            to: 1,
+            tiles: vec![0, 1],
+            length: 1.0,
+        });
+        assert_eq!(roads.route(1, 0).unwrap().0, vec![1, 0]);
+        assert!(roads.route(0, 2).is_none());
+    }
+
+    #[test]
+    fn road_path_falls_back_without_a_road() {
+        let mut world = world();
+        // no settlements, no roads, walks find their own way
+        assert!(world.road_path((10, 128), (240, 128)).is_none());
+        let id = world.spawn(10, 128).unwrap();
+        assert!(world.walk(id, 240, 128).unwrap() > 0);
+
+        moat(&mut world.terrain, (128, 40), 12);
+        settle(&mut world, &[(30, 128), (230, 128), (128, 40)]);
+        // too short to go by road
+        assert!(world.road_path((30, 130), (60, 130)).is_none());
+        // no road leads into the moat
+        assert!(world.road_path((30, 128), (128, 40)).is_none());
+        let path = world.road_path((20, 140), (240, 140)).unwrap();
+        let last = path.last().unwrap();
+        assert_eq!((last.x as usize, last.y as usize), (240, 140));
+        let road = path
+            .iter()
+            .filter(|c| world.terrain.ttype(c.x as usize, c.y as usize) == TileType::Road)
+            .count();
+        assert!(road > 150);
+    }
+
+    #[test]
+    fn long_walks_are_sent_by_road() {
+        let mut world = world();
+        settle(&mut world, &[(30, 128), (230, 128)]);
+        let id = world.spawn(20, 140).unwrap();
+        let to = Coords::from((240.5, 140.5));
+        let (chunk, _) = world.find_entity(id).unwrap();
+        let chunk = world.fetch_chunk_mut(chunk);
+        let entity = chunk.entities.iter_mut().find(|e| e.index == id).unwrap();
+        entity.target = Some(to);
+        chunk.walks.push((id, to));
+        world.route_walks();
+        let (_, entity) = world.find_entity(id).unwrap();
+        assert_ne!(entity.target, Some(to));
+        assert!(entity.path.len() > 200);
+        let last = entity.path.last().unwrap();
+        assert_eq!((last.x as usize, last.y as usize), (240, 140));
+        assert!(world.store.collect(|c| c.walks.clone()).is_empty());
+    }
+}
@@ -0,0 +1,296 @@
+use crate::codec::{self, Codec, Reader, Writer, FORMAT_VERSION};
+use crate::config::WorldConfig;
+use crate::construction::Blueprints;
+use crate::faction::FactionState;
+use crate::roads::Roads;
+use crate::settlement::Settlement;
+use crate::task::{Job, Target};
+use crate::worldgen::{worldgen, Chunk, World};
+use std::fs;
+
+pub const SAVE_MAGIC: &[u8; 4] = b"WGS1";
+
+// a save holds the seed, step, next entity id, config, settlements,
+// factions, blueprints and roads plus every chunk that no longer matches what
+// the seed generates; the terrain and all untouched chunks are regenerated on
+// load
+impl World {
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let mut w = Writer::new();
+        w.bytes.extend_from_slice(SAVE_MAGIC);
+        w.u8(FORMAT_VERSION);
+        w.u32(self.seed);
+        w.u64(self.step);
+        w.varint(self.next_id as u64);
+        self.config.encode(&mut w);
+        w.varint(self.settlements.len() as u64);
+        for settlement in &self.settlements {
+            settlement.encode(&mut w);
+        }
+        w.varint(self.factions.len() as u64);
+        for faction in &self.factions {
+            faction.encode(&mut w);
+        }
+        self.blueprints.encode(&mut w);
+        self.roads.encode(&mut w);
+        let modified = self.store.modified();
+        w.varint(modified.len() as u64);
+        for index in modified {
+            let bytes =
+                self.store
+                    .with_chunk(index, self.seed, &self.config, &self.terrain, codec::encode);
+            w.varint(bytes.len() as u64);
+            w.bytes.extend_from_slice(&bytes);
+        }
+        // write next to the target first so a crash never leaves half a save
+        let partial = format!("{}.partial", path);
+        fs::write(&partial, &w.bytes).map_err(|e| format!("cannot write {}: {}", partial, e))?;
+        fs::rename(&partial, path).map_err(|e| format!("cannot write {}: {}", path, e))
+    }
+    // the page directory is machine local and is not taken from the save
+    pub fn load(path: &str) -> Result<World, String> {
+        let bytes = fs::read(path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+        let mut r = Reader::from(&bytes);
+        if r.take(4)? != SAVE_MAGIC {
+            return Err(format!("{} is not a save file", path));
+        }
+        let version = r.u8()?;
+        if version != FORMAT_VERSION {
+            return Err(format!("unsupported save format version {}", version));
+        }
+        let seed = r.u32()?;
+        let step = r.u64()?;
+        let next_id = r.varint()? as usize;
+        let mut config = WorldConfig::decode(&mut r)?;
+        config.page_dir = String::new();
+        config.validate()?;
+        let mut world = worldgen(seed, &config)?;
+        world.step = step;
+        world.next_id = next_id;
+        world.settlements = (0..r.varint()?)
+            .map(|_| Settlement::decode(&mut r))
+            .collect::<Result<Vec<Settlement>, String>>()?;
+        world.factions = (0..r.varint()?)
+            .map(|_| FactionState::decode(&mut r))
+            .collect::<Result<Vec<FactionState>, String>>()?;
+        world.blueprints = Blueprints::decode(&mut r)?;
+        world.roads = Roads::decode(&mut r)?;
+        // settlements are looked up by id and centre, factions by capital
+        let side = config.world_size as usize * config.chunk_size as usize;
+        for (i, settlement) in world.settlements.iter().enumerate() {
+            if settlement.id != i {
+                return Err(format!("settlement {} is stored at {}", settlement.id, i));
+            }
+            let (x, y) = settlement.centre;
+            if settlement.chunk >= world.chunk_count() || x >= side || y >= side {
+                return Err(format!("settlement {} lies outside the world", i));
+            }
+        }
+        for faction in &world.factions {
+            if let Some(capital) = faction.capital {
+                if capital >= world.settlements.len() {
+                    return Err(format!(
+                        "capital {} of {:?} is not a settlement",
+                        capital, faction.faction
+                    ));
+                }
+            }
+        }
+        for (i, road) in world.roads.roads.iter().enumerate() {
+            if road.from >= world.settlements.len() || road.to >= world.settlements.len() {
+                return Err(format!("road {} does not join two settlements", i));
+            }
+            if road.tiles.iter().any(|t| *t >= side * side) {
+                return Err(format!("road {} runs outside the world", i));
+            }
+        }
+        for _ in 0..r.varint()? {
+            let len = r.varint()? as usize;
+            let chunk: Chunk = codec::decode(r.take(len)?)?;
+            if chunk.index >= world.chunk_count() {
+                return Err(format!("chunk {} lies outside the world", chunk.index));
+            }
+            // a chunk only holds the tiles of its own square of the world
+            let world_size = config.world_size as usize;
+            let chunk_size = config.chunk_size as usize;
+            let x0 = chunk.index % world_size * chunk_size;
+            let y0 = chunk.index / world_size * chunk_size;
+            let inside = |c: f32, c0: usize| c >= c0 as f32 && c < (c0 + chunk_size) as f32;
+            if let Some(tile) = chunk
+                .tiles
+                .iter()
+                .find(|t| !inside(t.coords.x, x0) || !inside(t.coords.y, y0))
+            {
+                return Err(format!(
+                    "tile at {}, {} lies outside chunk {}",
+                    tile.coords.x, tile.coords.y, chunk.index
+                ));
+            }
+            // jobs on a tile index it in the chunk's tiles
+            let held = chunk.tiles.iter().filter_map(|t| t.holds.as_ref());
+            for entity in chunk.entities.iter().chain(held) {
+                if let Some(Job {
+                    target: Target::Tile(i),
+                    ..
+                }) = entity.job
+                {
+                    if i >= chunk.tiles.len() {
+                        return Err(format!(
+                            "entity {} works on tile {} outside chunk {}",
+                            entity.index, i, chunk.index
+                        ));
+                    }
+                }
+            }
+            // tiles built on since generation override the regenerated terrain
+            for tile in &chunk.tiles {
+                let (x, y) = (tile.coords.x as usize, tile.coords.y as usize);
+                if world.terrain.ttype(x, y) != tile.ttype {
+                    world.terrain.set_ttype(x, y, tile.ttype.clone());
+                }
+            }
+            world.store.restore(chunk)?;
+        }
+        Ok(world)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitmap::{Bitmap, BuildingType};
+    use crate::roads::Road;
+    use crate::task::TaskKind;
+    use std::env;
+    use std::process;
+
+    fn path(name: &str) -> String {
+        env::temp_dir()
+            .join(format!("worldgen2-test-{}-{}.wgs", process::id(), name))
+            .to_string_lossy()
+            .to_string()
+    }
+    fn world() -> World {
+        let mut config = WorldConfig::new();
+        config.world_size = 2;
+        config.chunk_size = 32;
+        config.erosion_droplets = 1000;
+        let mut world = worldgen(7, &config).unwrap();
+        for i in 0..world.chunk_count() {
+            world.fetch_chunk_mut(i);
+        }
+        for _ in 0..5 {
+            world.resolve(1);
+            world.resolve_between();
+        }
+        world
+    }
+
+    #[test]
+    fn loaded_world_continues_identically() {
+        let path = path("continue");
+        let mut world = world();
+        world.save(&path).unwrap();
+        let mut loaded = World::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.step, world.step);
+        assert_eq!(loaded.next_id, world.next_id);
+        let settlements = |w: &World| w.settlements.iter().map(codec::encode).collect::<Vec<_>>();
+        assert_eq!(settlements(&loaded), settlements(&world));
+        let factions = |w: &World| w.factions.iter().map(codec::encode).collect::<Vec<_>>();
+        assert_eq!(factions(&loaded), factions(&world));
+        assert!(world.population() > 0 && !world.settlements.is_empty());
+        for i in 0..world.chunk_count() {
+            let chunk = codec::encode(&world.fetch_chunk(i).clone());
+            assert_eq!(codec::encode(&loaded.fetch_chunk(i).clone()), chunk);
+        }
+        for _ in 0..5 {
+            for w in [&mut world, &mut loaded] {
+                w.resolve(1);
+                w.resolve_between();
+            }
+        }
+        assert_eq!(loaded.digest(), world.digest());
+    }
+
+    #[test]
+    fn chunks_outside_the_world_are_refused() {
+        let path = path("outside");
+        let mut world = world();
+        world.fetch_chunk_mut(1).index = 4;
+        world.save(&path).unwrap();
+        assert!(World::load(&path).is_err());
+        let mut world = self::world();
+        world.fetch_chunk_mut(1).tiles[0].coords.x = 1e9;
+        world.save(&path).unwrap();
+        assert!(World::load(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn dangling_references_are_refused() {
+        let path = path("dangling");
+        let refused = |damage: &dyn Fn(&mut World)| {
+            let mut world = world();
+            assert!(!world.settlements.is_empty());
+            damage(&mut world);
+            world.save(&path).unwrap();
+            let loaded = World::load(&path);
+            fs::remove_file(&path).unwrap();
+            loaded.is_err()
+        };
+        assert!(refused(&|w| w.settlements[0].id = 1));
+        assert!(refused(&|w| w.settlements[0].chunk = 4));
+        assert!(refused(&|w| w.settlements[0].centre = (64, 0)));
+        assert!(refused(&|w| {
+            w.factions[0].capital = Some(w.settlements.len());
+        }));
+        assert!(refused(&|w| {
+            let index = (0..w.chunk_count())
+                .find(|&i| !w.fetch_chunk(i).entities.is_empty())
+                .unwrap();
+            let chunk = w.fetch_chunk_mut(index);
+            let tile = Target::Tile(chunk.tiles.len());
+            chunk.entities[0].job = Some(Job::from(TaskKind::Farm, tile));
+        }));
+        let road = |from: usize, tiles: Vec<usize>| Road {
+            from,
+            to: 0,
+            tiles,
+            length: 1.0,
+        };
+        assert!(refused(&|w| w
+            .roads
+            .add(road(w.settlements.len(), vec![0]))));
+        assert!(refused(&|w| w.roads.add(road(0, vec![64 * 64]))));
+    }
+
+    #[test]
+    fn blueprints_and_roads_are_kept() {
+        let path = path("plans");
+        let mut world = world();
+        world
+            .blueprints
+            .register(BuildingType::SmallHouse, &Bitmap::from("###\n#x \n###\n"));
+        world.roads.add(Road {
+            from: 0,
+            to: 0,
+            tiles: vec![65, 66, 130],
+            length: 2.4,
+        });
+        world.save(&path).unwrap();
+        let loaded = World::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            codec::encode(&loaded.blueprints),
+            codec::encode(&world.blueprints)
+        );
+        let house = loaded.blueprints.get(&BuildingType::SmallHouse).unwrap();
+        assert_eq!((house.width, house.height), (3, 3));
+        assert_eq!(codec::encode(&loaded.roads), codec::encode(&world.roads));
+        // the links between settlements come back with the roads
+        assert_eq!(
+            loaded.roads.neighbours(0).count(),
+            world.roads.neighbours(0).count()
+        );
+    }
+}
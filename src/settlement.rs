@@ -0,0 +1,459 @@
+use crate::bitmap::BuildingType;
+use crate::climate;
+use crate::config::WorldConfig;
+use crate::construction::{self, corners_around, Blueprints};
+use crate::math::{chunk_index, euclid, tile_in_chunk, tile_to_chunk, tile_to_world};
+use crate::terrain::Terrain;
+use crate::worldgen::{chunk_faction, pass_seed, Coords, Faction, Item, Status, TileType, World};
+use lazy_static::lazy_static;
+use rand::prelude::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha12Rng;
+
+// tiles between the candidate sites tried in a chunk
+const SITE_STRIDE: usize = 4;
+// how far a site looks for water
+const WATER_REACH: i64 = 12;
+// half the side of the square whose height range is a site's flatness
+const FLAT_REACH: i64 = 4;
+// sites scoring less are left alone
+const MIN_SCORE: f32 = 1.5;
+// residents live within this many tiles of the centre
+pub const RADIUS: f32 = 24.0;
+// one in CROWD tiles around a settlement starts with a human on it, one in
+// SPARSE elsewhere
+pub const CROWD: u32 = 6;
+pub const SPARSE: u32 = 64;
+// buildings standing when a settlement is founded
+const FOUNDING: [BuildingType; 3] = [
+    BuildingType::Storehouse,
+    BuildingType::SmallHouse,
+    BuildingType::SmallHouse,
+];
+// how far from the centre buildings go up
+const BUILD_REACH: i64 = 20;
+// steps between two updates of the settlements
+pub const PERIOD: u64 = 50;
+// residents one building houses before another is planned
+const HOUSING: usize = 8;
+// a settlement grows with this much bread per resident and this share of its
+// residents not fighting, and shrinks below a quarter and a half of them
+const FOOD_PER_HEAD: f32 = 1.0;
+const SAFE: f32 = 0.9;
+// coins per resident that make a trading town, it grows twice as fast
+const WEALTH: f32 = 4.0;
+
+lazy_static! {
+    static ref SYLLABLES: Vec<&'static str> = vec![
+        "ka", "la", "vi", "ru", "ta", "pi", "jo", "ne", "mä", "ko", "sa", "ri", "hu", "va", "lo",
+        "tu", "kel", "mur", "an", "ter"
+    ];
+    // endings of towns by water and of the others
+    static ref SHORES: Vec<&'static str> = vec!["järvi", "lahti", "niemi", "salmi"];
+    static ref INLAND: Vec<&'static str> = vec!["la", "mäki", "vaara", "kylä"];
+}
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Growth {
+    Growing,
+    Stable,
+    Shrinking,
+    Abandoned,
+}
+#[derive(Clone)]
+pub struct Settlement {
+    pub id: usize,
+    pub name: String,
+    pub faction: Faction,
+    // the tile the settlement grew around and the chunk it is in
+    pub centre: (usize, usize),
+    pub chunk: usize,
+    pub population: usize,
+    // building types and the top left corners they stand at
+    pub buildings: Vec<(BuildingType, (usize, usize))>,
+    pub growth: Growth,
+}
+// the best place to settle in a chunk
+pub struct Site {
+    pub centre: (usize, usize),
+    pub score: f32,
+    pub water: bool,
+}
+// scores the candidate tiles of a chunk on water nearby, flatness and biome.
+// only heights, water and climate count, so the site stays the same once
+// buildings, roads and bridges stand on it
+pub fn site(terrain: &Terrain, config: &WorldConfig, chunk: (i64, i64)) -> Option<Site> {
+    let chunk_size = config.chunk_size as usize;
+    let side = terrain.side() as i64;
+    let (ox, oy) = (chunk.0 as usize * chunk_size, chunk.1 as usize * chunk_size);
+    let mut best: Option<Site> = None;
+    for y in (oy..oy + chunk_size).step_by(SITE_STRIDE) {
+        for x in (ox..ox + chunk_size).step_by(SITE_STRIDE) {
+            if terrain.ttype(x, y).was_water() || terrain.height(x, y) <= config.sea_level {
+                continue;
+            }
+            let here = tile_to_world((x as i64, y as i64));
+            let mut water = None;
+            let (mut low, mut high) = (f32::MAX, f32::MIN);
+            for dy in (-WATER_REACH..=WATER_REACH).step_by(2) {
+                for dx in (-WATER_REACH..=WATER_REACH).step_by(2) {
+                    let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+                    if nx < 0 || ny < 0 || nx >= side || ny >= side {
+                        continue;
+                    }
+                    let (nx, ny) = (nx as usize, ny as usize);
+                    if terrain.ttype(nx, ny).was_water() {
+                        let d = euclid(&here, &tile_to_world((nx as i64, ny as i64)));
+                        water = Some(water.map_or(d, |w: f32| w.min(d)));
+                    } else if dx.abs() <= FLAT_REACH && dy.abs() <= FLAT_REACH {
+                        let height = terrain.height(nx, ny);
+                        low = low.min(height);
+                        high = high.max(height);
+                    }
+                }
+            }
+            let biome = climate::classify(terrain.temperature(x, y), terrain.moisture(x, y));
+            let fertility = if biome.is_fertile() {
+                1.0
+            } else if biome.is_passable() && biome != TileType::Glacier {
+                0.3
+            } else {
+                0.0
+            };
+            let score = water.map_or(0.0, |d| 1.5 * (1.0 - d / WATER_REACH as f32))
+                + 1.0 / (1.0 + (high - low).max(0.0))
+                + fertility;
+            if score >= MIN_SCORE && best.as_ref().is_none_or(|b| score > b.score) {
+                best = Some(Site {
+                    centre: (x, y),
+                    score,
+                    water: water.is_some(),
+                });
+            }
+        }
+    }
+    best
+}
+// a couple of syllables and an ending, towns by water end in -järvi and the like
+fn gen_name<R: Rng>(water: bool, rng: &mut R) -> String {
+    let count = rng.gen_range(1..=2);
+    let mut name: String = (0..count)
+        .map(|_| *SYLLABLES.choose(rng).unwrap())
+        .collect();
+    let endings: &Vec<&str> = if water { &SHORES } else { &INLAND };
+    name.push_str(endings.choose(rng).unwrap());
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => name,
+    }
+}
+// settles the best site of every chunk a faction lives in and puts up the
+// first buildings around it, straight into the terrain
+pub fn found(
+    seed: u32,
+    terrain: &mut Terrain,
+    config: &WorldConfig,
+    blueprints: &Blueprints,
+) -> Vec<Settlement> {
+    let mut rng = ChaCha12Rng::seed_from_u64(pass_seed(seed, 2));
+    let mut settlements = vec![];
+    // a terrain block at a time so every block is generated once
+    let world_size = config.world_size as i64;
+    let chunk_size = config.chunk_size as usize;
+    let mut chunks: Vec<(i64, i64)> = (0..world_size * world_size)
+        .map(|i| (i % world_size, i / world_size))
+        .collect();
+    chunks.sort_by_key(|(cx, cy)| {
+        let block = terrain.block_of(*cx as usize * chunk_size, *cy as usize * chunk_size);
+        (block, *cy, *cx)
+    });
+    for (cx, cy) in chunks {
+        let faction = match chunk_faction(seed, cx as i32, cy as i32) {
+            Some(faction) => faction,
+            None => continue,
+        };
+        let site = match site(terrain, config, (cx, cy)) {
+            Some(site) => site,
+            None => continue,
+        };
+        let mut buildings = vec![];
+        for btype in FOUNDING {
+            let blueprint = match blueprints.get(&btype) {
+                Some(blueprint) => blueprint,
+                None => continue,
+            };
+            let corner = corners_around(site.centre.0, site.centre.1, BUILD_REACH)
+                .find(|c| construction::fits(terrain, config, blueprint, *c).is_some());
+            if let Some((x, y)) = corner {
+                construction::stamp(terrain, blueprint, (x as usize, y as usize));
+                buildings.push((btype, (x as usize, y as usize)));
+            }
+        }
+        settlements.push(Settlement {
+            id: settlements.len(),
+            name: gen_name(site.water, &mut rng),
+            faction,
+            centre: site.centre,
+            chunk: chunk_index((cx, cy), config.world_size).unwrap(),
+            population: 0,
+            buildings,
+            growth: Growth::Stable,
+        });
+    }
+    settlements
+}
+// what the residents of a settlement have, counted in one chunk
+#[derive(Default)]
+struct Census {
+    residents: usize,
+    fighting: usize,
+    bread: i32,
+    coins: i32,
+}
+impl World {
+    // counts the residents of every settlement in a loaded chunk and lets it
+    // grow, shrink or be abandoned: settlements with food to spare that are
+    // safe get new residents and put up houses for them, trade speeds that up.
+    // shrinking ones give up the houses they no longer need and abandoned ones
+    // all of them and their chunk
+    pub fn resolve_settlements(&mut self) {
+        let settlements = &self.settlements;
+        let counts: Vec<(usize, Census)> = self.store.collect(|chunk| {
+            settlements
+                .iter()
+                .filter(|s| s.chunk == chunk.index)
+                .map(|s| {
+                    let centre = Coords::from((s.centre.0 as f32, s.centre.1 as f32));
+                    let mut census = Census::default();
+                    for e in &chunk.entities {
+                        if e.alignment.faction != s.faction || euclid(&e.coords, &centre) > RADIUS {
+                            continue;
+                        }
+                        census.residents += 1;
+                        census.fighting += (e.status == Status::Fighting) as usize;
+                        census.bread += e.inventory.count(&Item::Bread);
+                        census.coins += e.inventory.get_coins();
+                    }
+                    census.bread += chunk
+                        .stores
+                        .get(&s.faction)
+                        .map_or(0, |store| store.count(&Item::Bread));
+                    (s.id, census)
+                })
+                .collect()
+        });
+        for (id, census) in counts {
+            let growth = if census.residents == 0 {
+                Growth::Abandoned
+            } else {
+                let people = census.residents as f32;
+                let food = census.bread as f32 / people;
+                let safety = 1.0 - census.fighting as f32 / people;
+                if food >= FOOD_PER_HEAD && safety >= SAFE {
+                    Growth::Growing
+                } else if food < FOOD_PER_HEAD / 4.0 || safety < SAFE / 2.0 {
+                    Growth::Shrinking
+                } else {
+                    Growth::Stable
+                }
+            };
+            let settlement = &mut self.settlements[id];
+            let was = settlement.growth;
+            settlement.population = census.residents;
+            settlement.growth = growth;
+            match growth {
+                Growth::Growing => {
+                    let trading = census.coins as f32 / census.residents as f32 >= WEALTH;
+                    let births = if trading { 2 } else { 1 };
+                    for _ in 0..births {
+                        self.settle(id);
+                    }
+                }
+                Growth::Stable => {}
+                Growth::Shrinking => self.shrink(id),
+                Growth::Abandoned if was != Growth::Abandoned => self.abandon(id),
+                Growth::Abandoned => {}
+            }
+        }
+    }
+    // a new resident on a free tile near the centre, and a new house once the
+    // settlement has outgrown the ones it has
+    fn settle(&mut self, id: usize) {
+        let (faction, chunk, (x, y)) = {
+            let s = &self.settlements[id];
+            (s.faction.clone(), s.chunk, s.centre)
+        };
+        let centre = Coords::from((x as f32, y as f32));
+        let reach = RADIUS as i64;
+        let free: Vec<(usize, usize)> = corners_around(x, y, reach)
+            .filter(|(x, y)| {
+                let (x, y) = (*x as usize, *y as usize);
+                x < self.terrain.side()
+                    && y < self.terrain.side()
+                    && self.terrain.ttype(x, y).is_passable()
+                    && euclid(&centre, &Coords::from((x as f32, y as f32))) <= RADIUS
+            })
+            .map(|(x, y)| (x as usize, y as usize))
+            .collect();
+        let tile = free.choose(&mut self.fetch_chunk_mut(chunk).rng).cloned();
+        if let Some((x, y)) = tile {
+            if self.spawn_in(x, y, faction).is_ok() {
+                self.settlements[id].population += 1;
+            }
+        }
+        let settlement = &self.settlements[id];
+        if settlement.population > settlement.buildings.len().max(1) * HOUSING {
+            if let Ok(corner) = self.plan(&BuildingType::SmallHouse, x, y) {
+                self.settlements[id]
+                    .buildings
+                    .push((BuildingType::SmallHouse, corner));
+            }
+        }
+    }
+    // gives up the newest houses the residents left no longer need
+    fn shrink(&mut self, id: usize) {
+        loop {
+            let settlement = &self.settlements[id];
+            let needed = settlement.population.div_ceil(HOUSING).max(1);
+            if settlement.buildings.len() <= needed {
+                return;
+            }
+            let building = self.settlements[id].buildings.pop().unwrap();
+            self.release(&building);
+        }
+    }
+    // a settlement nobody lives in gives up all its buildings, and its
+    // faction the chunk it stood in
+    fn abandon(&mut self, id: usize) {
+        let buildings = std::mem::take(&mut self.settlements[id].buildings);
+        for building in &buildings {
+            self.release(building);
+        }
+        let (faction, chunk) = (
+            self.settlements[id].faction.clone(),
+            self.settlements[id].chunk,
+        );
+        for state in &mut self.factions {
+            if state.faction == faction {
+                state.territory.remove(&chunk);
+            }
+        }
+    }
+    // cancels the parts of a building that are not built yet, what stands
+    // is left standing
+    fn release(&mut self, building: &(BuildingType, (usize, usize))) {
+        let blueprint = match self.blueprints.get(&building.0) {
+            Some(blueprint) => blueprint.clone(),
+            None => return,
+        };
+        let chunk_size = self.config.chunk_size;
+        let (cx, cy) = building.1;
+        for (dx, dy, _) in blueprint.cells() {
+            let tile = (cx as i64 + dx, cy as i64 + dy);
+            let index = match chunk_index(tile_to_chunk(tile, chunk_size), self.config.world_size) {
+                Some(index) => index,
+                None => continue,
+            };
+            let (x, y) = tile_in_chunk(tile, chunk_size);
+            self.fetch_chunk_mut(index).tiles[y * chunk_size as usize + x].designed = None;
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::worldgen::worldgen;
+
+    // a world with a settlement whose residents all carry `bread`
+    fn world(bread: i32) -> World {
+        let mut config = WorldConfig::new();
+        config.world_size = 2;
+        config.chunk_size = 32;
+        config.erosion_droplets = 1000;
+        let mut world = worldgen(3, &config).unwrap();
+        let s = world.settlements[0].clone();
+        let centre = Coords::from((s.centre.0 as f32, s.centre.1 as f32));
+        let chunk = world.fetch_chunk_mut(s.chunk);
+        chunk.stores.clear();
+        for e in &mut chunk.entities {
+            if e.alignment.faction == s.faction && euclid(&e.coords, &centre) <= RADIUS {
+                let held = e.inventory.count(&Item::Bread);
+                e.inventory.add(Item::Bread, bread - held);
+                e.status = Status::Idle;
+            }
+        }
+        world
+    }
+
+    #[test]
+    fn settlement_grows_around_its_centre() {
+        let mut world = world(2);
+        let first = world.next_id;
+        world.resolve_settlements();
+        let s = world.settlements[0].clone();
+        assert_eq!(s.growth, Growth::Growing);
+        let centre = Coords::from((s.centre.0 as f32, s.centre.1 as f32));
+        let born: Vec<Coords> = world
+            .fetch_chunk(s.chunk)
+            .entities
+            .iter()
+            .filter(|e| e.index >= first)
+            .map(|e| e.coords)
+            .collect();
+        assert!(!born.is_empty());
+        assert!(born.iter().all(|c| euclid(c, &centre) <= RADIUS));
+        // not simply the first free corner next to the centre
+        assert!(born.iter().any(|c| euclid(c, &centre) > 2.0));
+    }
+
+    #[test]
+    fn shrinking_settlement_releases_houses() {
+        let mut world = world(0);
+        // a few residents left, one house is enough for them
+        let s = world.settlements[0].clone();
+        let centre = Coords::from((s.centre.0 as f32, s.centre.1 as f32));
+        let mut kept = 0;
+        world.fetch_chunk_mut(s.chunk).entities.retain(|e| {
+            let resident = e.alignment.faction == s.faction && euclid(&e.coords, &centre) <= RADIUS;
+            kept += resident as usize;
+            !resident || kept <= 3
+        });
+        let (x, y) = s.centre;
+        let corner = world.plan(&BuildingType::SmallHouse, x, y).unwrap();
+        world.settlements[0]
+            .buildings
+            .push((BuildingType::SmallHouse, corner));
+        let buildings = world.settlements[0].buildings.len();
+        let first = world.next_id;
+        world.resolve_settlements();
+        let s = world.settlements[0].clone();
+        assert_eq!(s.growth, Growth::Shrinking);
+        assert_eq!(world.next_id, first);
+        assert!(buildings > 1);
+        assert_eq!(s.buildings.len(), 1);
+        assert!(!s.buildings.iter().any(|b| b.1 == corner));
+        let designed = world
+            .fetch_chunk(s.chunk)
+            .tiles
+            .iter()
+            .any(|t| t.designed.is_some());
+        assert!(!designed);
+    }
+
+    #[test]
+    fn abandoned_settlement_gives_up_chunk() {
+        let mut world = world(2);
+        let s = world.settlements[0].clone();
+        let centre = Coords::from((s.centre.0 as f32, s.centre.1 as f32));
+        world
+            .fetch_chunk_mut(s.chunk)
+            .entities
+            .retain(|e| e.alignment.faction != s.faction || euclid(&e.coords, &centre) > RADIUS);
+        assert_eq!(world.owner(s.chunk), Some(&s.faction));
+        world.resolve_settlements();
+        let s = world.settlements[0].clone();
+        assert_eq!(s.growth, Growth::Abandoned);
+        assert!(s.buildings.is_empty());
+        assert_eq!(world.owner(s.chunk), None);
+    }
+}
@@ -0,0 +1,242 @@
+use crate::camera::Camera;
+use crate::util::RenderMsg;
+use crate::worldgen::{Faction, News, World};
+use rayon::prelude::*;
+use std::thread;
+use std::time::Duration;
+
+// the stage 1-3 pipeline shared by the window and headless runs. stage 1 and 2
+// run once every partition of the watched world has been pushed by stage 3
+pub struct Simulation {
+    pub worlds: Vec<World>,
+    pub step_increment: i32,
+    pub partition: usize,
+    // the world whose chunks are pushed to the renderer
+    pub vic_world: usize,
+    pub paused: bool,
+    // no more steps are resolved once the first world reaches it
+    pub step_limit: Option<u64>,
+    // steps between autosaves, 0 turns autosaving off
+    pub autosave: u64,
+    pub save_path: String,
+    last_save: u64,
+}
+impl Simulation {
+    pub fn from(worlds: Vec<World>) -> Simulation {
+        let last_save = worlds.first().map(|w| w.step).unwrap_or(0);
+        Simulation {
+            worlds,
+            step_increment: 1,
+            partition: 0,
+            vic_world: 0,
+            paused: false,
+            step_limit: None,
+            autosave: 0,
+            save_path: "autosave.wgs".to_string(),
+            last_save,
+        }
+    }
+    // runs stage 1 and 2 when the partitions of the watched world have gone around
+    pub fn tick(&mut self) -> bool {
+        if self.partition < self.worlds[self.vic_world].config.partition_size() {
+            return false;
+        }
+        self.partition = 0;
+        if self.paused || self.finished() {
+            return false;
+        }
+        self.resolve();
+        if self.autosave > 0 && self.worlds[0].step >= self.last_save + self.autosave {
+            self.last_save = self.worlds[0].step;
+            if let Err(e) = self.save() {
+                eprintln!("autosave failed: {}", e);
+            }
+        }
+        true
+    }
+    pub fn resolve(&mut self) {
+        let step_increment = self.step_increment;
+        // stage 1: resolve all tensions in the world
+        self.worlds
+            .par_iter_mut()
+            .for_each(|c| c.resolve(step_increment));
+        // stage 2: resolve all tensions between chunks
+        self.worlds.par_iter_mut().for_each(|c| c.resolve_between());
+    }
+    pub fn finished(&self) -> bool {
+        match self.step_limit {
+            Some(limit) => self.worlds[0].step >= limit,
+            None => false,
+        }
+    }
+    // stage 3: the chunks of the current partition that the camera sees
+    pub fn render(&mut self, vic_world: usize, camera: &Camera) -> Vec<RenderMsg> {
+        let visible = self.visible(vic_world, camera);
+        let world = &mut self.worlds[vic_world];
+        let mut news = News::from(vec!["news ".to_string()]);
+        let mut state = vec![];
+        for i in visible {
+            let owner = world.owner(i).cloned().unwrap_or(Faction::Empty);
+            let chunk = world.fetch_chunk(i);
+            news.newscast.append(&mut chunk.inquire_news().newscast);
+            state.push(RenderMsg::from(chunk.clone(), news.clone(), owner));
+        }
+        state
+    }
+    // stage 3 without a window: loads the chunks of the current partition
+    // that the camera sees so they are simulated, nothing is copied
+    pub fn load(&mut self, vic_world: usize, camera: &Camera) {
+        for i in self.visible(vic_world, camera) {
+            self.worlds[vic_world].fetch_chunk(i);
+        }
+    }
+    // indices of the chunks of the current partition that the camera sees
+    fn visible(&self, vic_world: usize, camera: &Camera) -> Vec<usize> {
+        let world = &self.worlds[vic_world];
+        let chunk_size = world.config.chunk_size as i32;
+        let world_size = world.config.world_size as i32;
+        let mut visible = vec![];
+        for i in world.config.partition(self.partition) {
+            // decide visibility from the chunk position so that chunks outside
+            // the camera are never generated
+            let first = (
+                (i as i32 % world_size * chunk_size) as f32,
+                (i as i32 / world_size * chunk_size) as f32,
+            );
+            let last = (
+                first.0 + (chunk_size - 1) as f32,
+                first.1 + (chunk_size - 1) as f32,
+            );
+            if first.0 < camera.coords.x
+                || first.1 < camera.coords.y
+                || last.0 > (-camera.coords.x as i32 + camera.render_distance_w + chunk_size) as f32
+                || last.1 as i32 + chunk_size
+                    > -camera.coords.y as i32 + camera.render_distance_h + chunk_size + chunk_size
+            {
+                continue;
+            }
+            visible.push(i);
+        }
+        visible
+    }
+    // one frame of the window loop
+    pub fn frame(&mut self, camera: &Camera) -> Vec<RenderMsg> {
+        self.tick();
+        let state = self.render(self.vic_world, camera);
+        self.partition += 1;
+        state
+    }
+    // runs without a window until the step limit or until `until` holds,
+    // every world is watched whole so all of its chunks are simulated
+    pub fn run(&mut self, mut until: impl FnMut(&mut Simulation) -> bool) {
+        let cameras: Vec<Camera> = self
+            .worlds
+            .iter()
+            .map(|w| Camera::overview(&w.config))
+            .collect();
+        while !self.finished() && !until(self) {
+            if self.paused {
+                thread::sleep(Duration::from_millis(10));
+                continue;
+            }
+            self.tick();
+            for (i, camera) in cameras.iter().enumerate() {
+                self.load(i, camera);
+            }
+            self.partition += 1;
+        }
+    }
+    pub fn save(&self) -> Result<(), String> {
+        for (i, world) in self.worlds.iter().enumerate() {
+            world.save(&self.save_path_for(i))?;
+        }
+        Ok(())
+    }
+    // every world gets its own file when there are several
+    pub fn save_path_for(&self, world: usize) -> String {
+        if self.worlds.len() > 1 {
+            format!("{}.{}", self.save_path, world)
+        } else {
+            self.save_path.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::WorldConfig;
+    use crate::terrain::Terrain;
+    use crate::worldgen::worldgen;
+    use std::{env, fs, process};
+
+    #[test]
+    fn partition_cycle_visits_every_chunk() {
+        for world_size in 1..=17 {
+            let mut config = WorldConfig::new();
+            config.world_size = world_size;
+            config.chunk_size = 2;
+            config.erosion_droplets = 0;
+            let terrain = Terrain::gen(1, &config);
+            let camera = Camera::overview(&config);
+            let mut sim = Simulation::from(vec![World::from(terrain, 1, config.clone()).unwrap()]);
+            let mut seen = vec![0; sim.worlds[0].chunk_count()];
+            for partition in 0..config.partition_size() {
+                sim.partition = partition;
+                for i in sim.visible(0, &camera) {
+                    seen[i] += 1;
+                }
+            }
+            assert!(seen.iter().all(|&n| n == 1), "world_size {}", world_size);
+        }
+    }
+    // two small worlds run headless to the step limit, autosaving every 10
+    // steps
+    fn headless(save: &str) -> Simulation {
+        let mut config = WorldConfig::new();
+        config.world_size = 2;
+        config.chunk_size = 32;
+        config.erosion_droplets = 1000;
+        let worlds = (7..9)
+            .map(|seed| worldgen(seed, &config).unwrap())
+            .collect();
+        let mut sim = Simulation::from(worlds);
+        sim.step_increment = 3;
+        sim.step_limit = Some(30);
+        sim.autosave = 10;
+        sim.save_path = env::temp_dir()
+            .join(format!("worldgen2-test-{}-{}.wgs", process::id(), save))
+            .to_string_lossy()
+            .to_string();
+        let mut checked = 0;
+        sim.run(|_| {
+            checked += 1;
+            false
+        });
+        assert!(checked > 0);
+        // autosaved at steps 12 and 24, the run ends with a save like a
+        // headless run of the binary
+        assert_eq!(sim.last_save, 24);
+        sim.save().unwrap();
+        sim
+    }
+    #[test]
+    fn headless_runs_reach_the_step_limit_the_same_way() {
+        let sim = headless("run-a");
+        let again = headless("run-b");
+        for (i, world) in sim.worlds.iter().enumerate() {
+            assert_eq!(world.step, 30);
+            assert!(world.population() > 0);
+            assert_eq!(world.digest(), again.worlds[i].digest());
+            // the save is the world as it ended
+            for sim in [&sim, &again] {
+                let path = sim.save_path_for(i);
+                let saved = World::load(&path).unwrap();
+                fs::remove_file(&path).unwrap();
+                assert_eq!(saved.step, 30);
+                assert_eq!(saved.digest(), world.digest());
+            }
+        }
+        assert_ne!(sim.worlds[0].digest(), sim.worlds[1].digest());
+    }
+}
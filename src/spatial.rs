@@ -0,0 +1,122 @@
+use crate::worldgen::{Coords, Entity};
+use std::collections::HashMap;
+
+// index and position of every entity in one cell
+type Cell = Vec<(usize, f32, f32)>;
+
+// positions of a chunk's entities bucketed into square cells, so the entities
+// around a point are found by looking at the few cells that overlap the search
+// radius. the positions are copied in, moving the entities afterwards does not
+// change what is found
+pub struct SpatialHash {
+    cell: f32,
+    cells: HashMap<(i32, i32), Cell>,
+}
+impl SpatialHash {
+    // `cell` is best set to the distance searched most, the vicinity distance
+    pub fn from(entities: &[Entity], cell: f32) -> SpatialHash {
+        let cell = cell.max(1.0);
+        let mut cells: HashMap<(i32, i32), Cell> = HashMap::new();
+        for (i, e) in entities.iter().enumerate() {
+            cells
+                .entry((
+                    (e.coords.x / cell).floor() as i32,
+                    (e.coords.y / cell).floor() as i32,
+                ))
+                .or_default()
+                .push((i, e.coords.x, e.coords.y));
+        }
+        SpatialHash { cell, cells }
+    }
+    // indices of the entities within `radius` of `at`, cell by cell
+    pub fn near(&self, at: &Coords, radius: f32) -> Vec<usize> {
+        let reach = (radius / self.cell).ceil() as i32;
+        let (cx, cy) = (
+            (at.x / self.cell).floor() as i32,
+            (at.y / self.cell).floor() as i32,
+        );
+        let mut found = vec![];
+        for y in cy - reach..=cy + reach {
+            for x in cx - reach..=cx + reach {
+                if let Some(cell) = self.cells.get(&(x, y)) {
+                    found.extend(
+                        cell.iter()
+                            .filter(|(_, x, y)| {
+                                (x - at.x) * (x - at.x) + (y - at.y) * (y - at.y) <= radius * radius
+                            })
+                            .map(|(i, _, _)| *i),
+                    );
+                }
+            }
+        }
+        found
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(points: &[(f32, f32)]) -> Vec<Entity> {
+        points
+            .iter()
+            .enumerate()
+            .map(|(i, p)| {
+                let mut entity = Entity::new(i);
+                entity.coords = Coords::from(*p);
+                entity
+            })
+            .collect()
+    }
+    // what checking every entity finds
+    fn every(entities: &[Entity], at: &Coords, radius: f32) -> Vec<usize> {
+        (0..entities.len())
+            .filter(|i| {
+                let (dx, dy) = (entities[*i].coords.x - at.x, entities[*i].coords.y - at.y);
+                dx * dx + dy * dy <= radius * radius
+            })
+            .collect()
+    }
+
+    #[test]
+    fn near_finds_what_checking_every_entity_finds() {
+        // on cell edges and corners, below zero and far outside the world
+        let mut points = vec![
+            (0.0, 0.0),
+            (4.0, 0.0),
+            (4.0, 4.0),
+            (8.0, 4.0),
+            (-4.0, 0.0),
+            (-0.001, 3.999),
+            (-3.5, -8.0),
+            (-12.0, 4.0),
+            (1000.0, 1000.0),
+            (1004.0, 996.0),
+            (12.0, 12.0),
+        ];
+        for i in 0..40 {
+            let i = i as f32;
+            points.push(((i * 7.3) % 30.0 - 10.0, (i * 3.1) % 30.0 - 10.0));
+        }
+        let entities = at(&points);
+        let grid = SpatialHash::from(&entities, 4.0);
+        for radius in [0.0, 1.0, 4.0, 5.5, 10.0, 40.0] {
+            for p in &points {
+                for offset in [(0.0, 0.0), (0.5, -2.0), (-4.0, 4.0)] {
+                    let at = Coords::from((p.0 + offset.0, p.1 + offset.1));
+                    let mut found = grid.near(&at, radius);
+                    found.sort();
+                    assert_eq!(found, every(&entities, &at, radius), "{:?} {}", p, radius);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn small_cells_are_widened() {
+        let entities = at(&[(0.0, 0.0), (0.5, 0.5), (3.0, 0.0)]);
+        let grid = SpatialHash::from(&entities, 0.0);
+        let mut found = grid.near(&Coords::from((0.0, 0.0)), 3.0);
+        found.sort();
+        assert_eq!(found, vec![0, 1, 2]);
+    }
+}
@@ -0,0 +1,233 @@
+use crate::worldgen::{Inventory, Item};
+use rand::Rng;
+
+// health every entity has, each point of strength adds STRENGTH_HEALTH
+const BASE_HEALTH: u8 = 80;
+const STRENGTH_HEALTH: u8 = 4;
+// hunger of a fed entity, it goes down by one every step
+pub const FULL: u8 = 100;
+// above this hunger an entity heals REGEN health every step
+const WELL_FED: u8 = 50;
+const REGEN: u8 = 1;
+// a starving entity loses one more health for every STARVATION_RAMP steps
+// it has gone without food
+const STARVATION_RAMP: u8 = 10;
+// chance in percent to dodge a hit per point of agility, and at most
+const DODGE_PER_AGILITY: u8 = 3;
+const MAX_DODGE: u8 = 50;
+// sides of the damage die
+const DAMAGE_ROLL: u8 = 6;
+
+#[derive(Clone)]
+pub struct Stats {
+    pub(crate) health: u8,
+    pub(crate) max_health: u8,
+    pub(crate) hunger: u8,
+    // steps gone hungry in a row
+    pub(crate) starvation: u8,
+    pub(crate) strength: u8,
+    pub(crate) intelligence: u8,
+    pub(crate) agility: u8,
+}
+impl Default for Stats {
+    fn default() -> Stats {
+        Stats::new()
+    }
+}
+impl Stats {
+    pub fn new() -> Stats {
+        Stats::from(10, 10, 10)
+    }
+    pub fn from(strength: u8, intelligence: u8, agility: u8) -> Stats {
+        let max_health = max_health(strength);
+        Stats {
+            health: max_health,
+            max_health,
+            hunger: FULL,
+            starvation: 0,
+            strength,
+            intelligence,
+            agility,
+        }
+    }
+    pub fn gen<R: Rng>(rng: &mut R) -> Stats {
+        Stats::from(
+            rng.gen_range(0..10),
+            rng.gen_range(0..10),
+            rng.gen_range(0..10),
+        )
+    }
+    pub fn is_dead(&self) -> bool {
+        self.health == 0
+    }
+    pub fn hurt(&mut self, damage: u8) {
+        self.health = self.health.saturating_sub(damage);
+    }
+    pub fn heal(&mut self, amount: u8) {
+        self.health = self.health.saturating_add(amount).min(self.max_health);
+    }
+    pub fn feed(&mut self, amount: u8) {
+        self.hunger = self.hunger.saturating_add(amount).min(FULL);
+        self.starvation = 0;
+    }
+    // one step of getting hungrier, starving once hunger is gone and healing
+    // while well fed
+    pub fn tick(&mut self) {
+        self.hunger = self.hunger.saturating_sub(1);
+        if self.hunger == 0 {
+            self.starvation = self.starvation.saturating_add(1);
+            self.hurt(starvation_damage(self.starvation));
+        } else {
+            self.starvation = 0;
+            if self.hunger > WELL_FED {
+                self.heal(REGEN);
+            }
+        }
+    }
+}
+pub fn max_health(strength: u8) -> u8 {
+    BASE_HEALTH.saturating_add(strength.saturating_mul(STRENGTH_HEALTH))
+}
+// health lost in a step after `starvation` steps without food
+pub fn starvation_damage(starvation: u8) -> u8 {
+    1u8.saturating_add(starvation / STARVATION_RAMP)
+}
+pub fn dodge_chance(agility: u8) -> u8 {
+    agility.saturating_mul(DODGE_PER_AGILITY).min(MAX_DODGE)
+}
+impl Item {
+    // damage added to a hit by a weapon
+    pub fn attack(&self) -> u8 {
+        match self {
+            Item::Sword => 5,
+            _ => 0,
+        }
+    }
+    // damage taken off a hit by armour
+    pub fn defence(&self) -> u8 {
+        match self {
+            Item::Armour => 4,
+            _ => 0,
+        }
+    }
+}
+// the best weapon and armour carried count, carrying more does not help
+pub fn weapon(inventory: &Inventory) -> u8 {
+    inventory.carried().map(|i| i.attack()).max().unwrap_or(0)
+}
+pub fn armour(inventory: &Inventory) -> u8 {
+    inventory.carried().map(|i| i.defence()).max().unwrap_or(0)
+}
+// damage of one hit, `dodge` and `roll` come from the dice: the hit misses
+// when `dodge` (0..100) is below the defender's dodge chance, otherwise it does
+// (1 + strength + weapon) * roll / 2 less the armour, with `roll` in 1..=6
+pub fn damage(
+    attacker: &Stats,
+    attacker_items: &Inventory,
+    defender: &Stats,
+    defender_items: &Inventory,
+    dodge: u8,
+    roll: u8,
+) -> u8 {
+    if dodge < dodge_chance(defender.agility) {
+        return 0;
+    }
+    let attack = 1u8
+        .saturating_add(attacker.strength)
+        .saturating_add(weapon(attacker_items));
+    // worked out wide so a strong hit is not halved after saturating
+    let hit = (attack as u16 * roll as u16 / 2).min(255) as u8;
+    hit.saturating_sub(armour(defender_items))
+}
+pub fn roll_damage<R: Rng>(
+    attacker: &Stats,
+    attacker_items: &Inventory,
+    defender: &Stats,
+    defender_items: &Inventory,
+    rng: &mut R,
+) -> u8 {
+    let dodge = rng.gen_range(0..100);
+    let roll = rng.gen_range(1..=DAMAGE_ROLL);
+    damage(
+        attacker,
+        attacker_items,
+        defender,
+        defender_items,
+        dodge,
+        roll,
+    )
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn health_saturates() {
+        let mut stats = Stats::default();
+        stats.hurt(255);
+        assert_eq!(stats.health, 0);
+        assert!(stats.is_dead());
+        stats.hurt(1);
+        assert_eq!(stats.health, 0);
+        stats.heal(255);
+        assert_eq!(stats.health, stats.max_health);
+        let mut strong = Stats::from(255, 0, 0);
+        assert_eq!(strong.max_health, 255);
+        strong.heal(255);
+        assert_eq!(strong.health, 255);
+        strong.feed(255);
+        assert_eq!(strong.hunger, FULL);
+    }
+    #[test]
+    fn max_health_by_strength() {
+        assert_eq!(max_health(0), BASE_HEALTH);
+        assert_eq!(max_health(10), BASE_HEALTH + 10 * STRENGTH_HEALTH);
+        assert_eq!(max_health(255), 255);
+    }
+    #[test]
+    fn starvation_ramps_up() {
+        assert_eq!(starvation_damage(1), 1);
+        assert_eq!(starvation_damage(STARVATION_RAMP - 1), 1);
+        assert_eq!(starvation_damage(STARVATION_RAMP), 2);
+        assert_eq!(starvation_damage(255), 1 + 255 / STARVATION_RAMP);
+        let mut stats = Stats::from(0, 0, 0);
+        stats.hunger = 1;
+        stats.tick();
+        assert_eq!((stats.hunger, stats.starvation), (0, 1));
+        assert_eq!(stats.health, BASE_HEALTH - 1);
+        stats.starvation = 255;
+        stats.tick();
+        assert_eq!(stats.starvation, 255);
+        stats.feed(10);
+        assert_eq!(stats.starvation, 0);
+    }
+    #[test]
+    fn dodge_is_bounded() {
+        assert_eq!(dodge_chance(0), 0);
+        assert_eq!(dodge_chance(10), 10 * DODGE_PER_AGILITY);
+        assert_eq!(dodge_chance(255), MAX_DODGE);
+        let nimble = Stats::from(0, 0, 255);
+        let none = Inventory::new();
+        let strong = Stats::from(255, 0, 0);
+        assert_eq!(damage(&strong, &none, &nimble, &none, MAX_DODGE - 1, 6), 0);
+        assert!(damage(&strong, &none, &nimble, &none, MAX_DODGE, 6) > 0);
+    }
+    #[test]
+    fn armour_above_attack_does_no_damage() {
+        let weak = Stats::from(0, 0, 0);
+        let none = Inventory::new();
+        let mut armoured = Inventory::new();
+        armoured.add(Item::Armour, 1);
+        assert_eq!(damage(&weak, &none, &weak, &armoured, 99, 1), 0);
+        assert_eq!(damage(&weak, &none, &weak, &armoured, 99, 6), 0);
+        let mut armed = Inventory::new();
+        armed.add(Item::Sword, 1);
+        assert_eq!(damage(&weak, &armed, &weak, &armoured, 99, 6), 18 - 4);
+        // 66 * 6 / 2, more than fits before halving
+        let strong = Stats::from(60, 0, 0);
+        assert_eq!(damage(&strong, &armed, &weak, &none, 99, 6), 198);
+        let strongest = Stats::from(255, 0, 0);
+        assert_eq!(damage(&strongest, &armed, &weak, &none, 99, 6), 255);
+        assert_eq!(damage(&strongest, &armed, &weak, &armoured, 99, 6), 251);
+    }
+}
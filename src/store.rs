@@ -0,0 +1,334 @@
+use crate::config::WorldConfig;
+use crate::region::RegionStore;
+use crate::terrain::Terrain;
+use crate::worldgen::{Chunk, Coords, Entity, Tile};
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::mem::size_of;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static STORES: AtomicUsize = AtomicUsize::new(0);
+
+// keeps a bounded set of generated chunks in memory, least recently used
+// chunks are dropped when the budget is exceeded and regenerated from the seed
+// next time; chunks that were simulated are paged out to region files instead
+pub struct ChunkStore {
+    pub budget: usize,
+    hot: HashMap<usize, Chunk>,
+    last_used: HashMap<usize, u64>,
+    clock: u64,
+    modified: HashSet<usize>,
+    pager: RegionStore,
+}
+impl ChunkStore {
+    // fails when the page directory cannot be created
+    pub fn from(config: &WorldConfig, seed: u32) -> Result<ChunkStore, String> {
+        // every store gets its own directory, worlds sharing a page_dir each
+        // get a subdirectory by seed, process and world so runs at the same
+        // time never clear each other's pages
+        let store = STORES.fetch_add(1, Ordering::Relaxed);
+        let dir = if config.page_dir.is_empty() {
+            env::temp_dir().join(format!("worldgen2-{}-{}", process::id(), store))
+        } else {
+            let name = format!("{}-{}-{}", seed, process::id(), store);
+            let dir = PathBuf::from(&config.page_dir).join(name);
+            // pages left by an earlier run with the same process id are
+            // stale, what was simulated then is in its save
+            let _ = fs::remove_dir_all(&dir);
+            dir
+        };
+        Ok(ChunkStore {
+            budget: ChunkStore::budget_for(config),
+            hot: HashMap::new(),
+            last_used: HashMap::new(),
+            clock: 0,
+            modified: HashSet::new(),
+            pager: RegionStore::from(&dir, config.region_size, config.world_size as usize)?,
+        })
+    }
+    // amount of chunks that fit in their half of the configured memory budget
+    pub fn budget_for(config: &WorldConfig) -> usize {
+        let tiles = (config.chunk_size * config.chunk_size) as usize;
+        let chunk_bytes = tiles * size_of::<Tile>() + tiles / 32 * size_of::<Entity>();
+        ((config.memory_budget * 1024 * 1024 / 2) / chunk_bytes.max(1)).max(1)
+    }
+    pub fn is_loaded(&self, index: usize) -> bool {
+        self.hot.contains_key(&index)
+    }
+    pub fn is_modified(&self, index: usize) -> bool {
+        self.modified.contains(&index)
+    }
+    pub fn loaded(&self) -> usize {
+        self.hot.len()
+    }
+    // chunks that differ from what the seed generates, in index order
+    pub fn modified(&self) -> Vec<usize> {
+        let mut modified: Vec<usize> = self.modified.iter().cloned().collect();
+        modified.sort();
+        modified
+    }
+    // entities in loaded and paged out chunks, untouched chunks are not counted
+    pub fn population(&self) -> usize {
+        let hot: usize = self.hot.values().map(|c| c.entities.len()).sum();
+        let paged: usize = self
+            .modified
+            .iter()
+            .filter(|i| !self.hot.contains_key(i))
+            .filter_map(|i| self.paged(*i))
+            .map(|c| c.entities.len())
+            .sum();
+        hot + paged
+    }
+    // searches loaded and paged out chunks, returns the chunk index as well
+    pub fn find_entity(&self, id: usize) -> Option<(usize, Entity)> {
+        for (index, chunk) in &self.hot {
+            if let Some(e) = chunk.entities.iter().find(|e| e.index == id) {
+                return Some((*index, e.clone()));
+            }
+        }
+        self.modified()
+            .into_iter()
+            .filter(|i| !self.hot.contains_key(i))
+            .filter_map(|i| self.paged(i))
+            .find_map(|c| {
+                let index = c.index;
+                c.entities
+                    .into_iter()
+                    .find(|e| e.index == id)
+                    .map(|e| (index, e))
+            })
+    }
+    // takes a chunk that was simulated elsewhere, it is paged in on first use
+    pub fn restore(&mut self, chunk: Chunk) -> Result<(), String> {
+        self.hot.remove(&chunk.index);
+        self.last_used.remove(&chunk.index);
+        self.modified.insert(chunk.index);
+        self.pager.write(&chunk)
+    }
+    pub fn get(
+        &mut self,
+        index: usize,
+        seed: u32,
+        config: &WorldConfig,
+        terrain: &Terrain,
+    ) -> &Chunk {
+        self.load(index, seed, config, terrain);
+        &self.hot[&index]
+    }
+    pub fn get_mut(
+        &mut self,
+        index: usize,
+        seed: u32,
+        config: &WorldConfig,
+        terrain: &Terrain,
+    ) -> &mut Chunk {
+        self.load(index, seed, config, terrain);
+        self.modified.insert(index);
+        self.hot.get_mut(&index).unwrap()
+    }
+    // looks at a chunk without loading it into the hot set
+    pub fn with_chunk<T>(
+        &self,
+        index: usize,
+        seed: u32,
+        config: &WorldConfig,
+        terrain: &Terrain,
+        f: impl FnOnce(&Chunk) -> T,
+    ) -> T {
+        if let Some(chunk) = self.hot.get(&index) {
+            return f(chunk);
+        }
+        match self.paged(index) {
+            Some(chunk) => f(&chunk),
+            None => f(&ChunkStore::generate(index, seed, config, terrain)),
+        }
+    }
+    // every loaded chunk, chunks with entities count as modified afterwards
+    pub fn resolve(&mut self, step_increment: i32, config: &WorldConfig, terrain: &Terrain) {
+        self.hot
+            .par_iter_mut()
+            .for_each(|(_, c)| c.resolve(step_increment, config, terrain));
+        for (index, chunk) in &self.hot {
+            if !chunk.entities.is_empty() {
+                self.modified.insert(*index);
+            }
+        }
+    }
+    // read phase over the loaded chunks, the results come in chunk order
+    pub fn collect<T: Send>(&self, f: impl Fn(&Chunk) -> Vec<T> + Sync) -> Vec<T> {
+        let mut found: Vec<(usize, Vec<T>)> = self
+            .hot
+            .par_iter()
+            .map(|(index, chunk)| (*index, f(chunk)))
+            .collect();
+        found.sort_by_key(|(index, _)| *index);
+        found.into_iter().flat_map(|(_, found)| found).collect()
+    }
+    // write phase over the loaded chunks, `f` tells whether it changed the chunk
+    pub fn apply(&mut self, f: impl Fn(&mut Chunk) -> bool + Sync + Send) {
+        let changed: Vec<usize> = self
+            .hot
+            .par_iter_mut()
+            .filter_map(|(index, chunk)| if f(chunk) { Some(*index) } else { None })
+            .collect();
+        self.modified.extend(changed);
+    }
+    pub fn generate(index: usize, seed: u32, config: &WorldConfig, terrain: &Terrain) -> Chunk {
+        let world_size = config.world_size as usize;
+        let coords = Coords::from(((index % world_size) as f32, (index / world_size) as f32));
+        Chunk::from(vec![], vec![], coords, index).gen(seed, config, terrain)
+    }
+    fn load(&mut self, index: usize, seed: u32, config: &WorldConfig, terrain: &Terrain) {
+        self.clock += 1;
+        self.last_used.insert(index, self.clock);
+        if self.hot.contains_key(&index) {
+            return;
+        }
+        while self.hot.len() >= self.budget && self.evict() {}
+        let chunk = match self.paged(index) {
+            Some(chunk) => chunk,
+            None => ChunkStore::generate(index, seed, config, terrain),
+        };
+        self.hot.insert(index, chunk);
+    }
+    // drops the least recently used chunk, false when it could not be paged
+    // out and stays in memory over the budget
+    fn evict(&mut self) -> bool {
+        let coldest = self
+            .hot
+            .keys()
+            .min_by_key(|i| self.last_used.get(i).unwrap_or(&0))
+            .cloned();
+        let index = match coldest {
+            Some(index) => index,
+            None => return false,
+        };
+        if self.modified.contains(&index) {
+            if let Err(e) = self.pager.write(&self.hot[&index]) {
+                eprintln!("cannot page out chunk {}, keeping it: {}", index, e);
+                return false;
+            }
+        }
+        self.hot.remove(&index);
+        self.last_used.remove(&index);
+        true
+    }
+    // a chunk that cannot be read back is generated again from the seed
+    fn paged(&self, index: usize) -> Option<Chunk> {
+        if !self.modified.contains(&index) {
+            return None;
+        }
+        self.pager.read(index).unwrap_or_else(|e| {
+            eprintln!("cannot page in chunk {}, generating it again: {}", index, e);
+            None
+        })
+    }
+}
+// pages are only of use to the run that wrote them
+impl Drop for ChunkStore {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.pager.dir);
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec;
+    use crate::worldgen::TileType;
+
+    // 4 x 4 chunks of 8 tiles
+    fn config() -> WorldConfig {
+        let mut config = WorldConfig::new();
+        config.world_size = 4;
+        config.chunk_size = 8;
+        config.erosion_droplets = 100;
+        config
+    }
+
+    #[test]
+    fn budget_follows_memory_budget() {
+        let mut config = WorldConfig::new();
+        let budget = ChunkStore::budget_for(&config);
+        assert!(budget > 1);
+        config.memory_budget *= 2;
+        assert!(ChunkStore::budget_for(&config).abs_diff(2 * budget) <= 1);
+        // a single chunk bigger than the budget is still kept
+        config.memory_budget = 1;
+        config.chunk_size = 4096;
+        assert_eq!(ChunkStore::budget_for(&config), 1);
+    }
+    #[test]
+    fn least_recently_used_is_evicted() {
+        let config = config();
+        let terrain = Terrain::gen(2, &config);
+        let mut store = ChunkStore::from(&config, 2).unwrap();
+        store.budget = 2;
+        store.get(0, 2, &config, &terrain);
+        store.get(1, 2, &config, &terrain);
+        store.get(0, 2, &config, &terrain);
+        store.get(2, 2, &config, &terrain);
+        assert_eq!(store.loaded(), 2);
+        assert!(store.is_loaded(0) && store.is_loaded(2));
+        assert!(!store.is_loaded(1));
+    }
+    #[test]
+    fn untouched_chunks_are_regenerated_and_simulated_paged_in() {
+        let config = config();
+        let terrain = Terrain::gen(2, &config);
+        let mut store = ChunkStore::from(&config, 2).unwrap();
+        store.budget = 1;
+        let untouched = codec::encode(store.get(0, 2, &config, &terrain));
+        let chunk = store.get_mut(1, 2, &config, &terrain);
+        chunk.entities.push(Entity::new(99));
+        chunk.tiles[3].ttype = TileType::WoodenWall;
+        store.get(2, 2, &config, &terrain);
+        assert!(!store.is_loaded(0) && !store.is_loaded(1));
+        assert_eq!(store.modified(), vec![1]);
+        // the simulated chunk was paged out and is found there
+        assert_eq!(store.population(), 1);
+        assert_eq!(store.find_entity(99).map(|(i, _)| i), Some(1));
+        let paged = store.get(1, 2, &config, &terrain);
+        assert_eq!(paged.entities[0].index, 99);
+        assert_eq!(paged.tiles[3].ttype, TileType::WoodenWall);
+        assert!(!store.is_modified(0));
+        assert_eq!(codec::encode(store.get(0, 2, &config, &terrain)), untouched);
+    }
+    #[test]
+    fn unusable_page_dir_fails() {
+        // a file where the page directory should go cannot hold the pages
+        let file = env::temp_dir().join(format!("worldgen2-test-{}-page-file", process::id()));
+        fs::write(&file, b"").unwrap();
+        let mut config = WorldConfig::new();
+        config.page_dir = file.to_string_lossy().to_string();
+        assert!(ChunkStore::from(&config, 1).is_err());
+        fs::remove_file(&file).unwrap();
+    }
+    #[test]
+    fn worlds_sharing_a_page_dir_keep_apart() {
+        let dir = env::temp_dir().join(format!("worldgen2-test-{}-pages", process::id()));
+        let mut config = WorldConfig::new();
+        config.page_dir = dir.to_string_lossy().to_string();
+        let a = ChunkStore::from(&config, 1).unwrap();
+        let b = ChunkStore::from(&config, 1).unwrap();
+        assert_ne!(a.pager.dir, b.pager.dir);
+        let name = a
+            .pager
+            .dir
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .to_string();
+        assert!(name.starts_with(&format!("1-{}-", process::id())));
+        // the pages go with the world
+        fs::create_dir_all(&a.pager.dir).unwrap();
+        let paged = a.pager.dir.clone();
+        drop(a);
+        assert!(!paged.exists());
+        drop(b);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,716 @@
+use crate::config::WorldConfig;
+use crate::construction;
+use crate::faction::{self, Relation};
+use crate::math::{self, euclid, tile_to_world, world_to_tile, NEIGHBOURS_8};
+use crate::needs::{self, CARRY};
+use crate::path;
+use crate::roads;
+use crate::worldgen::{Chunk, Coords, Entity, Inventory, Item, Status, Tasks, Tile, TileType};
+use rand::Rng;
+use std::collections::HashMap;
+
+// how far from the entity a task looks for a tile to work on, in tiles
+const SEARCH: i64 = 8;
+// wood a faction's store in one chunk holds at most
+const WOOD_LIMIT: i32 = 100;
+// how close an entity has to be to its target to work on it
+const REACH: f32 = 1.0;
+// work it takes to finish a task, an entity does 1 + intelligence / 4 a step
+const FARM_WORK: u16 = 8;
+const BUILD_WORK: u16 = 30;
+const CUT_WORK: u16 = 12;
+const FIGHT_WORK: u16 = 1;
+// entities more aggressive than this also raid factions they are at peace with
+const RAIDER: u8 = 90;
+// coins an entity is paid for cutting wood or putting up a tile, enough to buy
+// bread with when it does not farm
+const WAGE: i32 = 1;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TaskKind {
+    Build,
+    Fight,
+    AnimalHusbandry,
+    Industry,
+    Farm,
+    OilRig,
+}
+// what a task is done to, tiles by their index in the chunk and entities by
+// their id
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Target {
+    Tile(usize),
+    Entity(usize),
+}
+// the task an entity is busy with
+#[derive(Clone, Debug)]
+pub struct Job {
+    pub kind: TaskKind,
+    pub target: Target,
+    pub progress: u16,
+}
+impl Job {
+    pub fn from(kind: TaskKind, target: Target) -> Job {
+        Job {
+            kind,
+            target,
+            progress: 0,
+        }
+    }
+}
+pub trait Task: Sync {
+    // whether the entity can take on the task at all right now
+    fn ready(&self, entity: &Entity, chunk: &Chunk, config: &WorldConfig) -> bool;
+    // where the work is done, None when there is nothing to work on
+    fn target(&self, entity: &Entity, chunk: &Chunk, config: &WorldConfig) -> Option<Target>;
+    // work it takes at the target, see `work`
+    fn work(&self) -> u16;
+    fn reach(&self, _config: &WorldConfig) -> f32 {
+        REACH
+    }
+    // what finishing the task does to the world, `worker` is the entity's
+    // index in the chunk
+    fn finish(&self, worker: usize, target: Target, chunk: &mut Chunk, config: &WorldConfig);
+}
+// the tasks that can be done so far
+pub fn task(kind: TaskKind) -> Option<&'static dyn Task> {
+    match kind {
+        TaskKind::Build => Some(&Build),
+        TaskKind::Fight => Some(&Fight),
+        TaskKind::Farm => Some(&Farm),
+        TaskKind::Industry => Some(&Industry),
+        TaskKind::AnimalHusbandry | TaskKind::OilRig => None,
+    }
+}
+impl Tasks {
+    pub fn get(&self, kind: TaskKind) -> (u8, bool) {
+        match kind {
+            TaskKind::Build => self.build,
+            TaskKind::Fight => self.fight,
+            TaskKind::AnimalHusbandry => self.animal_husbandry,
+            TaskKind::Industry => self.industry,
+            TaskKind::Farm => self.farm,
+            TaskKind::OilRig => self.oil_rig,
+        }
+    }
+    // the tasks an entity is willing to do, highest priority first. priority 0
+    // or not being available rules a task out
+    pub fn by_priority(&self) -> Vec<TaskKind> {
+        let mut kinds: Vec<TaskKind> = [
+            TaskKind::Build,
+            TaskKind::Fight,
+            TaskKind::AnimalHusbandry,
+            TaskKind::Industry,
+            TaskKind::Farm,
+            TaskKind::OilRig,
+        ]
+        .into_iter()
+        .filter(|kind| {
+            let (priority, available) = self.get(*kind);
+            priority > 0 && available
+        })
+        .collect();
+        kinds.sort_by_key(|kind| std::cmp::Reverse(self.get(*kind).0));
+        kinds
+    }
+}
+// work an entity does on a task in one step
+pub fn work(entity: &Entity) -> u16 {
+    1 + entity.stats.intelligence as u16 / 4
+}
+// where a target is, None once it is gone from the chunk
+pub fn locate(target: &Target, chunk: &Chunk) -> Option<Coords> {
+    match target {
+        Target::Tile(i) => chunk
+            .tiles
+            .get(*i)
+            .map(|t| tile_to_world((t.coords.x as i64, t.coords.y as i64))),
+        Target::Entity(id) => chunk
+            .entities
+            .iter()
+            .find(|e| e.index == *id)
+            .map(|e| e.coords),
+    }
+}
+// the first task by priority that is ready and has something to work on.
+// anyone hungry with nothing to eat forages first, farmer or not
+fn pick(entity: &Entity, chunk: &Chunk, config: &WorldConfig) -> Option<Job> {
+    let mut kinds = entity.tasks.by_priority();
+    if foraging(entity) {
+        kinds.retain(|kind| *kind != TaskKind::Farm);
+        kinds.insert(0, TaskKind::Farm);
+    }
+    kinds.into_iter().find_map(|kind| {
+        let task = task(kind)?;
+        if !task.ready(entity, chunk, config) {
+            return None;
+        }
+        Some(Job::from(kind, task.target(entity, chunk, config)?))
+    })
+}
+// whether an entity is hungry with nothing to eat and drops other work to
+// find food
+fn foraging(entity: &Entity) -> bool {
+    needs::is_hungry(entity) && entity.inventory.count(&Item::Bread) == 0
+}
+// one step of work for every entity in the chunk. entities that are idle pick
+// a task, the others walk to their target and work on it once they are there.
+// entities sent somewhere are left alone until they arrive or go hungry with
+// nothing to eat
+pub fn resolve(chunk: &mut Chunk, config: &WorldConfig) {
+    for i in 0..chunk.entities.len() {
+        let entity = &chunk.entities[i];
+        let (job, picked) = match &entity.job {
+            Some(job)
+                if task(job.kind).is_some_and(|t| t.ready(entity, chunk, config))
+                    && (job.kind == TaskKind::Farm || !foraging(entity)) =>
+            {
+                (Some(job.clone()), false)
+            }
+            _ if entity.target.is_none() || foraging(entity) => (pick(entity, chunk, config), true),
+            _ => (None, false),
+        };
+        let mut job = match job {
+            Some(job) => job,
+            None => {
+                drop_job(&mut chunk.entities[i]);
+                continue;
+            }
+        };
+        let task = task(job.kind).unwrap();
+        let at = match locate(&job.target, chunk) {
+            Some(at) => at,
+            None => {
+                drop_job(&mut chunk.entities[i]);
+                continue;
+            }
+        };
+        if euclid(&entity.coords, &at) <= task.reach(config) {
+            job.progress = job.progress.saturating_add(work(entity));
+        }
+        if job.progress >= task.work() {
+            let entity = &mut chunk.entities[i];
+            entity.job = None;
+            entity.target = None;
+            entity.path.clear();
+            task.finish(i, job.target, chunk, config);
+        } else {
+            // the way to a new job goes around walls and water, once there
+            // the target is set every step so the entity stays at it
+            let mut path = if picked {
+                route(
+                    chunk,
+                    config,
+                    world_to_tile(&entity.coords),
+                    world_to_tile(&at),
+                )
+                .unwrap_or_default()
+            } else {
+                vec![]
+            };
+            // far targets are walked to straight and sent by road afterwards
+            let entity = &mut chunk.entities[i];
+            if picked && !path.is_empty() {
+                entity.target = Some(path.remove(0));
+                entity.path = path;
+            } else if picked || entity.target.is_none() {
+                entity.target = Some(at);
+                if picked && roads::is_long(&entity.coords, &at) {
+                    let id = entity.index;
+                    chunk.walks.push((id, at));
+                }
+            }
+            chunk.entities[i].job = Some(job);
+        }
+    }
+}
+// stops working, and walking to where the work was
+pub fn drop_job(entity: &mut Entity) {
+    if entity.job.take().is_some() {
+        entity.target = None;
+        entity.path.clear();
+    }
+}
+// index of the closest tile within SEARCH of the entity that is in the chunk,
+// can be walked to and passes `wanted`
+fn nearest_tile(
+    entity: &Entity,
+    chunk: &Chunk,
+    config: &WorldConfig,
+    wanted: impl Fn(&Tile) -> bool,
+) -> Option<usize> {
+    best_tile(entity, chunk, config, |t| wanted(t) as u32)
+}
+// index of the tile within SEARCH of the entity that is in the chunk and can
+// be walked to, scoring highest and the closest of those when several do.
+// tiles scoring 0 are never picked
+fn best_tile(
+    entity: &Entity,
+    chunk: &Chunk,
+    config: &WorldConfig,
+    score: impl Fn(&Tile) -> u32,
+) -> Option<usize> {
+    let from = world_to_tile(&entity.coords);
+    if from.0 < 0 || from.1 < 0 {
+        return None;
+    }
+    let side = (config.world_size * config.chunk_size) as usize;
+    // the best tile for whoever can walk anywhere around, and the best one
+    // that can be walked to when `reached` says which can
+    let best = |reached: Option<&HashMap<usize, usize>>| {
+        let mut best: Option<(usize, u32, f32, (i64, i64))> = None;
+        for dy in -SEARCH..=SEARCH {
+            for dx in -SEARCH..=SEARCH {
+                let tile = (from.0 + dx, from.1 + dy);
+                let at = tile_to_world(tile);
+                let t = match chunk.tile_at(&at, config.chunk_size) {
+                    Some(t) => t,
+                    None => continue,
+                };
+                let i = tile.1 as usize * side + tile.0 as usize;
+                if reached.is_some_and(|r| !r.contains_key(&i)) {
+                    continue;
+                }
+                let (s, d) = (score(t), euclid(&entity.coords, &at));
+                if s > 0 && best.is_none_or(|(_, bs, bd, _)| s > bs || (s == bs && d < bd)) {
+                    best = Some((t.index, s, d, tile));
+                }
+            }
+        }
+        best
+    };
+    // mostly it can be walked to, only when it cannot is every tile around
+    // searched for the ones that can
+    let (i, _, _, tile) = best(None)?;
+    if route(chunk, config, from, tile).is_some() {
+        return Some(i);
+    }
+    let reached = path::reachable(
+        side,
+        (from.0 as usize, from.1 as usize),
+        |x, y| nearby(chunk, config, from, (x, y)),
+        |a, b| step_cost(chunk, config, a, b),
+    );
+    best(Some(&reached)).map(|(i, ..)| i)
+}
+// centres of the tiles on the cheapest walk to a tile within SEARCH that stays
+// in the chunk, without the start. None when it cannot be walked to
+fn route(
+    chunk: &Chunk,
+    config: &WorldConfig,
+    from: (i64, i64),
+    to: (i64, i64),
+) -> Option<Vec<Coords>> {
+    if from.0 < 0 || from.1 < 0 || to.0 < 0 || to.1 < 0 {
+        return None;
+    }
+    let side = (config.world_size * config.chunk_size) as usize;
+    let path = path::astar(
+        side,
+        (from.0 as usize, from.1 as usize),
+        (to.0 as usize, to.1 as usize),
+        |x, y| nearby(chunk, config, from, (x, y)),
+        |a, b| step_cost(chunk, config, a, b),
+        path::MIN_COST,
+    )?;
+    Some(
+        path.into_iter()
+            .map(|i| tile_to_world(((i % side) as i64, (i / side) as i64)))
+            .collect(),
+    )
+}
+// whether a tile is in the chunk and within SEARCH of where a walk starts
+fn nearby(chunk: &Chunk, config: &WorldConfig, from: (i64, i64), to: (usize, usize)) -> bool {
+    let (x, y) = (to.0 as i64, to.1 as i64);
+    (x - from.0).abs() <= SEARCH
+        && (y - from.1).abs() <= SEARCH
+        && chunk
+            .tile_at(&tile_to_world((x, y)), config.chunk_size)
+            .is_some()
+}
+// cost of a step between neighbouring tiles of the chunk, by the tile types
+// the chunk holds rather than the terrain, which only learns of walls built
+// at the end of the step
+fn step_cost(
+    chunk: &Chunk,
+    config: &WorldConfig,
+    from: (usize, usize),
+    to: (usize, usize),
+) -> Option<f32> {
+    let tile = chunk.tile_at(
+        &tile_to_world((to.0 as i64, to.1 as i64)),
+        config.chunk_size,
+    )?;
+    let cost = path::tile_cost(&tile.ttype)?;
+    Some(if from.0 != to.0 && from.1 != to.1 {
+        cost * std::f32::consts::SQRT_2
+    } else {
+        cost
+    })
+}
+// chance in percent that farming a tile grows a bread. fertile land always
+// does, elsewhere farmers forage and hunt and come home empty-handed more
+// often
+pub fn harvest(ttype: &TileType) -> u32 {
+    match ttype {
+        TileType::Grass | TileType::Steppe | TileType::TemperateForest => 100,
+        TileType::Taiga | TileType::Marsh => 80,
+        TileType::Tundra | TileType::Desert => 60,
+        TileType::Glacier => 30,
+        _ => 0,
+    }
+}
+// grows a bread on the best ground nearby, see `harvest`
+pub struct Farm;
+impl Task for Farm {
+    fn ready(&self, entity: &Entity, _chunk: &Chunk, _config: &WorldConfig) -> bool {
+        entity.inventory.count(&Item::Bread) < CARRY
+    }
+    fn target(&self, entity: &Entity, chunk: &Chunk, config: &WorldConfig) -> Option<Target> {
+        best_tile(entity, chunk, config, |t| harvest(&t.ttype)).map(Target::Tile)
+    }
+    fn work(&self) -> u16 {
+        FARM_WORK
+    }
+    fn finish(&self, worker: usize, target: Target, chunk: &mut Chunk, _config: &WorldConfig) {
+        let chance = match target {
+            Target::Tile(i) => harvest(&chunk.tiles[i].ttype),
+            Target::Entity(_) => return,
+        };
+        if chunk.rng.gen_range(0..100) < chance {
+            chunk.entities[worker].inventory.add(Item::Bread, 1);
+        }
+    }
+}
+// turns a tile designed to become something else into it, paid for with
+// wood the entity carries or takes from its faction's store, the builder earns
+// a wage
+pub struct Build;
+impl Task for Build {
+    fn ready(&self, entity: &Entity, chunk: &Chunk, _config: &WorldConfig) -> bool {
+        wood(entity, chunk) > 0
+    }
+    fn target(&self, entity: &Entity, chunk: &Chunk, config: &WorldConfig) -> Option<Target> {
+        let wood = wood(entity, chunk);
+        nearest_tile(entity, chunk, config, |t| {
+            t.designed
+                .as_ref()
+                .is_some_and(|d| construction::materials(d) <= wood)
+        })
+        .map(Target::Tile)
+    }
+    fn work(&self) -> u16 {
+        BUILD_WORK
+    }
+    fn finish(&self, worker: usize, target: Target, chunk: &mut Chunk, config: &WorldConfig) {
+        let i = match target {
+            Target::Tile(i) => i,
+            Target::Entity(_) => return,
+        };
+        let ttype = match &chunk.tiles[i].designed {
+            Some(ttype) => ttype.clone(),
+            None => return,
+        };
+        let cost = construction::materials(&ttype);
+        if wood(&chunk.entities[worker], chunk) < cost {
+            return;
+        }
+        let entity = &mut chunk.entities[worker];
+        let carried = entity.inventory.count(&Item::Wood).min(cost);
+        entity.inventory.add(Item::Wood, -carried);
+        if carried < cost {
+            let faction = entity.alignment.faction.clone();
+            if let Some(store) = chunk.stores.get_mut(&faction) {
+                store.add(Item::Wood, carried - cost);
+            }
+        }
+        entity.inventory.add(Item::Coin, WAGE);
+        let tile = &mut chunk.tiles[i];
+        tile.designed = None;
+        tile.ttype = ttype.clone();
+        chunk.built.push(i);
+        if !ttype.is_passable() {
+            step_off(chunk, config, i);
+        }
+    }
+}
+// wood an entity can build with, its own and its faction's in the chunk
+fn wood(entity: &Entity, chunk: &Chunk) -> i32 {
+    entity.inventory.count(&Item::Wood)
+        + chunk
+            .stores
+            .get(&entity.alignment.faction)
+            .map_or(0, |s| s.count(&Item::Wood))
+}
+// moves everyone standing on a tile that was just walled up onto the closest
+// tile next to it that can still be walked on
+fn step_off(chunk: &mut Chunk, config: &WorldConfig, i: usize) {
+    let side = config.chunk_size as usize;
+    let free = math::neighbours((i % side, i / side), &NEIGHBOURS_8, side, side)
+        .map(|(x, y)| y * side + x)
+        .find(|n| chunk.tiles[*n].ttype.is_passable());
+    let free = match free {
+        Some(free) => &chunk.tiles[free].coords,
+        None => return,
+    };
+    let at = tile_to_world((free.x as i64, free.y as i64));
+    for entity in &mut chunk.entities {
+        if world_to_tile(&entity.coords) == world_to_tile(&chunk.tiles[i].coords) {
+            entity.coords = at;
+            entity.vel = (0.0, 0.0);
+        }
+    }
+}
+// cuts wood in forests for the faction's store for a wage
+pub struct Industry;
+impl Task for Industry {
+    fn ready(&self, entity: &Entity, chunk: &Chunk, _config: &WorldConfig) -> bool {
+        chunk
+            .stores
+            .get(&entity.alignment.faction)
+            .is_none_or(|s| s.count(&Item::Wood) < WOOD_LIMIT)
+    }
+    fn target(&self, entity: &Entity, chunk: &Chunk, config: &WorldConfig) -> Option<Target> {
+        nearest_tile(entity, chunk, config, |t| {
+            matches!(t.ttype, TileType::Taiga | TileType::TemperateForest)
+        })
+        .map(Target::Tile)
+    }
+    fn work(&self) -> u16 {
+        CUT_WORK
+    }
+    fn finish(&self, worker: usize, _target: Target, chunk: &mut Chunk, _config: &WorldConfig) {
+        chunk.entities[worker].inventory.add(Item::Coin, WAGE);
+        let faction = chunk.entities[worker].alignment.faction.clone();
+        chunk
+            .stores
+            .entry(faction)
+            .or_insert_with(Inventory::new)
+            .add(Item::Wood, 1);
+    }
+}
+// whether an entity picks a fight with another: anyone from a faction at war
+// with its own, and for the most aggressive also anyone from a faction it is
+// merely at peace with
+fn hostile(entity: &Entity, other: &Entity, chunk: &Chunk) -> bool {
+    let (a, b) = (&entity.alignment.faction, &other.alignment.faction);
+    if a == b {
+        return false;
+    }
+    match faction::relation(&chunk.relations, a, b) {
+        Relation::War => true,
+        Relation::Peace => entity.alignment.personality.aggression > RAIDER,
+        Relation::Alliance | Relation::TradePact => false,
+    }
+}
+// picks a fight with someone hostile nearby, as long as the entity is not
+// badly hurt or hungry
+pub struct Fight;
+impl Task for Fight {
+    fn ready(&self, entity: &Entity, _chunk: &Chunk, _config: &WorldConfig) -> bool {
+        entity.status != Status::Fighting
+            && entity.stats.health > entity.stats.max_health / 2
+            && !needs::is_hungry(entity)
+    }
+    fn target(&self, entity: &Entity, chunk: &Chunk, config: &WorldConfig) -> Option<Target> {
+        let range = 2.0 * config.vicinity_dist as f32;
+        chunk
+            .entities
+            .iter()
+            .filter(|e| hostile(entity, e, chunk))
+            .map(|e| (e.index, euclid(&entity.coords, &e.coords)))
+            .filter(|(_, d)| *d <= range)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(id, _)| Target::Entity(id))
+    }
+    fn work(&self) -> u16 {
+        FIGHT_WORK
+    }
+    fn reach(&self, config: &WorldConfig) -> f32 {
+        config.vicinity_dist as f32
+    }
+    fn finish(&self, worker: usize, _target: Target, chunk: &mut Chunk, _config: &WorldConfig) {
+        chunk.entities[worker].status = Status::Fighting;
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::Stats;
+    use crate::worldgen::Size;
+
+    // one chunk of 8 x 8 tiles of a kind
+    fn chunk(ttype: TileType) -> (Chunk, WorldConfig) {
+        let mut config = WorldConfig::new();
+        config.world_size = 1;
+        config.chunk_size = 8;
+        let tiles = (0..64)
+            .map(|i| {
+                let coords = Coords::from(((i % 8) as f32, (i / 8) as f32));
+                Tile::from(coords, i, Size::from((1, 1)), 0.0, ttype.clone(), None)
+            })
+            .collect();
+        (Chunk::from(tiles, vec![], Coords::new(), 0), config)
+    }
+    // a worker doing 3 work a step on the tile it stands on, willing to do
+    // one task only
+    fn worker(x: i64, y: i64, kind: TaskKind) -> Entity {
+        let mut entity = Entity::new(100);
+        entity.coords = tile_to_world((x, y));
+        entity.stats = Stats::from(10, 8, 10);
+        entity.tasks = Tasks {
+            build: (0, true),
+            fight: (0, true),
+            animal_husbandry: (0, true),
+            industry: (0, true),
+            farm: (0, true),
+            oil_rig: (0, true),
+        };
+        match kind {
+            TaskKind::Build => entity.tasks.build = (1, true),
+            TaskKind::Industry => entity.tasks.industry = (1, true),
+            TaskKind::Farm => entity.tasks.farm = (1, true),
+            _ => unreachable!(),
+        }
+        entity
+    }
+    // resolves the chunk until the worker is done, at most `steps` times
+    fn finish(chunk: &mut Chunk, config: &WorldConfig, steps: usize) -> usize {
+        for step in 1..=steps {
+            resolve(chunk, config);
+            if chunk.entities[0].job.is_none() {
+                return step;
+            }
+        }
+        panic!("not done in {} steps", steps);
+    }
+
+    #[test]
+    fn by_priority_skips_zero_and_unavailable() {
+        let tasks = Tasks {
+            build: (3, true),
+            fight: (5, false),
+            animal_husbandry: (4, true),
+            industry: (0, true),
+            farm: (2, true),
+            oil_rig: (9, true),
+        };
+        assert_eq!(
+            tasks.by_priority(),
+            vec![
+                TaskKind::OilRig,
+                TaskKind::AnimalHusbandry,
+                TaskKind::Build,
+                TaskKind::Farm
+            ]
+        );
+    }
+
+    #[test]
+    fn progress_adds_up_to_the_work() {
+        let (mut chunk, config) = chunk(TileType::Grass);
+        chunk.entities.push(worker(3, 3, TaskKind::Farm));
+        let work = work(&chunk.entities[0]);
+        assert_eq!(work, 3);
+        for step in 1..FARM_WORK.div_ceil(work) {
+            resolve(&mut chunk, &config);
+            let job = chunk.entities[0].job.as_ref().unwrap();
+            assert_eq!(job.kind, TaskKind::Farm);
+            assert_eq!(job.progress, step * work);
+        }
+        resolve(&mut chunk, &config);
+        assert!(chunk.entities[0].job.is_none());
+        // grass always grows a bread
+        assert_eq!(chunk.entities[0].inventory.count(&Item::Bread), 1);
+    }
+
+    #[test]
+    fn building_uses_wood_changes_the_tile_and_steps_off() {
+        let (mut chunk, config) = chunk(TileType::Grass);
+        let at = 3 * 8 + 3;
+        chunk.tiles[at].designed = Some(TileType::WoodenWall);
+        let mut entity = worker(3, 3, TaskKind::Build);
+        entity.inventory.add(Item::Wood, 1);
+        let faction = entity.alignment.faction.clone();
+        chunk.entities.push(entity);
+        let mut store = Inventory::new();
+        store.add(Item::Wood, 5);
+        chunk.stores.insert(faction.clone(), store);
+        assert_eq!(
+            finish(&mut chunk, &config, 20),
+            BUILD_WORK.div_ceil(3) as usize
+        );
+        assert_eq!(chunk.tiles[at].ttype, TileType::WoodenWall);
+        assert!(chunk.tiles[at].designed.is_none());
+        assert_eq!(chunk.built, vec![at]);
+        // the carried wood goes first, the rest comes from the store
+        let entity = &chunk.entities[0];
+        assert_eq!(entity.inventory.count(&Item::Wood), 0);
+        assert_eq!(chunk.stores[&faction].count(&Item::Wood), 4);
+        assert_eq!(entity.inventory.get_coins(), WAGE);
+        let tile = chunk.tile_at(&entity.coords, 8).unwrap();
+        assert_ne!(tile.index, at);
+        assert!(tile.ttype.is_passable());
+    }
+
+    #[test]
+    fn cutting_fills_the_store() {
+        let (mut chunk, config) = chunk(TileType::Taiga);
+        chunk.entities.push(worker(3, 3, TaskKind::Industry));
+        let faction = chunk.entities[0].alignment.faction.clone();
+        finish(&mut chunk, &config, 20);
+        assert_eq!(chunk.stores[&faction].count(&Item::Wood), 1);
+        assert_eq!(chunk.entities[0].inventory.get_coins(), WAGE);
+        // a full store is not cut for
+        chunk
+            .stores
+            .get_mut(&faction)
+            .unwrap()
+            .add(Item::Wood, WOOD_LIMIT);
+        assert!(pick(&chunk.entities[0], &chunk, &config).is_none());
+    }
+
+    #[test]
+    fn hungry_entities_forage_first() {
+        let (mut chunk, config) = chunk(TileType::Taiga);
+        let mut entity = worker(3, 3, TaskKind::Industry);
+        assert_eq!(
+            pick(&entity, &chunk, &config).unwrap().kind,
+            TaskKind::Industry
+        );
+        entity.stats.hunger = 10;
+        assert_eq!(pick(&entity, &chunk, &config).unwrap().kind, TaskKind::Farm);
+        // with bread to eat it keeps to its work
+        entity.inventory.add(Item::Bread, 1);
+        assert_eq!(
+            pick(&entity, &chunk, &config).unwrap().kind,
+            TaskKind::Industry
+        );
+        // a job is dropped for foraging
+        entity.inventory.add(Item::Bread, -1);
+        entity.job = Some(Job::from(TaskKind::Industry, Target::Tile(0)));
+        chunk.entities.push(entity);
+        resolve(&mut chunk, &config);
+        assert_eq!(chunk.entities[0].job.as_ref().unwrap().kind, TaskKind::Farm);
+    }
+
+    #[test]
+    fn walled_off_tiles_are_not_picked() {
+        let (mut chunk, config) = chunk(TileType::Grass);
+        chunk.tiles[6 * 8 + 6].ttype = TileType::Taiga;
+        for y in 0..8 {
+            chunk.tiles[y * 8 + 5].ttype = TileType::WoodenWall;
+        }
+        chunk.entities.push(worker(2, 6, TaskKind::Industry));
+        assert!(pick(&chunk.entities[0], &chunk, &config).is_none());
+        // a gap in the wall is walked through
+        chunk.tiles[5].ttype = TileType::Grass;
+        resolve(&mut chunk, &config);
+        let entity = &chunk.entities[0];
+        assert_eq!(entity.job.as_ref().unwrap().target, Target::Tile(6 * 8 + 6));
+        let mut walk = entity.path.clone();
+        walk.insert(0, entity.target.unwrap());
+        assert!(walk.contains(&tile_to_world((5, 0))));
+        assert_eq!(walk.last(), Some(&tile_to_world((6, 6))));
+    }
+}
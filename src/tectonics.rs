@@ -0,0 +1,251 @@
+use noise::{NoiseFn, Perlin};
+use rand::Rng;
+use std::collections::VecDeque;
+
+// tiles per side of one tectonic cell
+const CELL: usize = 16;
+const CONTINENT_HEIGHT: f32 = 4.0;
+const OCEAN_DEPTH: f32 = -8.0;
+const MOUNTAIN_HEIGHT: f32 = 14.0;
+const RIFT_DEPTH: f32 = -6.0;
+// cells over which a boundary fades into the plate interior
+const BOUNDARY_REACH: f32 = 6.0;
+
+#[derive(Clone)]
+pub struct Plate {
+    pub center: (f32, f32),
+    pub drift: (f32, f32),
+    pub continental: bool,
+}
+// coarse base elevation of the whole world, one value per tectonic cell
+#[derive(Clone)]
+pub struct TectonicMap {
+    pub width: usize,
+    pub height: usize,
+    pub plates: Vec<Plate>,
+    pub plate: Vec<usize>,
+    pub elevation: Vec<f32>,
+}
+impl TectonicMap {
+    // `side` is the world size in tiles
+    pub fn gen<R: Rng>(seed: u32, side: usize, plate_count: u32, rng: &mut R) -> TectonicMap {
+        let width = side.div_ceil(CELL).max(1);
+        let height = width;
+        let plates: Vec<Plate> = (0..plate_count.max(1))
+            .map(|_| {
+                let angle = rng.gen_range(0.0..std::f32::consts::TAU);
+                let speed = rng.gen_range(0.2..1.0);
+                Plate {
+                    center: (
+                        rng.gen_range(0.0..width as f32),
+                        rng.gen_range(0.0..height as f32),
+                    ),
+                    drift: (angle.cos() * speed, angle.sin() * speed),
+                    continental: rng.gen_bool(0.4),
+                }
+            })
+            .collect();
+        TectonicMap::from(seed, side, plates)
+    }
+    // the elevation that comes of the plates, whose centres are in cells.
+    // without plates the map is flat and no cell belongs to a plate
+    pub fn from(seed: u32, side: usize, plates: Vec<Plate>) -> TectonicMap {
+        let width = side.div_ceil(CELL).max(1);
+        let height = width;
+        if plates.is_empty() {
+            return TectonicMap {
+                width,
+                height,
+                plates,
+                plate: vec![],
+                elevation: vec![0.0; width * height],
+            };
+        }
+        // voronoi over warped coordinates so plate borders are not straight lines
+        let warp = Perlin::new(seed.wrapping_add(3000));
+        let mut plate = vec![0; width * height];
+        for y in 0..height {
+            for x in 0..width {
+                let wx = x as f32 + warp.get([x as f64 / 8.0, y as f64 / 8.0]) as f32 * 4.0;
+                let wy = y as f32 + warp.get([y as f64 / 8.0, x as f64 / 8.0]) as f32 * 4.0;
+                plate[y * width + x] = (0..plates.len())
+                    .min_by(|a, b| {
+                        let da = dist2(plates[*a].center, (wx, wy));
+                        let db = dist2(plates[*b].center, (wx, wy));
+                        da.total_cmp(&db)
+                    })
+                    .unwrap();
+            }
+        }
+        // stress on boundary cells: plates moving into each other push up
+        // mountains, plates moving apart open rifts
+        let mut stress = vec![0.0f32; width * height];
+        let mut distance = vec![u32::MAX; width * height];
+        let mut open = VecDeque::new();
+        for y in 0..height {
+            for x in 0..width {
+                let i = y * width + x;
+                let here = &plates[plate[i]];
+                for (dx, dy) in [(1i64, 0i64), (0, 1), (-1, 0), (0, -1)] {
+                    let (nx, ny) = (x as i64 + dx, y as i64 + dy);
+                    if nx < 0 || ny < 0 || nx >= width as i64 || ny >= height as i64 {
+                        continue;
+                    }
+                    let n = ny as usize * width + nx as usize;
+                    if plate[n] == plate[i] {
+                        continue;
+                    }
+                    let there = &plates[plate[n]];
+                    let relative = (here.drift.0 - there.drift.0, here.drift.1 - there.drift.1);
+                    stress[i] += relative.0 * dx as f32 + relative.1 * dy as f32;
+                    distance[i] = 0;
+                }
+                if distance[i] == 0 {
+                    open.push_back(i);
+                }
+            }
+        }
+        // spread the stress inwards from the boundaries
+        while let Some(i) = open.pop_front() {
+            let (x, y) = ((i % width) as i64, (i / width) as i64);
+            for (nx, ny) in [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)] {
+                if nx < 0 || ny < 0 || nx >= width as i64 || ny >= height as i64 {
+                    continue;
+                }
+                let n = ny as usize * width + nx as usize;
+                if distance[n] == u32::MAX && plate[n] == plate[i] {
+                    distance[n] = distance[i] + 1;
+                    stress[n] = stress[i];
+                    open.push_back(n);
+                }
+            }
+        }
+        let elevation = (0..width * height)
+            .map(|i| {
+                let base = if plates[plate[i]].continental {
+                    CONTINENT_HEIGHT
+                } else {
+                    OCEAN_DEPTH
+                };
+                let falloff = if distance[i] == u32::MAX {
+                    0.0
+                } else {
+                    (-(distance[i] as f32) / BOUNDARY_REACH).exp()
+                };
+                let boundary = if stress[i] > 0.0 {
+                    stress[i].min(1.0) * MOUNTAIN_HEIGHT
+                } else {
+                    -stress[i].max(-1.0) * RIFT_DEPTH
+                };
+                base + boundary * falloff
+            })
+            .collect();
+        TectonicMap {
+            width,
+            height,
+            plates,
+            plate,
+            elevation,
+        }
+    }
+    // bilinear sample at tile coordinates
+    pub fn sample(&self, x: f64, y: f64) -> f32 {
+        let fx = (x as f32 / CELL as f32 - 0.5).clamp(0.0, (self.width - 1) as f32);
+        let fy = (y as f32 / CELL as f32 - 0.5).clamp(0.0, (self.height - 1) as f32);
+        let (cx, cy) = (fx as usize, fy as usize);
+        let (nx, ny) = ((cx + 1).min(self.width - 1), (cy + 1).min(self.height - 1));
+        let (u, v) = (fx - cx as f32, fy - cy as f32);
+        let at = |x: usize, y: usize| self.elevation[y * self.width + x];
+        at(cx, cy) * (1.0 - u) * (1.0 - v)
+            + at(nx, cy) * u * (1.0 - v)
+            + at(cx, ny) * (1.0 - u) * v
+            + at(nx, ny) * u * v
+    }
+}
+fn dist2(a: (f32, f32), b: (f32, f32)) -> f32 {
+    (a.0 - b.0) * (a.0 - b.0) + (a.1 - b.1) * (a.1 - b.1)
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    // two continents side by side, 8 x 8 cells, drifting `drift` towards
+    // each other
+    fn pair(drift: f32) -> TectonicMap {
+        let plate = |x: f32, dx: f32| Plate {
+            center: (x, 4.0),
+            drift: (dx, 0.0),
+            continental: true,
+        };
+        TectonicMap::from(1, 8 * CELL, vec![plate(1.0, drift), plate(7.0, -drift)])
+    }
+    // cells with the other plate on the side facing it and not behind, plate 0
+    // is the western one. the warped border also has stretches where it is
+    // the other way round
+    fn facing(map: &TectonicMap) -> Vec<usize> {
+        (0..map.elevation.len())
+            .filter(|i| {
+                let x = i % map.width;
+                let other = |n: usize| map.plate[n] != map.plate[*i];
+                let east = x + 1 < map.width && other(i + 1);
+                let west = x > 0 && other(i - 1);
+                if map.plate[*i] == 0 {
+                    east && !west
+                } else {
+                    west && !east
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn converging_plates_raise_mountains() {
+        let map = pair(1.0);
+        assert!(facing(&map).len() >= map.height);
+        for i in facing(&map) {
+            assert_eq!(map.elevation[i], CONTINENT_HEIGHT + MOUNTAIN_HEIGHT);
+        }
+    }
+
+    #[test]
+    fn diverging_plates_open_rifts() {
+        let map = pair(-1.0);
+        assert!(facing(&map).len() >= map.height);
+        for i in facing(&map) {
+            assert_eq!(map.elevation[i], CONTINENT_HEIGHT + RIFT_DEPTH);
+        }
+        // plates drifting the same way leave each other alone
+        assert!(pair(0.0).elevation.iter().all(|e| *e == CONTINENT_HEIGHT));
+    }
+
+    #[test]
+    fn sampling_stays_in_bounds() {
+        for (side, plates) in [(1, 1), (17, 3), (100, 6), (256, 12)] {
+            let map = TectonicMap::gen(4, side, plates, &mut StdRng::seed_from_u64(4));
+            assert_eq!(map.width, side.div_ceil(CELL));
+            assert_eq!(map.elevation.len(), map.width * map.height);
+            assert!(map.plate.iter().all(|p| *p < map.plates.len()));
+            let low = map.elevation.iter().cloned().fold(f32::MAX, f32::min);
+            let high = map.elevation.iter().cloned().fold(f32::MIN, f32::max);
+            let far = (side * 2) as f64;
+            for (x, y) in [(-far, -far), (0.0, 0.0), (far, 0.0), (0.0, far), (far, far)] {
+                let h = map.sample(x, y);
+                assert!(h.is_finite() && h >= low - 1e-4 && h <= high + 1e-4);
+            }
+            for i in 0..side * side {
+                let h = map.sample((i % side) as f64, (i / side) as f64);
+                assert!(h >= low - 1e-4 && h <= high + 1e-4);
+            }
+        }
+    }
+    #[test]
+    fn no_plates_make_a_flat_map() {
+        let map = TectonicMap::from(5, 100, vec![]);
+        assert_eq!((map.width, map.height), (7, 7));
+        assert!(map.plate.is_empty());
+        assert!(map.elevation.iter().all(|e| *e == 0.0));
+        assert_eq!(map.sample(50.0, 99.0), 0.0);
+    }
+}
@@ -0,0 +1,563 @@
+use crate::climate;
+use crate::config::{WorldConfig, TERRAIN_BYTES};
+use crate::erosion;
+use crate::height::{HeightGenerator, HeightMap};
+use crate::hydrology::{self, LAKE_DEPTH};
+use crate::tectonics::TectonicMap;
+use crate::worldgen::{chunk_seed, pass_seed, TileType};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{PoisonError, RwLock};
+
+// tiles a side of one block of the terrain, a world no wider is a single
+// block
+const BLOCK: usize = 2048;
+// blocks kept in memory whatever the budget, enough for a search around the
+// corner where four blocks meet
+const MIN_BLOCKS: usize = 4;
+// bytes a cell of the whole world pass keeps: height, fill level, receiver,
+// moisture and water
+const GLOBAL_BYTES: usize = 4 * size_of::<f32>() + size_of::<Option<TileType>>();
+
+// the per tile layers of one block, row-major like a HeightMap
+#[derive(Clone)]
+pub struct Layers {
+    pub heights: HeightMap,
+    pub ttypes: Vec<TileType>,
+    pub temperature: Vec<f32>,
+    pub moisture: Vec<f32>,
+}
+impl Layers {
+    // flat grass at height zero
+    pub fn new(side: usize) -> Layers {
+        Layers {
+            heights: HeightMap::new(side, side),
+            ttypes: vec![TileType::Grass; side * side],
+            temperature: vec![0.0; side * side],
+            moisture: vec![0.0; side * side],
+        }
+    }
+}
+// what a block is generated from
+struct Source {
+    seed: u32,
+    config: WorldConfig,
+    generator: HeightGenerator,
+    global: Global,
+}
+// the whole world eroded, flooded and blown over in one pass, one cell every
+// `step` tiles each way. it is as fine as a quarter of the budget allows, at a
+// step of 1 it holds the final heights, otherwise how much erosion raised or
+// lowered the land
+struct Global {
+    step: usize,
+    cells: usize,
+    heights: Vec<f32>,
+    filled: Vec<f32>,
+    receiver: Vec<u32>,
+    moisture: Vec<f32>,
+    water: Vec<Option<TileType>>,
+}
+struct Cached {
+    layers: Layers,
+    last_used: AtomicU64,
+}
+// heights, tile types and climate of the world, split into square blocks that
+// are cut from the whole world pass when first read. at most `capacity` blocks
+// stay in memory, the least recently used one is dropped for the next and cut
+// again when it is read again. tiles changed since generation are kept aside
+// and put back into a block cut again
+pub struct Terrain {
+    side: usize,
+    block: usize,
+    capacity: usize,
+    source: Option<Source>,
+    cache: RwLock<HashMap<usize, Cached>>,
+    clock: AtomicU64,
+    changed: HashMap<usize, TileType>,
+}
+impl Terrain {
+    pub fn gen(seed: u32, config: &WorldConfig) -> Terrain {
+        Terrain::tiled(seed, config, BLOCK)
+    }
+    fn tiled(seed: u32, config: &WorldConfig, block: usize) -> Terrain {
+        let side = (config.world_size * config.chunk_size) as usize;
+        let mut generator = HeightGenerator::from(seed, config.noise_scale, &config.octaves);
+        if config.plates > 0 {
+            let mut rng = StdRng::seed_from_u64(pass_seed(seed, 1));
+            generator = generator.with_base(TectonicMap::gen(seed, side, config.plates, &mut rng));
+        }
+        let block = block.clamp(1, side.max(1));
+        // the terrain gets half of the memory budget, the chunks the other
+        // half. the whole world pass and the blocks get a quarter each
+        let budget = config.memory_budget * 1024 * 1024 / 4;
+        let mut source = Source {
+            seed,
+            config: config.clone(),
+            generator,
+            global: Global::empty(),
+        };
+        source.global = Global::gen(&source, side, budget);
+        Terrain {
+            side,
+            block,
+            capacity: (budget / (block * block * TERRAIN_BYTES)).max(MIN_BLOCKS),
+            source: Some(source),
+            cache: RwLock::new(HashMap::new()),
+            clock: AtomicU64::new(0),
+            changed: HashMap::new(),
+        }
+    }
+    // a single block made by hand, it is never dropped
+    pub fn from(layers: Layers) -> Terrain {
+        let side = layers.heights.width;
+        let cached = Cached {
+            layers,
+            last_used: AtomicU64::new(0),
+        };
+        Terrain {
+            side,
+            block: side.max(1),
+            capacity: 1,
+            source: None,
+            cache: RwLock::new(HashMap::from([(0, cached)])),
+            clock: AtomicU64::new(0),
+            changed: HashMap::new(),
+        }
+    }
+    pub fn side(&self) -> usize {
+        self.side
+    }
+    // a tile's place in the whole world, row-major
+    pub fn index(&self, x: usize, y: usize) -> usize {
+        y * self.side + x
+    }
+    pub fn blocks(&self) -> usize {
+        let per = self.side.div_ceil(self.block);
+        per * per
+    }
+    pub fn block_of(&self, x: usize, y: usize) -> usize {
+        y / self.block * self.side.div_ceil(self.block) + x / self.block
+    }
+    // blocks in memory right now
+    pub fn loaded(&self) -> usize {
+        self.cache
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len()
+    }
+    pub fn height(&self, x: usize, y: usize) -> f32 {
+        self.read(x, y, |layers, i| layers.heights.data[i])
+    }
+    pub fn ttype(&self, x: usize, y: usize) -> TileType {
+        self.read(x, y, |layers, i| layers.ttypes[i].clone())
+    }
+    pub fn temperature(&self, x: usize, y: usize) -> f32 {
+        self.read(x, y, |layers, i| layers.temperature[i])
+    }
+    pub fn moisture(&self, x: usize, y: usize) -> f32 {
+        self.read(x, y, |layers, i| layers.moisture[i])
+    }
+    // a block that is not in memory takes the change when it is generated
+    pub fn set_ttype(&mut self, x: usize, y: usize, ttype: TileType) {
+        let (b, (x0, y0)) = (self.block_of(x, y), self.origin(self.block_of(x, y)));
+        self.changed.insert(self.index(x, y), ttype.clone());
+        let cache = self.cache.get_mut().unwrap_or_else(PoisonError::into_inner);
+        if let Some(cached) = cache.get_mut(&b) {
+            let i = cached.layers.heights.index(x - x0, y - y0);
+            cached.layers.ttypes[i] = ttype;
+        }
+    }
+    // looks at a whole block, cutting it when it is not in memory
+    pub fn with_block<T>(&self, b: usize, f: impl FnOnce(&Layers) -> T) -> T {
+        {
+            let cache = self.cache.read().unwrap_or_else(PoisonError::into_inner);
+            if let Some(cached) = cache.get(&b) {
+                // only written when it changes so reading threads do not
+                // fight over the cache line
+                let now = self.clock.load(Ordering::Relaxed);
+                if cached.last_used.load(Ordering::Relaxed) != now {
+                    cached.last_used.store(now, Ordering::Relaxed);
+                }
+                return f(&cached.layers);
+            }
+        }
+        // cut without holding the lock, cutting runs on the thread pool and
+        // may pick up other work that reads the terrain
+        let layers = self.generate(b);
+        let value = f(&layers);
+        let mut cache = self.cache.write().unwrap_or_else(PoisonError::into_inner);
+        if !cache.contains_key(&b) {
+            while cache.len() >= self.capacity {
+                let coldest = cache
+                    .iter()
+                    .min_by_key(|(_, c)| c.last_used.load(Ordering::Relaxed))
+                    .map(|(b, _)| *b);
+                match coldest {
+                    Some(coldest) => cache.remove(&coldest),
+                    None => break,
+                };
+            }
+            let now = self.clock.fetch_add(1, Ordering::Relaxed) + 1;
+            cache.insert(
+                b,
+                Cached {
+                    layers,
+                    last_used: AtomicU64::new(now),
+                },
+            );
+        }
+        value
+    }
+    fn read<T>(&self, x: usize, y: usize, f: impl FnOnce(&Layers, usize) -> T) -> T {
+        let b = self.block_of(x, y);
+        let (x0, y0) = self.origin(b);
+        self.with_block(b, |layers| f(layers, layers.heights.index(x - x0, y - y0)))
+    }
+    // top left tile of a block
+    fn origin(&self, b: usize) -> (usize, usize) {
+        let per = self.side.div_ceil(self.block);
+        (b % per * self.block, b / per * self.block)
+    }
+    // heights, water and climate of a block. they only depend on the tile
+    // and the whole world pass, so blocks meet without a seam
+    fn generate(&self, b: usize) -> Layers {
+        let source = self
+            .source
+            .as_ref()
+            .expect("a terrain made by hand holds all of its blocks");
+        let (config, global) = (&source.config, &source.global);
+        let (x0, y0) = self.origin(b);
+        let (w, h) = (
+            (x0 + self.block).min(self.side) - x0,
+            (y0 + self.block).min(self.side) - y0,
+        );
+        let mut heights = if global.step == 1 {
+            let mut map = HeightMap::new(w, h);
+            for y in 0..h {
+                let row = (y0 + y) * self.side + x0;
+                map.data[y * w..(y + 1) * w].copy_from_slice(&global.heights[row..row + w]);
+            }
+            map
+        } else {
+            source.heights(x0, y0, w, h)
+        };
+        if global.step > 1 {
+            heights
+                .data
+                .par_iter_mut()
+                .enumerate()
+                .for_each(|(i, height)| {
+                    *height += global.sample(&global.heights, x0 + i % w, y0 + i / w);
+                });
+        }
+        let temperature = climate::temperature(&heights, y0, self.side, config.sea_level);
+        let (moisture, mut ttypes): (Vec<f32>, Vec<TileType>) = (0..w * h)
+            .into_par_iter()
+            .map(|i| {
+                let (x, y) = (x0 + i % w, y0 + i / w);
+                let height = heights.data[i];
+                let moisture = global.sample(&global.moisture, x, y);
+                let ttype = if height < config.sea_level {
+                    TileType::Ocean
+                } else if global.sample(&global.filled, x, y) - height > LAKE_DEPTH {
+                    TileType::Lake
+                } else {
+                    climate::classify(temperature[i], moisture)
+                };
+                (moisture, ttype)
+            })
+            .unzip();
+        // rivers run from every river cell to the cell it drains into, a cell
+        // past the block can still run into it
+        let step = global.step;
+        let first = ((x0 / step).saturating_sub(1), (y0 / step).saturating_sub(1));
+        let last = (
+            ((x0 + w - 1) / step + 1).min(global.cells - 1),
+            ((y0 + h - 1) / step + 1).min(global.cells - 1),
+        );
+        for cy in first.1..=last.1 {
+            for cx in first.0..=last.0 {
+                let c = cy * global.cells + cx;
+                if global.water[c] != Some(TileType::River) {
+                    continue;
+                }
+                let r = global.receiver[c] as usize;
+                let from = ((cx * step) as i64, (cy * step) as i64);
+                let to = (
+                    (r % global.cells * step) as i64,
+                    (r / global.cells * step) as i64,
+                );
+                let n = (to.0 - from.0).abs().max((to.1 - from.1).abs()).max(1);
+                for k in 0..=n {
+                    let x = from.0 + (to.0 - from.0) * k / n;
+                    let y = from.1 + (to.1 - from.1) * k / n;
+                    let (x, y) = (x as usize, y as usize);
+                    if !(x0..x0 + w).contains(&x) || !(y0..y0 + h).contains(&y) {
+                        continue;
+                    }
+                    let i = (y - y0) * w + x - x0;
+                    if !matches!(ttypes[i], TileType::Ocean | TileType::Lake) {
+                        ttypes[i] = TileType::River;
+                    }
+                }
+            }
+        }
+        for (i, ttype) in &self.changed {
+            let (x, y) = (i % self.side, i / self.side);
+            if (x0..x0 + w).contains(&x) && (y0..y0 + h).contains(&y) {
+                ttypes[(y - y0) * w + x - x0] = ttype.clone();
+            }
+        }
+        Layers {
+            heights,
+            ttypes,
+            temperature,
+            moisture,
+        }
+    }
+}
+impl Global {
+    fn empty() -> Global {
+        Global {
+            step: 1,
+            cells: 0,
+            heights: vec![],
+            filled: vec![],
+            receiver: vec![],
+            moisture: vec![],
+            water: vec![],
+        }
+    }
+    // erosion, water and wind over the whole world at the finest step that
+    // fits the budget. at a step of 1 this is the world generated whole
+    fn gen(source: &Source, side: usize, budget: usize) -> Global {
+        let config = &source.config;
+        let step = (1..=side.max(1))
+            .find(|step| side.div_ceil(*step).pow(2) * GLOBAL_BYTES <= budget)
+            .unwrap_or(side.max(1));
+        let cells = side.div_ceil(step);
+        let mut map = if step == 1 {
+            source.heights(0, 0, side, side)
+        } else {
+            let mut map = HeightMap::new(cells, cells);
+            map.data.par_iter_mut().enumerate().for_each(|(i, height)| {
+                let (x, y) = ((i % cells * step) as f64, (i / cells * step) as f64);
+                *height = source.generator.get(x, y);
+            });
+            map
+        };
+        let plain = (step > 1).then(|| map.data.clone());
+        // droplets in proportion to the cells, the whole count at a step of 1
+        let droplets =
+            config.erosion_droplets as u64 * (cells * cells) as u64 / (side * side).max(1) as u64;
+        let mut rng = StdRng::seed_from_u64(pass_seed(source.seed, 0));
+        erosion::hydraulic(&mut map, droplets as u32, &mut rng);
+        erosion::thermal(
+            &mut map,
+            config.thermal_iterations,
+            config.talus * step as f32,
+        );
+        // a cell drains the tiles of step * step cells
+        let threshold = config.river_threshold.div_ceil((step * step) as u32).max(1);
+        let hydrology = hydrology::compute(&map, config.sea_level, threshold);
+        let moisture = climate::moisture(&map, &hydrology.water, step as f32);
+        let heights = match plain {
+            Some(plain) => map.data.iter().zip(plain).map(|(h, p)| h - p).collect(),
+            None => map.data,
+        };
+        Global {
+            step,
+            cells,
+            heights,
+            filled: hydrology.filled.data,
+            receiver: hydrology.receiver.iter().map(|r| *r as u32).collect(),
+            moisture,
+            water: hydrology.water,
+        }
+    }
+    // a layer at a tile, bilinear between the cells around it
+    fn sample(&self, layer: &[f32], x: usize, y: usize) -> f32 {
+        if self.step == 1 {
+            return layer[y * self.cells + x];
+        }
+        let (cx, cy) = (x / self.step, y / self.step);
+        let (nx, ny) = ((cx + 1).min(self.cells - 1), (cy + 1).min(self.cells - 1));
+        let u = (x % self.step) as f32 / self.step as f32;
+        let v = (y % self.step) as f32 / self.step as f32;
+        let at = |x: usize, y: usize| layer[y * self.cells + x];
+        at(cx, cy) * (1.0 - u) * (1.0 - v)
+            + at(nx, cy) * u * (1.0 - v)
+            + at(cx, ny) * (1.0 - u) * v
+            + at(nx, ny) * u * v
+    }
+}
+impl Source {
+    // noise heights of a rectangle of the world. jitter is drawn per chunk so
+    // the heights do not depend on scheduling or on the rectangle
+    fn heights(&self, wx: usize, wy: usize, width: usize, height: usize) -> HeightMap {
+        let chunk_size = self.config.chunk_size as usize;
+        let first = (wx / chunk_size, wy / chunk_size);
+        let last = (
+            (wx + width - 1) / chunk_size,
+            (wy + height - 1) / chunk_size,
+        );
+        let across = last.0 - first.0 + 1;
+        let heights: Vec<(usize, usize, f32)> = (0..across * (last.1 - first.1 + 1))
+            .into_par_iter()
+            .flat_map_iter(|c| {
+                let (cx, cy) = (first.0 + c % across, first.1 + c / across);
+                let mut rng = StdRng::seed_from_u64(chunk_seed(self.seed, cx as i32, cy as i32));
+                (0..chunk_size * chunk_size)
+                    .filter_map(|t| {
+                        let x = t % chunk_size + cx * chunk_size;
+                        let y = t / chunk_size + cy * chunk_size;
+                        let inside =
+                            (wx..wx + width).contains(&x) && (wy..wy + height).contains(&y);
+                        // every tile draws, so the ones inside get the same
+                        // jitter whatever part of the chunk is inside
+                        let noise = inside.then(|| self.generator.get(x as f64, y as f64));
+                        let jitter = rng.gen_range(-1.0..1.0) * self.config.jitter;
+                        noise.map(|noise| (x - wx, y - wy, noise + jitter))
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        let mut map = HeightMap::new(width, height);
+        for (x, y, h) in heights {
+            map.set(x, y, h);
+        }
+        map
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 4 x 4 chunks of 16 tiles
+    fn config() -> WorldConfig {
+        let mut config = WorldConfig::new();
+        config.world_size = 4;
+        config.chunk_size = 16;
+        config.erosion_droplets = 200;
+        config
+    }
+    fn heights(terrain: &Terrain, b: usize) -> Vec<u32> {
+        terrain.with_block(b, |l| l.heights.data.iter().map(|h| h.to_bits()).collect())
+    }
+
+    #[test]
+    fn blocks_are_generated_when_read() {
+        let terrain = Terrain::tiled(3, &config(), 16);
+        assert_eq!(terrain.blocks(), 16);
+        assert_eq!(terrain.loaded(), 0);
+        terrain.height(20, 40);
+        assert_eq!(terrain.loaded(), 1);
+        assert_eq!(terrain.block_of(20, 40), 9);
+        // a world no wider than a block is one block
+        let whole = Terrain::gen(3, &config());
+        assert_eq!(whole.blocks(), 1);
+    }
+    #[test]
+    fn dropped_blocks_come_back_the_same() {
+        let mut terrain = Terrain::tiled(3, &config(), 16);
+        terrain.capacity = 4;
+        let first = heights(&terrain, 0);
+        for b in 0..terrain.blocks() {
+            heights(&terrain, b);
+            assert!(terrain.loaded() <= 4);
+        }
+        assert_eq!(heights(&terrain, 0), first);
+    }
+    #[test]
+    fn changed_tiles_survive_being_dropped() {
+        let mut terrain = Terrain::tiled(3, &config(), 16);
+        terrain.capacity = 4;
+        terrain.height(1, 1);
+        terrain.set_ttype(1, 1, TileType::WoodenWall);
+        // a block not in memory takes the change once it is generated
+        terrain.set_ttype(60, 60, TileType::Road);
+        assert_eq!(terrain.ttype(1, 1), TileType::WoodenWall);
+        for b in 1..terrain.blocks() {
+            heights(&terrain, b);
+        }
+        assert_eq!(terrain.ttype(1, 1), TileType::WoodenWall);
+        assert_eq!(terrain.ttype(60, 60), TileType::Road);
+    }
+    #[test]
+    fn heights_do_not_depend_on_the_rectangle() {
+        let terrain = Terrain::tiled(3, &config(), 16);
+        let source = terrain.source.as_ref().unwrap();
+        let whole = source.heights(0, 0, 64, 64);
+        let part = source.heights(10, 20, 30, 25);
+        for y in 0..25 {
+            for x in 0..30 {
+                assert_eq!(part.get(x, y), whole.get(x + 10, y + 20));
+            }
+        }
+    }
+    #[test]
+    fn blocks_kept_follow_the_budget() {
+        let mut config = config();
+        config.memory_budget = 1;
+        // a quarter of a megabyte holds 78 blocks of 16 * 16 tiles
+        assert_eq!(Terrain::tiled(3, &config, 16).capacity, 78);
+        // but never less than four, one of 256 * 256 tiles is already more
+        config.world_size = 16;
+        assert_eq!(Terrain::tiled(3, &config, 256).capacity, MIN_BLOCKS);
+    }
+    // 8 x 8 chunks of 64 tiles, with rivers
+    fn wide() -> WorldConfig {
+        let mut config = WorldConfig::new();
+        config.world_size = 8;
+        config.chunk_size = 64;
+        config.erosion_droplets = 2000;
+        config.river_threshold = 50;
+        config
+    }
+    // every layer of every tile is the same cut in blocks of 256 as in one
+    // block of the whole world
+    fn same_across_blocks(config: &WorldConfig) -> Terrain {
+        let tiled = Terrain::tiled(3, config, 256);
+        let whole = Terrain::tiled(3, config, 512);
+        assert_eq!((tiled.blocks(), whole.blocks()), (4, 1));
+        for y in 0..512 {
+            for x in 0..512 {
+                assert_eq!(tiled.height(x, y).to_bits(), whole.height(x, y).to_bits());
+                assert_eq!(tiled.ttype(x, y), whole.ttype(x, y));
+                assert_eq!(tiled.temperature(x, y), whole.temperature(x, y));
+                assert_eq!(tiled.moisture(x, y), whole.moisture(x, y));
+            }
+        }
+        tiled
+    }
+    fn rivers(terrain: &Terrain) -> usize {
+        (0..terrain.blocks())
+            .map(|b| {
+                terrain.with_block(b, |l| {
+                    l.ttypes.iter().filter(|t| **t == TileType::River).count()
+                })
+            })
+            .sum()
+    }
+    #[test]
+    fn block_borders_do_not_show() {
+        let tiled = same_across_blocks(&wide());
+        assert_eq!(tiled.source.as_ref().unwrap().global.step, 1);
+        assert!(rivers(&tiled) > 0);
+    }
+    #[test]
+    fn block_borders_do_not_show_on_a_coarse_pass() {
+        let mut config = wide();
+        config.memory_budget = 1;
+        let tiled = same_across_blocks(&config);
+        // a quarter of a megabyte holds 103 * 103 cells of 5 * 5 tiles
+        let global = &tiled.source.as_ref().unwrap().global;
+        assert_eq!((global.step, global.cells), (5, 103));
+        assert!(rivers(&tiled) > 0);
+    }
+}
@@ -1,16 +1,19 @@
-use crate::renderer::Camera;
-use crate::worldgen::{Chunk, News};
+use crate::camera::Camera;
+use crate::worldgen::{Chunk, Faction, News};
 
 #[derive(Clone)]
 pub struct RenderMsg {
     pub chunk: Chunk,
     pub news: News,
+    // the faction holding the chunk
+    pub owner: Faction,
 }
 impl RenderMsg {
-    pub fn from(chunk: Chunk, news: News) -> RenderMsg {
+    pub fn from(chunk: Chunk, news: News, owner: Faction) -> RenderMsg {
         RenderMsg {
             chunk: chunk,
             news: news,
+            owner,
         }
     }
 }
@@ -1,16 +1,26 @@
-use crate::math::dist;
+use crate::config::WorldConfig;
+use crate::construction::Blueprints;
+use crate::faction::{self, FactionState, Relation};
+use crate::math::{
+    chunk_index, euclid, tile_in_chunk, tile_to_chunk, world_to_chunk, world_to_tile,
+};
+use crate::movement;
+use crate::needs;
+use crate::path::PathCache;
+use crate::roads::{self, Roads};
+use crate::settlement::{self, Settlement};
+use crate::spatial::SpatialHash;
+use crate::stats::{self, Stats};
+use crate::store::ChunkStore;
+use crate::task::{self, Job};
+use crate::terrain::Terrain;
 use lazy_static::lazy_static;
 use noise::{NoiseFn, Perlin};
 use rand::prelude::SliceRandom;
-use rand::Rng;
-use rayon::prelude::*;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha12Rng;
 use std::collections::HashMap;
 lazy_static! {
-    pub static ref WORLD_SIZE: u32 = 16;
-    pub static ref CHUNK_SIZE: u32 = 128;
-    pub static ref TILE_SIZE: u32 = 1;
-    pub static ref NOISE_SCALE: f64 = 64.0;
-    pub static ref VICINITY_DIST: i32 = 4;
     pub static ref HUMAN_NAMES_F: Vec<String> = vec![
         "Kirsika".to_string(),
         "Markus".to_string(),
@@ -28,12 +38,12 @@ lazy_static! {
 }
 #[derive(Clone)]
 pub struct Tasks {
-    build: (u8, bool),
-    fight: (u8, bool),
-    animal_husbandry: (u8, bool),
-    industry: (u8, bool),
-    farm: (u8, bool),
-    oil_rig: (u8, bool),
+    pub(crate) build: (u8, bool),
+    pub(crate) fight: (u8, bool),
+    pub(crate) animal_husbandry: (u8, bool),
+    pub(crate) industry: (u8, bool),
+    pub(crate) farm: (u8, bool),
+    pub(crate) oil_rig: (u8, bool),
 }
 impl Tasks {
     pub fn new() -> Tasks {
@@ -53,30 +63,54 @@ pub enum Gender {
     Female,
     Other,
 }
-pub fn gen_human_name(faction: Faction, gender: &Gender) -> String {
+pub fn gen_human_name<R: Rng>(faction: Faction, gender: &Gender, rng: &mut R) -> String {
     match gender {
-        Gender::Male => HUMAN_NAMES_M
-            .choose(&mut rand::thread_rng())
-            .unwrap()
-            .to_string(),
-        Gender::Female => HUMAN_NAMES_M
-            .choose(&mut rand::thread_rng())
-            .unwrap()
-            .to_string(),
-        Gender::Other => HUMAN_NAMES_M
-            .choose(&mut rand::thread_rng())
-            .unwrap()
-            .to_string(),
+        Gender::Male => HUMAN_NAMES_M.choose(rng).unwrap().to_string(),
+        Gender::Female => HUMAN_NAMES_M.choose(rng).unwrap().to_string(),
+        Gender::Other => HUMAN_NAMES_M.choose(rng).unwrap().to_string(),
+    }
+}
+// mixes the world seed with chunk coordinates so every chunk has its own
+// reproducible random stream regardless of generation order
+pub fn chunk_seed(seed: u32, x: i32, y: i32) -> u64 {
+    let mut z = (seed as u64)
+        ^ ((x as u32 as u64) << 32 | y as u32 as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+// seed for world wide passes, negative coordinates never belong to a chunk
+pub fn pass_seed(seed: u32, pass: i32) -> u64 {
+    chunk_seed(seed, -1 - pass, -1)
+}
+// the faction living in a chunk, None for chunks nobody lives in
+pub fn chunk_faction(seed: u32, x: i32, y: i32) -> Option<Faction> {
+    let value = Perlin::new(seed).get([x as f64 + 0.1, y as f64 + 0.1]);
+    if value > 0.0 && value < 0.1 {
+        Some(Faction::Novgorod)
+    } else if value > 0.1 && value < 0.2 {
+        Some(Faction::Virumaa)
+    } else if value > 0.2 && value < 0.3 {
+        Some(Faction::Kalevala)
+    } else if value > 0.3 && value < 0.4 {
+        Some(Faction::Tapiola)
+    } else if value > 0.4 && value < 0.5 {
+        Some(Faction::Pohjola)
+    } else {
+        None
     }
 }
 #[derive(Hash, Eq, PartialEq, Clone)]
 pub enum Item {
     Bread,
     Coin,
+    Sword,
+    Armour,
+    Wood,
 }
 #[derive(Clone)]
 pub struct Inventory {
-    items: HashMap<Item, i32>,
+    pub(crate) items: HashMap<Item, i32>,
 }
 impl Inventory {
     pub fn new() -> Inventory {
@@ -88,14 +122,19 @@ impl Inventory {
         let count = self.items.get(&Item::Coin).unwrap_or(&0);
         *count
     }
-}
-#[derive(Clone)]
-pub struct Stats {
-    health: i8,
-    hunger: u8,
-    strength: u8,
-    intelligence: u8,
-    agility: u8,
+    pub fn count(&self, item: &Item) -> i32 {
+        *self.items.get(item).unwrap_or(&0)
+    }
+    pub fn add(&mut self, item: Item, count: i32) {
+        *self.items.entry(item).or_insert(0) += count;
+    }
+    // items there is at least one of
+    pub fn carried(&self) -> impl Iterator<Item = &Item> {
+        self.items
+            .iter()
+            .filter(|(_, count)| **count > 0)
+            .map(|(item, _)| item)
+    }
 }
 #[derive(Clone, Eq, Hash, PartialEq, Debug)]
 pub enum Faction {
@@ -109,14 +148,13 @@ pub enum Faction {
 }
 #[derive(Clone)]
 pub struct Personality {
-    aggression: u8,
+    pub(crate) aggression: u8,
 }
 impl Personality {
     pub fn new() -> Personality {
         Personality { aggression: 0 }
     }
-    pub fn gen() -> Personality {
-        let mut rng = rand::thread_rng();
+    pub fn gen<R: Rng>(rng: &mut R) -> Personality {
         Personality {
             aggression: rng.gen_range(0..100),
         }
@@ -128,56 +166,68 @@ pub struct Alignment {
     pub personality: Personality,
 }
 impl Alignment {
-    pub fn new() -> Alignment {
+    pub fn new<R: Rng>(rng: &mut R) -> Alignment {
         Alignment {
             faction: Faction::Empty,
-            personality: Personality::gen(),
+            personality: Personality::gen(rng),
         }
     }
-    pub fn from(faction: Faction) -> Alignment {
+    pub fn from<R: Rng>(faction: Faction, rng: &mut R) -> Alignment {
         Alignment {
             faction: faction,
-            personality: Personality::gen(),
-        }
-    }
-}
-impl Stats {
-    pub fn new() -> Stats {
-        Stats {
-            health: 100,
-            hunger: 100,
-            strength: 10,
-            intelligence: 10,
-            agility: 10,
-        }
-    }
-    pub fn gen() -> Stats {
-        let mut rng = rand::thread_rng();
-        Stats {
-            health: 100,
-            hunger: 100,
-            strength: rng.gen_range(0..10),
-            intelligence: rng.gen_range(0..10),
-            agility: rng.gen_range(0..10),
+            personality: Personality::gen(rng),
         }
     }
 }
-#[derive(Clone, PartialEq)]
+#[derive(Clone, PartialEq, Debug)]
 pub enum Status {
     Talking,
     Fighting,
     Idle,
 }
-#[derive(Clone)]
+#[derive(Clone, PartialEq, Debug)]
 pub enum TileType {
     Grass,
     WoodenWall,
+    River,
+    Lake,
+    Ocean,
+    Tundra,
+    Taiga,
+    TemperateForest,
+    Steppe,
+    Desert,
+    Marsh,
+    Glacier,
+    WoodenFloor,
+    WoodenDoor,
+    Road,
+    Bridge,
+}
+impl TileType {
+    pub fn is_water(&self) -> bool {
+        matches!(self, TileType::River | TileType::Lake | TileType::Ocean)
+    }
+    pub fn is_passable(&self) -> bool {
+        !self.is_water() && *self != TileType::WoodenWall
+    }
+    // water before anything was built over it
+    pub fn was_water(&self) -> bool {
+        self.is_water() || *self == TileType::Bridge
+    }
+    // farmers grow food here
+    pub fn is_fertile(&self) -> bool {
+        matches!(
+            self,
+            TileType::Grass | TileType::Steppe | TileType::TemperateForest
+        )
+    }
 }
 #[derive(Clone)]
 pub enum EntityType {
     Human,
 }
-#[derive(Clone)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Coords {
     pub x: f32,
     pub y: f32,
@@ -211,6 +261,12 @@ impl Coords {
 pub struct Entity {
     pub coords: Coords,
     pub vel: (f32, f32),
+    // direction a wandering entity walks in, radians
+    pub heading: f32,
+    // where the entity is walking to, it wanders when there is none
+    pub target: Option<Coords>,
+    // waypoints after the target, taken one by one as they are reached
+    pub path: Vec<Coords>,
     pub etype: EntityType,
     pub stats: Stats,
     pub status: Status,
@@ -220,21 +276,28 @@ pub struct Entity {
     pub name: String,
     pub gender: Gender,
     pub tasks: Tasks,
+    // the task the entity is busy with, picked from `tasks`
+    pub job: Option<Job>,
 }
 impl Entity {
     pub fn new(index: usize) -> Entity {
         Entity {
             coords: Coords::new(),
             vel: (0.0, 0.0),
+            heading: 0.0,
+            target: None,
+            path: vec![],
             etype: EntityType::Human,
             stats: Stats::new(),
             status: Status::Idle,
-            alignment: Alignment::new(),
+            // rolled from the index so the entity comes out the same every run
+            alignment: Alignment::new(&mut ChaCha12Rng::seed_from_u64(index as u64)),
             inventory: Inventory::new(),
             index: index,
             name: "".to_string(),
             gender: Gender::Female,
             tasks: Tasks::new(),
+            job: None,
         }
     }
     pub fn from(
@@ -251,6 +314,9 @@ impl Entity {
             coords: coords,
             etype: etype,
             vel: (0.0, 0.0),
+            heading: 0.0,
+            target: None,
+            path: vec![],
             stats: stats,
             status: Status::Idle,
             index: index,
@@ -259,53 +325,59 @@ impl Entity {
             name: name,
             gender: gender,
             tasks: Tasks::new(),
+            job: None,
         }
     }
-    pub fn resolve(&mut self, step_increment: i32) {
-        // movement
-
-        self.coords.x += step_increment as f32 * self.vel.0;
-        self.coords.y += step_increment as f32 * self.vel.0;
-
-        if self.stats.hunger > 0 {
-            self.stats.hunger -= 1;
-        }
-        let mut rng = rand::thread_rng();
-        let roll = rng.gen_range(0..10);
-        if self.stats.hunger == 0 {
-            if self.stats.health >= 0 {
-                self.stats.health -= 2;
-            } else {
-                self.stats.health = 0;
-            }
+    // one step, movement, food and tasks are resolved for the whole chunk at
+    // once in `movement`, `needs` and `task`
+    pub fn resolve(&mut self) {
+        self.stats.tick();
+    }
+    // what meeting an entity within the vicinity distance does to this one,
+    // only fighters of another faction hit it so a fight does not spread
+    // through a settlement. nothing is changed here so all encounters of a
+    // step can be read first
+    pub fn encounter<R: Rng>(&self, other: &Entity, rng: &mut R) -> Option<Encounter> {
+        if other.status != Status::Fighting || other.alignment.faction == self.alignment.faction {
+            return None;
         }
-        // resolve tasks
-        //
-        if self.tasks.build.1 {}
-    }
-    pub fn resolve_against(&mut self, other: &mut Entity, step_increment: i32) {
-        let mut rng = rand::thread_rng();
-        let roll = rng.gen_range(0..10);
-        if dist(&self.coords, &other.coords) <= *VICINITY_DIST {
-            if other.status == Status::Fighting {
-                let dmg = other.stats.strength * roll;
-                self.stats.health -= dmg as i8;
-                if self.alignment.personality.aggression > 25 {
-                    self.status = Status::Fighting;
-                }
-            }
+        Some(Encounter {
+            damage: stats::roll_damage(
+                &other.stats,
+                &other.inventory,
+                &self.stats,
+                &self.inventory,
+                rng,
+            ),
+            provoked: self.alignment.personality.aggression > 25,
+        })
+    }
+    pub fn suffer(&mut self, encounter: &Encounter) {
+        self.stats.hurt(encounter.damage);
+        // badly hurt entities give up and stop fighting
+        if self.stats.health < self.stats.max_health / 4 {
+            self.status = Status::Idle;
+        } else if encounter.provoked {
+            self.status = Status::Fighting;
         }
     }
 }
+pub struct Encounter {
+    pub damage: u8,
+    // the entity fights back
+    pub provoked: bool,
+}
 #[derive(Clone)]
 pub struct Tile {
     pub coords: Coords,
     pub index: usize,
     pub size: Size,
-    pub height: i32,
+    pub height: f32,
     pub ttype: TileType,
     pub holds: Option<Entity>,
     pub designed: Option<TileType>,
+    pub temperature: f32,
+    pub moisture: f32,
 }
 
 impl Tile {
@@ -313,7 +385,7 @@ impl Tile {
         coords: Coords,
         index: usize,
         size: Size,
-        height: i32,
+        height: f32,
         ttype: TileType,
         holds: Option<Entity>,
     ) -> Tile {
@@ -325,6 +397,8 @@ impl Tile {
             ttype,
             holds,
             designed: None,
+            temperature: 0.0,
+            moisture: 0.0,
         }
     }
 }
@@ -334,6 +408,16 @@ pub struct Chunk {
     pub entities: Vec<Entity>,
     pub coords: Coords,
     pub index: usize,
+    pub rng: ChaCha12Rng,
+    // food and goods each faction keeps in the chunk
+    pub stores: HashMap<Faction, Inventory>,
+    // tiles built since the world last copied them into its terrain
+    pub built: Vec<usize>,
+    // long walks entities set out on since the world last sent them by road,
+    // by entity id and where to
+    pub walks: Vec<(usize, Coords)>,
+    // how the factions stand with each other, handed down by the world
+    pub relations: HashMap<(Faction, Faction), Relation>,
 }
 
 impl Chunk {
@@ -343,6 +427,11 @@ impl Chunk {
             entities,
             coords,
             index,
+            rng: ChaCha12Rng::seed_from_u64(0),
+            stores: HashMap::new(),
+            built: vec![],
+            walks: vec![],
+            relations: HashMap::new(),
         }
     }
     pub fn new() -> Chunk {
@@ -351,115 +440,173 @@ impl Chunk {
             entities: vec![],
             coords: Coords::new(),
             index: 0,
+            rng: ChaCha12Rng::seed_from_u64(0),
+            stores: HashMap::new(),
+            built: vec![],
+            walks: vec![],
+            relations: HashMap::new(),
         }
     }
-    pub fn resolve(&mut self, step_increment: i32) {
+    pub fn resolve(&mut self, step_increment: i32, config: &WorldConfig, terrain: &Terrain) {
         for i in 0..step_increment {
             for _t in &mut self.tiles {}
-            let mut entities_clone = self.entities.clone();
-            for clone in &mut entities_clone {
-                for entity in &mut self.entities {
-                    entity.resolve_against(clone, step_increment);
-                }
-            }
+            self.interact(config);
+            task::resolve(self, config);
+            movement::resolve(self, terrain, config);
+            needs::resolve(self, config);
             for entity in &mut self.entities {
-                entity.resolve(step_increment);
+                entity.resolve();
             }
             self.entities = self
                 .entities
                 .iter()
-                .filter(|e| e.stats.health > 0)
+                .filter(|e| !e.stats.is_dead())
                 .cloned()
                 .collect();
         }
     }
-    pub fn gen(&mut self, seed: u32) -> Chunk {
-        let mut rng = rand::thread_rng();
+    // every entity meets the others within the vicinity distance, found
+    // through a spatial hash. the encounters are all worked out before any
+    // of them is applied
+    pub fn interact(&mut self, config: &WorldConfig) {
+        let vicinity = config.vicinity_dist as f32;
+        let grid = SpatialHash::from(&self.entities, vicinity);
+        let mut encounters = vec![];
+        for (i, entity) in self.entities.iter().enumerate() {
+            for j in grid.near(&entity.coords, vicinity) {
+                if j == i {
+                    continue;
+                }
+                if let Some(encounter) = entity.encounter(&self.entities[j], &mut self.rng) {
+                    encounters.push((i, encounter));
+                }
+            }
+        }
+        for (i, encounter) in encounters {
+            self.entities[i].suffer(&encounter);
+        }
+    }
+    pub fn gen(&mut self, seed: u32, config: &WorldConfig, terrain: &Terrain) -> Chunk {
+        let mut rng = ChaCha12Rng::seed_from_u64(chunk_seed(
+            seed,
+            self.coords.x as i32,
+            self.coords.y as i32,
+        ));
         let mut tiles: Vec<Tile> = vec![];
         let mut entities: Vec<Entity> = vec![];
-        let fac_perlin = Perlin::new(seed);
-        let perlin = Perlin::new(seed);
-        let perlin2 = Perlin::new(seed + 1);
-        let perlin3 = Perlin::new(seed + 2);
-
-        let mut discard_entities = false;
-        let mut faction = &Faction::Empty;
-        if fac_perlin.get([self.coords.x as f64 + 0.1, self.coords.y as f64 + 0.1]) > 0.0
-            && fac_perlin.get([self.coords.x as f64 + 0.1, self.coords.y as f64 + 0.1]) < 0.1
-        {
-            faction = &Faction::Novgorod;
-        } else if fac_perlin.get([self.coords.x as f64 + 0.1, self.coords.y as f64 + 0.1]) > 0.1
-            && fac_perlin.get([self.coords.x as f64 + 0.1, self.coords.y as f64 + 0.1]) < 0.2
-        {
-            faction = &Faction::Virumaa;
-        } else if fac_perlin.get([self.coords.x as f64 + 0.1, self.coords.y as f64 + 0.1]) > 0.2
-            && fac_perlin.get([self.coords.x as f64 + 0.1, self.coords.y as f64 + 0.1]) < 0.3
-        {
-            faction = &Faction::Kalevala;
-        } else if fac_perlin.get([self.coords.x as f64 + 0.1, self.coords.y as f64 + 0.1]) > 0.3
-            && fac_perlin.get([self.coords.x as f64 + 0.1, self.coords.y as f64 + 0.1]) < 0.4
-        {
-            faction = &Faction::Tapiola;
-        } else if fac_perlin.get([self.coords.x as f64 + 0.1, self.coords.y as f64 + 0.1]) > 0.4
-            && fac_perlin.get([self.coords.x as f64 + 0.1, self.coords.y as f64 + 0.1]) < 0.5
-        {
-            faction = &Faction::Pohjola;
+        let faction = chunk_faction(seed, self.coords.x as i32, self.coords.y as i32);
+        let discard_entities = faction.is_none();
+        let faction = &faction.unwrap_or(Faction::Empty);
+        // most people live around the chunk's settlement if it has one
+        let centre = if discard_entities {
+            None
         } else {
-            discard_entities = true;
-        }
-        for c in 0..(*CHUNK_SIZE as i32 * *CHUNK_SIZE as i32) {
-            let x = c % (*CHUNK_SIZE as i32) + self.coords.x as i32 * *CHUNK_SIZE as i32;
-            let y = (c / *CHUNK_SIZE as i32) + self.coords.y as i32 * *CHUNK_SIZE as i32;
-            let a = 2.0;
-            let n1 = perlin.get([
-                (x as f64) / *NOISE_SCALE + 0.1,
-                (y as f64) / *NOISE_SCALE + 0.1,
-            ]) * a;
-
-            let n2 = perlin2.get([
-                (x as f64) / *NOISE_SCALE * 2.0 + 0.1,
-                (y as f64) / *NOISE_SCALE * 2.0 + 0.1,
-            ]) * a
-                / 8.0;
-
-            let n3 = perlin3.get([
-                (x as f64) / (*NOISE_SCALE * 8.0) + 0.1,
-                (y as f64) / (*NOISE_SCALE * 8.0) + 0.1,
-            ]) * a
-                * -8.0;
-            let height: i32 = (n1 + n2 + n3 + rng.gen_range(-1.0..1.0)) as i32;
-            let gender = GENDERS.choose(&mut rand::thread_rng()).unwrap();
-            if height > 0 && !discard_entities && rng.gen_range(0..32) == 1 {
-                entities.push(Entity::from(
-                    c as usize,
+            settlement::site(
+                terrain,
+                config,
+                (self.coords.x as i64, self.coords.y as i64),
+            )
+            .map(|site| site.centre)
+        };
+        let chunk_size = config.chunk_size as i32;
+        for c in 0..(chunk_size * chunk_size) {
+            let x = c % chunk_size + self.coords.x as i32 * chunk_size;
+            let y = (c / chunk_size) + self.coords.y as i32 * chunk_size;
+            let (tx, ty) = (x as usize, y as usize);
+            let height = terrain.height(tx, ty);
+            let ttype = terrain.ttype(tx, ty);
+            let odds = match centre {
+                Some(centre)
+                    if euclid(
+                        &Coords::from((x as f32, y as f32)),
+                        &Coords::from((centre.0 as f32, centre.1 as f32)),
+                    ) < settlement::RADIUS =>
+                {
+                    settlement::CROWD
+                }
+                _ => settlement::SPARSE,
+            };
+            if height > config.sea_level
+                && ttype.is_passable()
+                && !discard_entities
+                && rng.gen_range(0..odds) == 1
+            {
+                let gender = GENDERS.choose(&mut rng).unwrap();
+                let stats = Stats::gen(&mut rng);
+                let alignment = Alignment::from(faction.clone(), &mut rng);
+                let name = gen_human_name(faction.clone(), gender, &mut rng);
+                let mut inventory = Inventory::new();
+                if rng.gen_range(0..10) == 0 {
+                    inventory.add(Item::Sword, 1);
+                }
+                if rng.gen_range(0..10) == 0 {
+                    inventory.add(Item::Armour, 1);
+                }
+                inventory.add(Item::Coin, rng.gen_range(1..10));
+                // ids are unique in the world, spawned entities count up after these
+                let mut entity = Entity::from(
+                    self.index * (chunk_size * chunk_size) as usize + c as usize,
                     Coords::from((x as f32, y as f32)),
                     (0.0, 0.0),
                     EntityType::Human,
-                    Stats::gen(),
-                    Alignment::from(faction.clone()),
-                    gen_human_name(faction.clone(), gender),
+                    stats,
+                    alignment,
+                    name,
                     gender.clone(),
-                ))
+                );
+                entity.inventory = inventory;
+                match rng.gen_range(0..5) {
+                    0 | 1 => entity.tasks.farm.0 = 1,
+                    2 => entity.tasks.industry.0 = 1,
+                    _ => {}
+                }
+                // the most aggressive go looking for fights, the fairly
+                // aggressive only fight in wars
+                if entity.alignment.personality.aggression > 90 {
+                    entity.tasks.fight.0 = 2;
+                } else if entity.alignment.personality.aggression > 60 {
+                    entity.tasks.fight.0 = 1;
+                }
+                entities.push(entity);
             }
             tiles.push(Tile::from(
                 Coords::from((x as f32, y as f32)),
                 c as usize,
-                Size::from((*TILE_SIZE as i32, *TILE_SIZE as i32)),
+                Size::from((config.tile_size as i32, config.tile_size as i32)),
                 height,
-                TileType::Grass,
+                ttype,
                 None,
             ));
+            let tile = tiles.last_mut().unwrap();
+            tile.temperature = terrain.temperature(tx, ty);
+            tile.moisture = terrain.moisture(tx, ty);
         }
         Chunk {
             tiles: tiles,
             entities: entities,
-            coords: self.coords.clone(),
+            coords: self.coords,
             index: self.index,
+            rng,
+            stores: HashMap::new(),
+            built: vec![],
+            walks: vec![],
+            relations: HashMap::new(),
         }
     }
     pub fn fetch_tile(&self, index: usize) -> &Tile {
         &self.tiles[index]
     }
+    // the chunk's tile under a world position, None outside the chunk
+    pub fn tile_at(&self, coords: &Coords, chunk_size: u32) -> Option<&Tile> {
+        let tile = world_to_tile(coords);
+        if self.tiles.is_empty()
+            || tile_to_chunk(tile, chunk_size) != (self.coords.x as i64, self.coords.y as i64)
+        {
+            return None;
+        }
+        let (x, y) = tile_in_chunk(tile, chunk_size);
+        self.tiles.get(y * chunk_size as usize + x)
+    }
     pub fn inquire_news(&self) -> News {
         let mut news = vec![];
         let mut coin_count = 0;
@@ -486,38 +633,402 @@ impl News {
     }
 }
 pub struct World {
-    pub chunks: Vec<Chunk>,
+    pub terrain: Terrain,
+    pub store: ChunkStore,
+    pub paths: PathCache,
+    pub blueprints: Blueprints,
+    pub settlements: Vec<Settlement>,
+    pub roads: Roads,
+    pub factions: Vec<FactionState>,
+    pub seed: u32,
+    pub step: u64,
+    pub next_id: usize,
+    pub config: WorldConfig,
 }
 impl World {
-    pub fn from(chunks: Vec<Chunk>) -> World {
-        World { chunks }
+    pub fn from(terrain: Terrain, seed: u32, config: WorldConfig) -> Result<World, String> {
+        Ok(World {
+            terrain,
+            store: ChunkStore::from(&config, seed)?,
+            paths: PathCache::from(&config),
+            blueprints: Blueprints::new(),
+            settlements: vec![],
+            roads: Roads::new(),
+            factions: vec![],
+            seed,
+            step: 0,
+            next_id: (config.world_size * config.world_size) as usize
+                * (config.chunk_size * config.chunk_size) as usize,
+            config,
+        })
+    }
+    pub fn chunk_count(&self) -> usize {
+        (self.config.world_size * self.config.world_size) as usize
     }
     pub fn fetch_chunk_mut(&mut self, index: usize) -> &mut Chunk {
-        &mut self.chunks[index]
+        self.store
+            .get_mut(index, self.seed, &self.config, &self.terrain)
     }
-    pub fn fetch_chunk(&self, index: usize) -> &Chunk {
-        &self.chunks[index]
+    pub fn fetch_chunk(&mut self, index: usize) -> &Chunk {
+        self.store
+            .get(index, self.seed, &self.config, &self.terrain)
     }
-    pub fn resolve(&mut self, step_increment: i32) {
-        self.chunks
-            .par_iter_mut()
-            .for_each(|c| c.resolve(step_increment));
-    }
-    pub fn resolve_between(&mut self, step_increment: i32) {}
-}
-pub fn worldgen(seed: u32) -> World {
-    let mut chunks: Vec<Chunk> = vec![];
-    for c in 0..((*WORLD_SIZE * *WORLD_SIZE) as i32) {
-        let x = (c % *WORLD_SIZE as i32) as f32;
-        let y = (c / *WORLD_SIZE as i32) as f32;
-        chunks.push(Chunk::from(
-            vec![],
-            vec![],
-            Coords::from((x, y)),
-            c as usize,
+    pub fn population(&self) -> usize {
+        self.store.population()
+    }
+    // looks through simulated chunks first, an untouched entity is still in
+    // the chunk it was generated in
+    pub fn find_entity(&self, id: usize) -> Option<(usize, Entity)> {
+        if let Some(found) = self.store.find_entity(id) {
+            return Some(found);
+        }
+        let tiles = (self.config.chunk_size * self.config.chunk_size) as usize;
+        let chunk = id / tiles;
+        if chunk >= self.chunk_count() || self.store.is_modified(chunk) {
+            return None;
+        }
+        self.store
+            .with_chunk(chunk, self.seed, &self.config, &self.terrain, |c| {
+                c.entities.iter().find(|e| e.index == id).cloned()
+            })
+            .map(|e| (chunk, e))
+    }
+    // a random tile a human can be put on, drawn from the seed and the next id
+    // so a replayed run spawns in the same places
+    pub fn spawn_tile(&self) -> Option<(usize, usize)> {
+        let side = self.terrain.side();
+        let mut rng = ChaCha12Rng::seed_from_u64(pass_seed(self.seed, 4) ^ self.next_id as u64);
+        (0..1000)
+            .map(|_| (rng.gen_range(0..side), rng.gen_range(0..side)))
+            .find(|(x, y)| {
+                let ttype = self.terrain.ttype(*x, *y);
+                !ttype.is_water() && ttype.is_passable()
+            })
+    }
+    // puts a new human on a dry tile and returns its id
+    pub fn spawn(&mut self, x: usize, y: usize) -> Result<usize, String> {
+        self.spawn_in(x, y, Faction::Empty)
+    }
+    pub fn spawn_in(&mut self, x: usize, y: usize, faction: Faction) -> Result<usize, String> {
+        let side = self.terrain.side();
+        if x >= side || y >= side {
+            return Err(format!("{} {} is outside the world", x, y));
+        }
+        let ttype = self.terrain.ttype(x, y);
+        if ttype.is_water() {
+            return Err(format!("{} {} is water", x, y));
+        }
+        if !ttype.is_passable() {
+            return Err(format!("{} {} is a wall", x, y));
+        }
+        let chunk_size = self.config.chunk_size as usize;
+        let chunk_index = y / chunk_size * self.config.world_size as usize + x / chunk_size;
+        let id = self.next_id;
+        self.next_id += 1;
+        let chunk = self.fetch_chunk_mut(chunk_index);
+        let gender = GENDERS.choose(&mut chunk.rng).unwrap().clone();
+        let stats = Stats::gen(&mut chunk.rng);
+        let alignment = Alignment::from(faction.clone(), &mut chunk.rng);
+        let name = gen_human_name(faction, &gender, &mut chunk.rng);
+        chunk.entities.push(Entity::from(
+            id,
+            Coords::from((x as f32, y as f32)),
+            (0.0, 0.0),
+            EntityType::Human,
+            stats,
+            alignment,
+            name,
+            gender,
         ));
+        Ok(id)
+    }
+    pub fn find_path(&mut self, from: (usize, usize), to: (usize, usize)) -> Option<Vec<Coords>> {
+        self.paths.find(&self.terrain, from, to)
+    }
+    // sends an entity walking to a tile, by road when it is far and a road
+    // leads there, otherwise along the cheapest path
+    pub fn walk(&mut self, id: usize, x: usize, y: usize) -> Result<usize, String> {
+        let (chunk, entity) = self.find_entity(id).ok_or(format!("no entity {}", id))?;
+        let from = (entity.coords.x as usize, entity.coords.y as usize);
+        let mut path = match self.road_path(from, (x, y)) {
+            Some(path) => path,
+            None => self
+                .find_path(from, (x, y))
+                .ok_or(format!("no path to {} {}", x, y))?,
+        };
+        let length = path.len();
+        let entity = self
+            .fetch_chunk_mut(chunk)
+            .entities
+            .iter_mut()
+            .find(|e| e.index == id)
+            .unwrap();
+        entity.target = if path.is_empty() {
+            None
+        } else {
+            Some(path.remove(0))
+        };
+        entity.path = path;
+        entity.job = None;
+        Ok(length)
+    }
+    // only chunks that are in memory are simulated
+    pub fn resolve(&mut self, step_increment: i32) {
+        let relations = self.relations();
+        self.store.apply(|chunk| {
+            chunk.relations = relations.clone();
+            false
+        });
+        self.store
+            .resolve(step_increment, &self.config, &self.terrain);
+        self.settle_built();
+        self.route_walks();
+        let step = self.step + step_increment as u64;
+        if step / settlement::PERIOD != self.step / settlement::PERIOD {
+            self.resolve_settlements();
+            self.resolve_factions();
+        }
+        self.step = step;
+    }
+    // chunk an entity stands in by its coordinates
+    pub fn home(&self, entity: &Entity) -> usize {
+        home(entity, &self.config)
+    }
+    // moves entities that walked out of their chunk into the chunk they are in
+    // now and lets entities on both sides of a chunk border meet. everything
+    // is read from the loaded chunks first and written afterwards, so the
+    // chunks can be handled in parallel
+    pub fn resolve_between(&mut self) {
+        let config = &self.config;
+        let chunk_size = config.chunk_size as f32;
+        let vicinity = config.vicinity_dist as f32;
+        let near_border = |chunk: &Coords, e: &Entity| {
+            let (x, y) = (
+                e.coords.x - chunk.x * chunk_size,
+                e.coords.y - chunk.y * chunk_size,
+            );
+            x < vicinity || y < vicinity || x >= chunk_size - vicinity || y >= chunk_size - vicinity
+        };
+        let moving: Vec<(usize, Entity)> = self.store.collect(|chunk| {
+            chunk
+                .entities
+                .iter()
+                .filter(|e| home(e, config) != chunk.index)
+                .map(|e| (home(e, config), e.clone()))
+                .collect()
+        });
+        let border: Vec<(usize, Entity)> = self.store.collect(|chunk| {
+            chunk
+                .entities
+                .iter()
+                .filter(|e| near_border(&chunk.coords, e))
+                .map(|e| (chunk.index, e.clone()))
+                .collect()
+        });
+        self.store.apply(|chunk| {
+            let before = chunk.entities.len();
+            chunk.entities.retain(|e| home(e, config) == chunk.index);
+            let mut met = false;
+            for entity in chunk.entities.iter_mut() {
+                if !near_border(&chunk.coords, entity) {
+                    continue;
+                }
+                let at = entity.coords;
+                let close = |other: &Entity| euclid(&other.coords, &at) <= vicinity;
+                for (_, other) in border
+                    .iter()
+                    .filter(|(index, other)| *index != chunk.index && close(other))
+                {
+                    if let Some(encounter) = entity.encounter(other, &mut chunk.rng) {
+                        entity.suffer(&encounter);
+                    }
+                    met = true;
+                }
+            }
+            met || chunk.entities.len() != before
+        });
+        for (index, mut entity) in moving {
+            // jobs point at tiles by their index in the chunk left behind
+            task::drop_job(&mut entity);
+            self.fetch_chunk_mut(index).entities.push(entity);
+        }
+    }
+    // stable fingerprint of the generated contents, same seed gives same digest
+    // on every platform and toolchain
+    pub fn digest(&self) -> u64 {
+        let mut hasher = Fnv::new();
+        // block by block, so a large world is not generated over and over
+        for b in 0..self.terrain.blocks() {
+            self.terrain.with_block(b, |layers| {
+                for height in &layers.heights.data {
+                    hasher.write(&height.to_bits().to_le_bytes());
+                }
+            });
+        }
+        for index in 0..self.chunk_count() {
+            hasher.write(&(index as u64).to_le_bytes());
+            self.store
+                .with_chunk(index, self.seed, &self.config, &self.terrain, |chunk| {
+                    for entity in &chunk.entities {
+                        hasher.write(&entity.coords.x.to_bits().to_le_bytes());
+                        hasher.write(&entity.coords.y.to_bits().to_le_bytes());
+                        hasher.write(entity.name.as_bytes());
+                        hasher.write(&[
+                            entity.stats.health,
+                            entity.stats.strength,
+                            entity.stats.intelligence,
+                            entity.stats.agility,
+                            entity.alignment.personality.aggression,
+                        ]);
+                    }
+                });
+        }
+        hasher.0
+    }
+}
+// 64 bit FNV-1a, fixed unlike the std hashers
+struct Fnv(u64);
+impl Fnv {
+    fn new() -> Fnv {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+fn home(entity: &Entity, config: &WorldConfig) -> usize {
+    let last = config.world_size as i64 - 1;
+    let (cx, cy) = world_to_chunk(&entity.coords, config.chunk_size);
+    chunk_index((cx.clamp(0, last), cy.clamp(0, last)), config.world_size).unwrap()
+}
+// only the terrain layers are built up front, chunks are generated from them
+// when they are first fetched
+pub fn worldgen(seed: u32, config: &WorldConfig) -> Result<World, String> {
+    let mut terrain = Terrain::gen(seed, config);
+    let blueprints = Blueprints::new();
+    let settlements = settlement::found(seed, &mut terrain, config, &blueprints);
+    let roads = roads::lay(&mut terrain, &settlements);
+    let factions = faction::found(seed, config, &settlements);
+    let mut world = World::from(terrain, seed, config.clone())?;
+    world.settlements = settlements;
+    world.roads = roads;
+    world.factions = factions;
+    Ok(world)
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn small() -> WorldConfig {
+        let mut config = WorldConfig::new();
+        config.world_size = 2;
+        config.chunk_size = 32;
+        config.erosion_droplets = 1000;
+        config
+    }
+    #[test]
+    fn same_seed_same_digest() {
+        let digest = worldgen(7, &small()).unwrap().digest();
+        assert_eq!(digest, worldgen(7, &small()).unwrap().digest());
+        assert_ne!(digest, worldgen(8, &small()).unwrap().digest());
+        // changes whenever generation does, update it when that is intended
+        assert_eq!(digest, 0xcc66_25f1_d767_873a);
+        let mut fnv = Fnv::new();
+        fnv.write(b"a");
+        assert_eq!(fnv.0, 0xaf63_dc4c_8601_ec8c);
+    }
+    #[test]
+    fn population_stays_in_band() {
+        let mut config = small();
+        config.chunk_size = 64;
+        // seeds that starved or fought themselves down to a fraction of
+        // their people within a thousand steps
+        for seed in [3, 4, 8, 12] {
+            let mut world = worldgen(seed, &config).unwrap();
+            for i in 0..world.chunk_count() {
+                world.fetch_chunk(i);
+            }
+            let start = world.population();
+            for step in 1..=1000 {
+                world.resolve(1);
+                world.resolve_between();
+                if step % 100 == 0 {
+                    let population = world.population();
+                    assert!(
+                        population >= start / 2 && population <= start * 2,
+                        "seed {} went from {} to {} people by step {}",
+                        seed,
+                        start,
+                        population,
+                        step
+                    );
+                }
+            }
+        }
+    }
+    #[test]
+    fn crossing_entities_move_once_and_meet_across_borders() {
+        let mut world = worldgen(7, &small()).unwrap();
+        let human = |index: usize, faction: Faction, at: (f32, f32), status: Status| {
+            let mut entity = Entity::new(index);
+            entity.coords = Coords::from(at);
+            entity.stats = Stats::from(10, 5, 5);
+            entity.alignment.faction = faction;
+            entity.alignment.personality.aggression = 50;
+            entity.status = status;
+            entity
+        };
+        world.fetch_chunk_mut(0).entities = vec![
+            // stands at the border, next to a fighter across it
+            human(1, Faction::Kalevala, (30.5, 5.0), Status::Idle),
+            // far from the border
+            human(2, Faction::Kalevala, (10.0, 10.0), Status::Idle),
+            // walked over into chunk 1
+            human(3, Faction::Kalevala, (40.0, 10.0), Status::Idle),
+        ];
+        world.fetch_chunk_mut(1).entities =
+            vec![human(4, Faction::Pohjola, (32.5, 5.0), Status::Fighting)];
+        world.resolve_between();
+        let indices = |world: &mut World, chunk: usize| {
+            let mut indices: Vec<usize> = world
+                .fetch_chunk(chunk)
+                .entities
+                .iter()
+                .map(|e| e.index)
+                .collect();
+            indices.sort();
+            indices
+        };
+        assert_eq!(indices(&mut world, 0), vec![1, 2]);
+        assert_eq!(indices(&mut world, 1), vec![3, 4]);
+        let chunk = world.fetch_chunk(0);
+        assert_eq!(chunk.entities[0].status, Status::Fighting);
+        assert_eq!(chunk.entities[1].status, Status::Idle);
+        // the one across only fights back once it is attacked itself
+        let chunk = world.fetch_chunk(1);
+        let other = chunk.entities.iter().find(|e| e.index == 4).unwrap();
+        assert_eq!(other.stats.health, other.stats.max_health);
+    }
+    #[test]
+    fn spawn_tiles_are_dry_and_follow_the_seed() {
+        let mut world = worldgen(7, &small()).unwrap();
+        let other = worldgen(7, &small()).unwrap();
+        let (x, y) = world.spawn_tile().unwrap();
+        assert_eq!(other.spawn_tile(), Some((x, y)));
+        let ttype = world.terrain.ttype(x, y);
+        assert!(!ttype.is_water() && ttype.is_passable());
+        // every spawn draws anew
+        let id = world.spawn(x, y).unwrap();
+        assert_eq!(world.find_entity(id).unwrap().1.coords.x, x as f32);
+        let tiles: Vec<_> = (0..5)
+            .map(|_| {
+                let (x, y) = world.spawn_tile().unwrap();
+                world.spawn(x, y).unwrap();
+                (x, y)
+            })
+            .collect();
+        assert!(tiles.iter().any(|t| *t != (x, y)));
     }
-    chunks.par_iter_mut().for_each(|c| *c = c.gen(seed));
-    let world = World::from(chunks);
-    world
 }